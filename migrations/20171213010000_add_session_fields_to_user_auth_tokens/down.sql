@@ -0,0 +1,4 @@
+ALTER TABLE user_auth_tokens
+DROP COLUMN IF EXISTS revoked_at;
+ALTER TABLE user_auth_tokens
+DROP COLUMN IF EXISTS last_used_at;
@@ -0,0 +1,4 @@
+ALTER TABLE user_auth_tokens
+ADD COLUMN last_used_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc');
+ALTER TABLE user_auth_tokens
+ADD COLUMN revoked_at TIMESTAMP;
@@ -0,0 +1,22 @@
+CREATE TABLE login_requests (
+  id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  updated_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  email TEXT NOT NULL,
+  ip TEXT
+);
+CREATE TRIGGER update_login_requests_updated_at BEFORE UPDATE ON login_requests FOR EACH ROW EXECUTE PROCEDURE update_updated_at();
+CREATE INDEX login_requests_email_created_at ON login_requests (email, created_at);
+CREATE INDEX login_requests_ip_created_at ON login_requests (ip, created_at);
+
+CREATE TABLE login_attempts (
+  id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  updated_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  email TEXT NOT NULL,
+  ip TEXT,
+  is_success BOOL NOT NULL
+);
+CREATE TRIGGER update_login_attempts_updated_at BEFORE UPDATE ON login_attempts FOR EACH ROW EXECUTE PROCEDURE update_updated_at();
+CREATE INDEX login_attempts_email_created_at ON login_attempts (email, created_at);
+CREATE INDEX login_attempts_ip_created_at ON login_attempts (ip, created_at);
@@ -0,0 +1,12 @@
+CREATE TABLE user_api_keys (
+  id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  updated_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  user_id UUID NOT NULL REFERENCES users (id),
+  name TEXT NOT NULL,
+  key UUID NOT NULL UNIQUE DEFAULT uuid_generate_v4(),
+  scopes TEXT[] NOT NULL,
+  last_used_at TIMESTAMP,
+  revoked_at TIMESTAMP
+);
+CREATE TRIGGER update_user_api_keys_updated_at BEFORE UPDATE ON user_api_keys FOR EACH ROW EXECUTE PROCEDURE update_updated_at();
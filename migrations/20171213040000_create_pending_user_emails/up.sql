@@ -0,0 +1,10 @@
+CREATE TABLE pending_user_emails (
+  id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  updated_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  user_id UUID NOT NULL REFERENCES users (id),
+  email TEXT NOT NULL,
+  confirmation TEXT NOT NULL,
+  UNIQUE (user_id, email)
+);
+CREATE TRIGGER update_pending_user_emails_updated_at BEFORE UPDATE ON pending_user_emails FOR EACH ROW EXECUTE PROCEDURE update_updated_at();
@@ -0,0 +1,6 @@
+ALTER TABLE game_players
+DROP COLUMN IF EXISTS turn_notified_at;
+ALTER TABLE users
+DROP COLUMN IF EXISTS turn_digest_at;
+ALTER TABLE users
+DROP COLUMN IF EXISTS turn_notification;
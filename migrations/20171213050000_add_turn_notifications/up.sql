@@ -0,0 +1,6 @@
+ALTER TABLE users
+ADD COLUMN turn_notification TEXT NOT NULL DEFAULT 'off';
+ALTER TABLE users
+ADD COLUMN turn_digest_at TIMESTAMP;
+ALTER TABLE game_players
+ADD COLUMN turn_notified_at TIMESTAMP;
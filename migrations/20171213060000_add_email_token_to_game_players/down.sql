@@ -0,0 +1,2 @@
+ALTER TABLE game_players
+DROP COLUMN IF EXISTS email_token;
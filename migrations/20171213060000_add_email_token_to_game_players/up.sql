@@ -0,0 +1,2 @@
+ALTER TABLE game_players
+ADD COLUMN email_token UUID NOT NULL UNIQUE DEFAULT uuid_generate_v4();
@@ -0,0 +1,17 @@
+CREATE TABLE outbound_emails (
+  id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  updated_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  message_id TEXT NOT NULL,
+  from_address TEXT NOT NULL,
+  to_addresses TEXT[] NOT NULL,
+  message TEXT NOT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  next_attempt_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  last_error TEXT,
+  sent_at TIMESTAMP,
+  failed_at TIMESTAMP
+);
+CREATE TRIGGER update_outbound_emails_updated_at BEFORE UPDATE ON outbound_emails FOR EACH ROW EXECUTE PROCEDURE update_updated_at();
+CREATE INDEX outbound_emails_pending_idx ON outbound_emails (next_attempt_at)
+WHERE sent_at IS NULL AND failed_at IS NULL;
@@ -0,0 +1 @@
+DROP INDEX IF EXISTS friends_user_pair_idx;
@@ -0,0 +1,4 @@
+CREATE UNIQUE INDEX friends_user_pair_idx ON friends (
+  LEAST(source_user_id, target_user_id),
+  GREATEST(source_user_id, target_user_id)
+);
@@ -0,0 +1,10 @@
+CREATE TABLE user_blocks (
+  id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  updated_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  user_id UUID NOT NULL REFERENCES users (id),
+  blocked_user_id UUID NOT NULL REFERENCES users (id) CHECK (blocked_user_id != user_id),
+  UNIQUE (user_id, blocked_user_id)
+);
+CREATE TRIGGER update_user_blocks_updated_at BEFORE UPDATE ON user_blocks FOR EACH ROW EXECUTE PROCEDURE update_updated_at();
+CREATE INDEX user_blocks_blocked_user_id_idx ON user_blocks (blocked_user_id);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS open_game_players;
+DROP TABLE IF EXISTS open_games;
@@ -0,0 +1,22 @@
+CREATE TABLE open_games (
+  id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  updated_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  game_version_id UUID NOT NULL REFERENCES game_versions (id),
+  creator_id UUID NOT NULL REFERENCES users (id),
+  player_count INTEGER NOT NULL CHECK (player_count > 1),
+  game_id UUID REFERENCES games (id),
+  cancelled_at TIMESTAMP
+);
+CREATE TRIGGER update_open_games_updated_at BEFORE UPDATE ON open_games FOR EACH ROW EXECUTE PROCEDURE update_updated_at();
+CREATE INDEX open_games_open_idx ON open_games (created_at)
+WHERE game_id IS NULL AND cancelled_at IS NULL;
+CREATE TABLE open_game_players (
+  id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  updated_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  open_game_id UUID NOT NULL REFERENCES open_games (id) ON DELETE CASCADE,
+  user_id UUID NOT NULL REFERENCES users (id),
+  UNIQUE (open_game_id, user_id)
+);
+CREATE TRIGGER update_open_game_players_updated_at BEFORE UPDATE ON open_game_players FOR EACH ROW EXECUTE PROCEDURE update_updated_at();
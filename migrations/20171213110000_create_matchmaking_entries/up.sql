@@ -0,0 +1,13 @@
+CREATE TABLE matchmaking_entries (
+  id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  updated_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  user_id UUID NOT NULL REFERENCES users (id),
+  game_type_id UUID NOT NULL REFERENCES game_types (id),
+  player_count INTEGER NOT NULL CHECK (player_count > 1),
+  rating INTEGER NOT NULL,
+  game_id UUID REFERENCES games (id)
+);
+CREATE TRIGGER update_matchmaking_entries_updated_at BEFORE UPDATE ON matchmaking_entries FOR EACH ROW EXECUTE PROCEDURE update_updated_at();
+CREATE UNIQUE INDEX matchmaking_entries_queued_idx ON matchmaking_entries (user_id, game_type_id)
+WHERE game_id IS NULL;
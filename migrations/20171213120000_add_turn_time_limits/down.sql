@@ -0,0 +1,21 @@
+DROP TRIGGER IF EXISTS spend_time_bank ON game_players;
+DROP FUNCTION IF EXISTS spend_time_bank();
+CREATE OR REPLACE FUNCTION update_is_turn_at()
+RETURNS TRIGGER AS $$
+BEGIN
+    NEW.is_turn_at = now() AT TIME ZONE 'utc';
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+ALTER TABLE game_players
+DROP COLUMN IF EXISTS turn_warned_at;
+ALTER TABLE game_players
+DROP COLUMN IF EXISTS time_bank_secs;
+ALTER TABLE games
+DROP COLUMN IF EXISTS timeout_penalty;
+ALTER TABLE games
+DROP COLUMN IF EXISTS grace_hours;
+ALTER TABLE games
+DROP COLUMN IF EXISTS time_bank_hours;
+ALTER TABLE games
+DROP COLUMN IF EXISTS turn_days;
@@ -0,0 +1,45 @@
+ALTER TABLE games
+ADD COLUMN turn_days INTEGER CHECK (turn_days > 0);
+ALTER TABLE games
+ADD COLUMN time_bank_hours INTEGER NOT NULL DEFAULT 0 CHECK (time_bank_hours >= 0);
+ALTER TABLE games
+ADD COLUMN grace_hours INTEGER NOT NULL DEFAULT 0 CHECK (grace_hours >= 0);
+ALTER TABLE games
+ADD COLUMN timeout_penalty TEXT NOT NULL DEFAULT 'concede';
+ALTER TABLE game_players
+ADD COLUMN time_bank_secs INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE game_players
+ADD COLUMN turn_warned_at TIMESTAMP;
+
+CREATE OR REPLACE FUNCTION update_is_turn_at()
+RETURNS TRIGGER AS $$
+BEGIN
+    NEW.is_turn_at = now() AT TIME ZONE 'utc';
+    NEW.turn_warned_at = NULL;
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+-- Time taken beyond the per turn allowance comes out of the player's time bank.
+CREATE OR REPLACE FUNCTION spend_time_bank()
+RETURNS TRIGGER AS $$
+DECLARE
+    allowance INTERVAL;
+BEGIN
+    SELECT make_interval(days => turn_days) INTO allowance
+    FROM games
+    WHERE id = NEW.game_id AND turn_days IS NOT NULL;
+    IF allowance IS NOT NULL THEN
+        NEW.time_bank_secs = GREATEST(0, NEW.time_bank_secs - GREATEST(0, EXTRACT(EPOCH FROM
+            (now() AT TIME ZONE 'utc') - OLD.is_turn_at - allowance))::INTEGER);
+    END IF;
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+CREATE TRIGGER spend_time_bank
+BEFORE UPDATE
+ON game_players
+FOR EACH ROW
+WHEN (OLD.is_turn = TRUE AND NEW.is_turn = FALSE)
+EXECUTE PROCEDURE spend_time_bank();
@@ -0,0 +1,16 @@
+CREATE OR REPLACE FUNCTION spend_time_bank()
+RETURNS TRIGGER AS $$
+DECLARE
+    allowance INTERVAL;
+BEGIN
+    SELECT make_interval(days => turn_days) INTO allowance
+    FROM games
+    WHERE id = NEW.game_id AND turn_days IS NOT NULL;
+    IF allowance IS NOT NULL THEN
+        NEW.time_bank_secs = GREATEST(0, NEW.time_bank_secs - GREATEST(0, EXTRACT(EPOCH FROM
+            (now() AT TIME ZONE 'utc') - OLD.is_turn_at - allowance))::INTEGER);
+    END IF;
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+DROP TABLE IF EXISTS user_vacations;
@@ -0,0 +1,34 @@
+CREATE TABLE user_vacations (
+  id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  updated_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  user_id UUID NOT NULL REFERENCES users (id),
+  starts_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  ends_at TIMESTAMP NOT NULL,
+  CHECK (ends_at >= starts_at)
+);
+CREATE TRIGGER update_user_vacations_updated_at BEFORE UPDATE ON user_vacations FOR EACH ROW EXECUTE PROCEDURE update_updated_at();
+CREATE INDEX user_vacations_user_id_idx ON user_vacations (user_id, ends_at);
+
+-- Time spent on vacation during a turn doesn't come out of the player's time bank.
+CREATE OR REPLACE FUNCTION spend_time_bank()
+RETURNS TRIGGER AS $$
+DECLARE
+    allowance INTERVAL;
+    paused INTERVAL;
+    now_utc TIMESTAMP := now() AT TIME ZONE 'utc';
+BEGIN
+    SELECT make_interval(days => turn_days) INTO allowance
+    FROM games
+    WHERE id = NEW.game_id AND turn_days IS NOT NULL;
+    IF allowance IS NOT NULL THEN
+        SELECT COALESCE(SUM(LEAST(ends_at, now_utc) - GREATEST(starts_at, OLD.is_turn_at)),
+            INTERVAL '0') INTO paused
+        FROM user_vacations
+        WHERE user_id = NEW.user_id AND starts_at < now_utc AND ends_at > OLD.is_turn_at;
+        NEW.time_bank_secs = GREATEST(0, NEW.time_bank_secs - GREATEST(0, EXTRACT(EPOCH FROM
+            now_utc - OLD.is_turn_at - allowance - paused))::INTEGER);
+    END IF;
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
@@ -0,0 +1,2 @@
+ALTER TABLE game_players
+ADD COLUMN resigned_at TIMESTAMP;
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS game_proposal_votes;
+DROP TABLE IF EXISTS game_proposals;
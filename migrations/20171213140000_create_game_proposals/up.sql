@@ -0,0 +1,25 @@
+CREATE TABLE game_proposals (
+  id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  updated_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  game_id UUID NOT NULL REFERENCES games (id),
+  game_player_id UUID NOT NULL REFERENCES game_players (id),
+  kind TEXT NOT NULL,
+  expires_at TIMESTAMP NOT NULL,
+  outcome TEXT,
+  settled_at TIMESTAMP
+);
+CREATE TRIGGER update_game_proposals_updated_at BEFORE UPDATE ON game_proposals FOR EACH ROW EXECUTE PROCEDURE update_updated_at();
+CREATE UNIQUE INDEX game_proposals_open_idx ON game_proposals (game_id)
+WHERE settled_at IS NULL;
+
+CREATE TABLE game_proposal_votes (
+  id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  updated_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  game_proposal_id UUID NOT NULL REFERENCES game_proposals (id),
+  game_player_id UUID NOT NULL REFERENCES game_players (id),
+  is_accepted BOOLEAN NOT NULL,
+  UNIQUE (game_proposal_id, game_player_id)
+);
+CREATE TRIGGER update_game_proposal_votes_updated_at BEFORE UPDATE ON game_proposal_votes FOR EACH ROW EXECUTE PROCEDURE update_updated_at();
@@ -0,0 +1,14 @@
+CREATE TABLE game_history_entries (
+  id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+  created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  updated_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  game_id UUID NOT NULL REFERENCES games (id),
+  sequence INTEGER NOT NULL CHECK (sequence >= 0),
+  game_player_id UUID REFERENCES game_players (id),
+  action TEXT NOT NULL,
+  command TEXT,
+  game_state TEXT NOT NULL,
+  whose_turn INTEGER[] NOT NULL,
+  UNIQUE (game_id, sequence)
+);
+CREATE TRIGGER update_game_history_entries_updated_at BEFORE UPDATE ON game_history_entries FOR EACH ROW EXECUTE PROCEDURE update_updated_at();
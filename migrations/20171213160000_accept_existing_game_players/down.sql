@@ -0,0 +1,2 @@
+-- Players who accepted can't be told apart from those accepted here, so there's nothing to
+-- undo.
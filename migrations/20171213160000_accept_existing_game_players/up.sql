@@ -0,0 +1,4 @@
+-- Players in games created before accepting was required never had the chance to accept.
+UPDATE game_players
+SET has_accepted = TRUE
+WHERE has_accepted = FALSE;
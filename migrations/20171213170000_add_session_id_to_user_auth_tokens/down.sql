@@ -0,0 +1,2 @@
+ALTER TABLE user_auth_tokens
+DROP COLUMN IF EXISTS session_id;
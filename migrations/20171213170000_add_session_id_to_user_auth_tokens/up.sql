@@ -0,0 +1,2 @@
+ALTER TABLE user_auth_tokens
+ADD COLUMN session_id UUID NOT NULL UNIQUE DEFAULT uuid_generate_v4();
@@ -0,0 +1 @@
+-- Backfilled entries can't be told apart from recorded ones, so there's nothing to undo.
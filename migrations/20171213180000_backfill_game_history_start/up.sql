@@ -0,0 +1,20 @@
+-- Games created before history was recorded start their history from their current state.
+INSERT INTO game_history_entries (game_id, sequence, action, game_state, whose_turn)
+SELECT
+  games.id,
+  0,
+  'start',
+  games.game_state,
+  ARRAY(
+    SELECT game_players.position
+    FROM game_players
+    WHERE game_players.game_id = games.id
+    AND game_players.is_turn
+    ORDER BY game_players.position
+  )
+FROM games
+WHERE NOT EXISTS (
+  SELECT 1
+  FROM game_history_entries
+  WHERE game_history_entries.game_id = games.id
+);
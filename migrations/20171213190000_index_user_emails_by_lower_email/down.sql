@@ -0,0 +1 @@
+DROP INDEX IF EXISTS user_emails_lower_email_idx;
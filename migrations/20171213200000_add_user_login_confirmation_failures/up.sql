@@ -0,0 +1,2 @@
+ALTER TABLE users
+ADD COLUMN login_confirmation_failures INTEGER NOT NULL DEFAULT 0;
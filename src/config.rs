@@ -1,48 +1,163 @@
+use uuid::Uuid;
 use failure::{Error, ResultExt};
 
 use std::env;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::path::PathBuf;
+use std::time::Duration;
 
 lazy_static! {
   pub static ref CONFIG: Config = from_env().unwrap();
 }
 
 pub enum Mail {
-    File,
+    /// Writes emails to a directory, for local development.
+    File { dir: PathBuf },
     Smtp {
-        addr: String,
-        user: String,
-        pass: String,
+        host: String,
+        port: u16,
+        tls: SmtpTls,
+        auth: Option<(String, String)>,
     },
+    /// Keeps emails in memory so tests can assert against them.
+    Capture,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SmtpTls {
+    None,
+    StartTls,
+    Implicit,
+}
+
+impl SmtpTls {
+    pub fn default_port(&self) -> u16 {
+        match *self {
+            SmtpTls::None => 25,
+            SmtpTls::StartTls => 587,
+            SmtpTls::Implicit => 465,
+        }
+    }
+}
+
+impl FromStr for SmtpTls {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Ok(match s {
+            "none" => SmtpTls::None,
+            "starttls" => SmtpTls::StartTls,
+            "implicit" => SmtpTls::Implicit,
+            _ => bail!("Invalid SMTP TLS mode"),
+        })
+    }
 }
 
 impl Mail {
     fn smtp_from_env() -> Result<Self, Error> {
+        let host = env::var("SMTP_HOST")
+            .or_else(|_| env::var("SMTP_ADDR"))
+            .context("SMTP_HOST must be set")?;
+        let tls = match env::var("SMTP_TLS") {
+            Ok(v) => SmtpTls::from_str(&v).context("SMTP_TLS must be none, starttls or implicit")?,
+            Err(_) => SmtpTls::StartTls,
+        };
         Ok(Mail::Smtp {
-            addr: env::var("SMTP_ADDR").context("SMTP_ADDR must be set")?,
-            user: env::var("SMTP_USER").context("SMTP_USER must be set")?,
-            pass: env::var("SMTP_PASS").context("SMTP_PASS must be set")?,
+            host,
+            port: match env::var("SMTP_PORT") {
+                Ok(v) => v.parse().context("SMTP_PORT must be a number")?,
+                Err(_) => tls.default_port(),
+            },
+            tls,
+            auth: match (env::var("SMTP_USER"), env::var("SMTP_PASS")) {
+                (Ok(user), Ok(pass)) => Some((user, pass)),
+                _ => None,
+            },
+        })
+    }
+
+    fn file_from_env() -> Self {
+        Mail::File {
+            dir: env::var("MAIL_FILE_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| env::temp_dir()),
+        }
+    }
+
+    pub fn from_env() -> Result<Self, Error> {
+        Ok(match env::var("MAIL_TRANSPORT") {
+            Ok(ref t) if t == "smtp" => Self::smtp_from_env()?,
+            Ok(ref t) if t == "file" => Self::file_from_env(),
+            Ok(ref t) if t == "capture" => Mail::Capture,
+            Ok(_) => bail!("MAIL_TRANSPORT must be smtp, file or capture"),
+            Err(_) => if env::var("SMTP_HOST").is_ok() || env::var("SMTP_ADDR").is_ok() {
+                Self::smtp_from_env()?
+            } else {
+                Self::default_transport()
+            },
         })
     }
 
-    pub fn from_env() -> Self {
-        Self::smtp_from_env().unwrap_or(Mail::File)
+    #[cfg(not(test))]
+    fn default_transport() -> Self {
+        Self::file_from_env()
+    }
+
+    /// Tests capture emails unless told otherwise so they never send real ones.
+    #[cfg(test)]
+    fn default_transport() -> Self {
+        Mail::Capture
     }
 }
 
 pub struct Config {
     pub database_url: String,
     pub database_url_r: Option<String>,
+    pub database_pool_size: u32,
+    pub database_pool_timeout: Duration,
     pub redis_url: String,
     pub mail: Mail,
     pub mail_from: String,
+    pub web_url: String,
+    pub trust_proxy: bool,
+    /// Proxies in front of the directly connected one, skipped when reading `X-Forwarded-For`.
+    pub trusted_proxies: Vec<IpAddr>,
+    pub require_accept: bool,
+    pub admin_user_ids: Vec<Uuid>,
 }
 
 fn from_env() -> Result<Config, Error> {
     Ok(Config {
         database_url: env::var("DATABASE_URL").context("DATABASE_URL must be set")?,
         database_url_r: env::var("DATABASE_URL_R").ok(),
+        database_pool_size: match env::var("DATABASE_POOL_SIZE") {
+            Ok(v) => v.parse().context("DATABASE_POOL_SIZE must be a number")?,
+            Err(_) => 10,
+        },
+        database_pool_timeout: Duration::from_secs(match env::var("DATABASE_POOL_TIMEOUT") {
+            Ok(v) => v.parse().context("DATABASE_POOL_TIMEOUT must be a number of seconds")?,
+            Err(_) => 30,
+        }),
         redis_url: env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string()),
-        mail: Mail::from_env(),
+        mail: Mail::from_env()?,
         mail_from: env::var("MAIL_FROM").unwrap_or_else(|_| "play@brdg.me".to_string()),
+        web_url: env::var("WEB_URL").unwrap_or_else(|_| "https://brdg.me".to_string()),
+        trust_proxy: env::var("TRUST_PROXY").is_ok(),
+        trusted_proxies: match env::var("TRUSTED_PROXIES") {
+            Ok(v) => v.split(',')
+                .map(|ip| IpAddr::from_str(ip.trim()))
+                .collect::<Result<Vec<IpAddr>, _>>()
+                .context("TRUSTED_PROXIES must be a comma separated list of IP addresses")?,
+            Err(_) => vec![],
+        },
+        require_accept: env::var("ALLOW_PLAY_BEFORE_ACCEPT").is_err(),
+        admin_user_ids: match env::var("ADMIN_USER_IDS") {
+            Ok(v) => v.split(',')
+                .map(|id| Uuid::from_str(id.trim()))
+                .collect::<Result<Vec<Uuid>, _>>()
+                .context("ADMIN_USER_IDS must be a comma separated list of user IDs")?,
+            Err(_) => vec![],
+        },
     })
 }
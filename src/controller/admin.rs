@@ -0,0 +1,59 @@
+use rocket::request::{self, FromRequest, Request};
+use rocket::http::Status;
+use rocket::Outcome;
+use rocket_contrib::Json;
+use failure::{Error, ResultExt};
+
+use config::CONFIG;
+use db::{query, CONN};
+use db::models::*;
+use controller::{UuidParam, CORS};
+use errors::ControllerError;
+
+/// A user listed in `ADMIN_USER_IDS`.
+pub struct Admin(pub User);
+
+impl<'a, 'r> FromRequest<'a, 'r> for Admin {
+    type Error = Error;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Error> {
+        match User::from_request(request) {
+            Outcome::Success(user) => if CONFIG.admin_user_ids.contains(&user.id) {
+                Outcome::Success(Admin(user))
+            } else {
+                Outcome::Failure((Status::Forbidden, format_err!("admin access required")))
+            },
+            Outcome::Failure(f) => Outcome::Failure(f),
+            Outcome::Forward(f) => Outcome::Forward(f),
+        }
+    }
+}
+
+#[get("/mail/failed")]
+pub fn failed_emails(admin: Admin) -> Result<CORS<Json<Vec<OutboundEmail>>>, ControllerError> {
+    let conn = &*CONN.r.get().context("unable to get connection")?;
+    Ok(CORS(Json(query::mail::find_failed(conn)?)))
+}
+
+#[post("/mail/<id>/retry")]
+pub fn retry_email(
+    admin: Admin,
+    id: UuidParam,
+) -> Result<CORS<Json<OutboundEmail>>, ControllerError> {
+    let id = id.into_uuid();
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+    Ok(CORS(Json(query::mail::retry(&id, conn)?.ok_or_else(
+        || ControllerError::bad_request("failed email does not exist"),
+    )?)))
+}
+
+/// Every recorded state of a game, for auditing and debugging game servers. States include
+/// hidden information, so this isn't available to players.
+#[get("/game/<id>/history")]
+pub fn game_history(
+    admin: Admin,
+    id: UuidParam,
+) -> Result<CORS<Json<Vec<GameHistoryEntry>>>, ControllerError> {
+    let conn = &*CONN.r.get().context("unable to get connection")?;
+    Ok(CORS(Json(query::history::find_by_game(&id.into_uuid(), conn)?)))
+}
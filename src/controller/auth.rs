@@ -7,12 +7,15 @@ use lettre::email::EmailBuilder;
 use uuid::Uuid;
 use failure::{Error, ResultExt};
 
+use std::ops::Deref;
 use std::str::FromStr;
 
 use db::{query, CONN};
 use db::models::*;
+use db::scope::{Scope, SCOPES};
 use mail;
-use controller::CORS;
+use controller::{ClientIp, UuidParam, CORS};
+use errors::ControllerError;
 
 #[derive(Deserialize)]
 pub struct CreateForm {
@@ -20,13 +23,22 @@ pub struct CreateForm {
 }
 
 #[post("/", data = "<data>")]
-pub fn create(data: Json<CreateForm>) -> Result<CORS<()>, Error> {
+pub fn create(data: Json<CreateForm>, ip: ClientIp) -> Result<CORS<()>, ControllerError> {
     let create_email = data.into_inner().email;
     let conn = &*CONN.w.get().context("unable to get connection")?;
+    if query::login::request_limit_exceeded(&create_email, ip.addr(), conn)
+        .context("unable to check login request limit")?
+    {
+        return Err(ControllerError::too_many_requests(
+            "too many login requests, please try again later",
+        ));
+    }
     let confirmation =
         query::user_login_request(&create_email, conn).context("unable to request user login")?;
+    query::login::create_request(&create_email, ip.addr(), conn)
+        .context("unable to record login request")?;
 
-    mail::send(EmailBuilder::new()
+    let message = EmailBuilder::new()
         .to(create_email.as_ref())
         .from("play@brdg.me")
         .subject("brdg.me login confirmation")
@@ -37,10 +49,8 @@ This confirmation will expire in 30 minutes if not used.",
             confirmation
         )))
         .build()
-        .context("unable to create login confirmation email")?)
-        .context({
-        "unable to send login confirmation email"
-    })?;
+        .context("unable to create login confirmation email")?;
+    mail::send(message, conn).context("unable to send login confirmation email")?;
 
     Ok(CORS(()))
 }
@@ -52,68 +62,367 @@ pub struct ConfirmRequest {
 }
 
 #[post("/confirm", data = "<data>")]
-pub fn confirm(data: Json<ConfirmRequest>) -> Result<CORS<Json<String>>, Error> {
+pub fn confirm(
+    data: Json<ConfirmRequest>,
+    ip: ClientIp,
+) -> Result<CORS<Json<String>>, ControllerError> {
     let data = data.into_inner();
     let conn = &*CONN.w.get().context("unable to get connection")?;
+    if query::login::attempt_limit_exceeded(&data.email, ip.addr(), conn)
+        .context("unable to check login attempt limit")?
+    {
+        return Err(ControllerError::too_many_requests(
+            "too many failed login attempts, please try again later",
+        ));
+    }
 
     match query::user_login_confirm(&data.email, &data.code, conn)
         .context("unable to confirm login")?
     {
-        Some(token) => Ok(CORS(Json(token.id.to_string()))),
-        None => Err(format_err!("unable to confirm login")),
+        Some(token) => {
+            query::login::create_attempt(&data.email, ip.addr(), true, conn)
+                .context("unable to record login attempt")?;
+            Ok(CORS(Json(token.id.to_string())))
+        }
+        None => {
+            query::login::create_attempt(&data.email, ip.addr(), false, conn)
+                .context("unable to record login attempt")?;
+            Err(ControllerError::unauthorized(
+                "invalid or expired confirmation code",
+            ))
+        }
     }
 }
 
-impl<'a, 'r> FromRequest<'a, 'r> for User {
+#[post("/logout")]
+pub fn logout(token: UserAuthToken) -> Result<CORS<()>, ControllerError> {
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+
+    query::revoke_user_auth_token(&token.user_id, &token.session_id, conn)
+        .context("unable to revoke auth token")?;
+    Ok(CORS(()))
+}
+
+#[get("/sessions")]
+pub fn sessions(
+    token: UserAuthToken,
+) -> Result<CORS<Json<Vec<PublicUserAuthToken>>>, ControllerError> {
+    let conn = &*CONN.r.get().context("unable to get connection")?;
+
+    Ok(CORS(Json(query::find_valid_user_auth_tokens_for_users(&[token.user_id], conn)
+        .context("unable to find sessions")?
+        .into_iter()
+        .map(|uat| uat.into_public())
+        .collect())))
+}
+
+/// Revokes a session by its `session_id`, as listed by `sessions`.
+#[delete("/sessions/<session_id>")]
+pub fn revoke_session(
+    session_id: UuidParam,
+    user: User,
+) -> Result<CORS<Json<PublicUserAuthToken>>, ControllerError> {
+    let session_id = session_id.into_uuid();
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+
+    match query::revoke_user_auth_token(&user.id, &session_id, conn)
+        .context("unable to revoke session")?
+    {
+        Some(uat) => Ok(CORS(Json(uat.into_public()))),
+        None => Err(ControllerError::bad_request("session does not exist")),
+    }
+}
+
+#[delete("/sessions")]
+pub fn revoke_sessions(
+    user: User,
+) -> Result<CORS<Json<Vec<PublicUserAuthToken>>>, ControllerError> {
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+
+    Ok(CORS(Json(query::revoke_user_auth_tokens_for_user(&user.id, conn)
+        .context("unable to revoke sessions")?
+        .into_iter()
+        .map(|uat| uat.into_public())
+        .collect())))
+}
+
+#[get("/api_keys")]
+pub fn api_keys(
+    token: UserAuthToken,
+) -> Result<CORS<Json<Vec<PublicUserApiKey>>>, ControllerError> {
+    let conn = &*CONN.r.get().context("unable to get connection")?;
+
+    Ok(CORS(Json(query::api_key::find_valid_by_user(&token.user_id, conn)
+        .context("unable to find API keys")?
+        .into_iter()
+        .map(|ak| ak.into_public())
+        .collect())))
+}
+
+#[derive(Deserialize)]
+pub struct CreateApiKeyRequest {
+    name: String,
+    scopes: Vec<String>,
+}
+
+/// The key is only ever returned when it is created.
+#[post("/api_keys", data = "<data>")]
+pub fn create_api_key(
+    data: Json<CreateApiKeyRequest>,
+    token: UserAuthToken,
+) -> Result<CORS<Json<UserApiKey>>, ControllerError> {
+    let data = data.into_inner();
+    let name = data.name.trim();
+    if name.is_empty() {
+        return Err(ControllerError::bad_request("API key name must not be empty"));
+    }
+    if data.scopes.is_empty() {
+        return Err(ControllerError::bad_request(
+            "API key must have at least one scope",
+        ));
+    }
+    Scope::from_strings(&data.scopes).map_err(|_| {
+        ControllerError::bad_request(format!(
+            "invalid scope, valid scopes are: {}",
+            SCOPES
+                .iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        ))
+    })?;
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+
+    Ok(CORS(Json(query::api_key::create(&token.user_id, name, &data.scopes, conn)
+        .context("unable to create API key")?)))
+}
+
+#[delete("/api_keys/<id>")]
+pub fn revoke_api_key(
+    id: UuidParam,
+    token: UserAuthToken,
+) -> Result<CORS<Json<PublicUserApiKey>>, ControllerError> {
+    let id = id.into_uuid();
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+
+    match query::api_key::revoke(&token.user_id, &id, conn).context("unable to revoke API key")? {
+        Some(ak) => Ok(CORS(Json(ak.into_public()))),
+        None => Err(ControllerError::bad_request("API key does not exist")),
+    }
+}
+
+/// Tokens can be passed either as a Bearer token, or as the username for Basic auth.
+fn auth_header_token(request: &Request) -> Result<Uuid, Error> {
+    let auth_header = request
+        .headers()
+        .get_one("Authorization")
+        .ok_or_else::<Error, _>(|| format_err!("missing Authorization header"))?;
+    let token = if auth_header.starts_with("Bearer ") {
+        auth_header[7..].trim().to_string()
+    } else if auth_header.starts_with("Basic ") {
+        Basic::from_str(&auth_header[6..])
+            .map_err::<Error, _>(|_| format_err!("invalid Authorization header"))?
+            .username
+    } else {
+        bail!("expected Basic or Bearer Authorization header");
+    };
+    Ok(Uuid::parse_str(&token)
+        .map_err::<Error, _>(|_| format_err!("Authorization token not in valid format"))?)
+}
+
+enum Credential {
+    AuthToken(UserAuthToken),
+    ApiKey(UserApiKey),
+}
+
+impl Credential {
+    fn user_id(&self) -> Uuid {
+        match *self {
+            Credential::AuthToken(ref uat) => uat.user_id,
+            Credential::ApiKey(ref ak) => ak.user_id,
+        }
+    }
+}
+
+fn touch_credential(credential: &Credential) -> Result<(), Error> {
+    let needs_touch = match *credential {
+        Credential::AuthToken(ref uat) => query::user_auth_token_needs_touch(uat),
+        Credential::ApiKey(ref ak) => query::api_key::needs_touch(ak),
+    };
+    if !needs_touch {
+        return Ok(());
+    }
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+    match *credential {
+        Credential::AuthToken(ref uat) => {
+            query::touch_user_auth_token(&uat.id, conn)?;
+        }
+        Credential::ApiKey(ref ak) => {
+            query::api_key::touch(&ak.id, conn)?;
+        }
+    }
+    Ok(())
+}
+
+/// API keys are only accepted if they have the scope, and never when it's `None`.
+fn request_credential(
+    request: &Request,
+    scope: Option<Scope>,
+) -> request::Outcome<Credential, Error> {
+    let token = match auth_header_token(request) {
+        Ok(t) => t,
+        Err(e) => return Outcome::Failure((Status::Unauthorized, e)),
+    };
+    let conn = &*match CONN.r.get() {
+        Ok(c) => c,
+        Err(_) => {
+            return Outcome::Failure((
+                Status::InternalServerError,
+                format_err!("error getting connection"),
+            ))
+        }
+    };
+
+    let credential = match query::find_valid_user_auth_token(&token, conn) {
+        Ok(Some(uat)) => Credential::AuthToken(uat),
+        Ok(None) => match query::api_key::find_valid_by_key(&token, conn) {
+            Ok(Some(ak)) => match scope {
+                Some(scope) if ak.scopes.contains(&scope.to_string()) => Credential::ApiKey(ak),
+                Some(_) => {
+                    return Outcome::Failure((
+                        Status::Forbidden,
+                        format_err!("API key does not have the required scope"),
+                    ))
+                }
+                None => {
+                    return Outcome::Failure((
+                        Status::Forbidden,
+                        format_err!("API keys cannot be used for this request"),
+                    ))
+                }
+            },
+            _ => {
+                return Outcome::Failure((Status::Unauthorized, format_err!("invalid credentials")))
+            }
+        },
+        Err(_) => {
+            return Outcome::Failure((Status::Unauthorized, format_err!("invalid credentials")))
+        }
+    };
+    if let Err(e) = touch_credential(&credential) {
+        warn!("unable to touch credential: {}", e);
+    }
+    Outcome::Success(credential)
+}
+
+/// Only login sessions can be used as a `UserAuthToken`, API keys are rejected.
+impl<'a, 'r> FromRequest<'a, 'r> for UserAuthToken {
     type Error = Error;
 
     fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Error> {
-        let auth_header = match request.headers().get_one("Authorization") {
-            Some(a) => a,
-            None => {
-                return Outcome::Failure((
-                    Status::Unauthorized,
-                    format_err!("missing Authorization header"),
-                ))
-            }
-        };
-        if !auth_header.starts_with("Basic ") {
-            return Outcome::Failure((
+        match request_credential(request, None) {
+            Outcome::Success(Credential::AuthToken(uat)) => Outcome::Success(uat),
+            Outcome::Success(Credential::ApiKey(_)) => Outcome::Failure((
                 Status::Unauthorized,
-                format_err!("expected Basic Authorization header"),
-            ));
+                format_err!("API keys cannot be used for this request"),
+            )),
+            Outcome::Failure(f) => Outcome::Failure(f),
+            Outcome::Forward(f) => Outcome::Forward(f),
         }
-        let auth = match Basic::from_str(&auth_header[6..]) {
-            Ok(a) => a,
-            Err(e) => {
-                return Outcome::Failure((
-                    Status::Unauthorized,
-                    format_err!("invalid Authorization header"),
-                ))
-            }
-        };
-        let token = match Uuid::parse_str(&auth.username) {
-            Ok(uuid) => uuid,
-            Err(_) => {
-                return Outcome::Failure((
-                    Status::Unauthorized,
-                    format_err!("Authorization password not in valid format"),
-                ))
-            }
-        };
-        let conn = &*match CONN.r.get() {
-            Ok(c) => c,
-            Err(_) => {
-                return Outcome::Failure((
-                    Status::InternalServerError,
-                    format_err!("error getting connection"),
-                ))
-            }
-        };
+    }
+}
 
-        match query::authenticate(&token, conn) {
-            Ok(Some(user)) => Outcome::Success(user),
-            _ => Outcome::Failure((Status::Unauthorized, format_err!("invalid credentials"))),
+fn request_user(request: &Request, scope: Option<Scope>) -> request::Outcome<User, Error> {
+    let user_id = match request_credential(request, scope) {
+        Outcome::Success(credential) => credential.user_id(),
+        Outcome::Failure(f) => return Outcome::Failure(f),
+        Outcome::Forward(f) => return Outcome::Forward(f),
+    };
+    let conn = &*match CONN.r.get() {
+        Ok(c) => c,
+        Err(_) => {
+            return Outcome::Failure((
+                Status::InternalServerError,
+                format_err!("error getting connection"),
+            ))
         }
+    };
+
+    match query::find_user(&user_id, conn) {
+        Ok(Some(user)) => Outcome::Success(user),
+        _ => Outcome::Failure((Status::Unauthorized, format_err!("invalid credentials"))),
+    }
+}
+
+/// Only login sessions can be used as a `User`. Handlers which API keys may call take a
+/// `ReadUser` or `PlayUser` instead.
+impl<'a, 'r> FromRequest<'a, 'r> for User {
+    type Error = Error;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Error> {
+        request_user(request, None)
+    }
+}
+
+/// A user from a login session, or an API key with the `read` scope.
+pub struct ReadUser(pub User);
+
+impl Deref for ReadUser {
+    type Target = User;
+
+    fn deref(&self) -> &User {
+        &self.0
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ReadUser {
+    type Error = Error;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Error> {
+        request_user(request, Some(Scope::Read)).map(ReadUser)
+    }
+}
+
+/// A user from a login session, or an API key with the `play` scope.
+pub struct PlayUser(pub User);
+
+impl Deref for PlayUser {
+    type Target = User;
+
+    fn deref(&self) -> &User {
+        &self.0
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for PlayUser {
+    type Error = Error;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Error> {
+        request_user(request, Some(Scope::Play)).map(PlayUser)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore]
+    fn create_sends_login_confirmation() {
+        let email = format!("{}@example.com", Uuid::new_v4());
+        create(
+            Json(CreateForm {
+                email: email.clone(),
+            }),
+            ClientIp(None),
+        ).unwrap();
+
+        let conn = &*CONN.w.get().unwrap();
+        let (_, user) = query::find_user_by_email(&email, conn).unwrap().unwrap();
+        let confirmation = user.login_confirmation.unwrap();
+        let sent = mail::captured_for(&email).unwrap();
+        assert_eq!(1, sent.len());
+        assert!(sent[0].message.contains("brdg.me login confirmation"));
+        assert!(sent[0].message.contains(&confirmation));
     }
 }
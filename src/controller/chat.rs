@@ -0,0 +1,188 @@
+use rocket::State;
+use rocket_contrib::Json;
+use diesel::Connection;
+use diesel::pg::PgConnection;
+use uuid::Uuid;
+use failure::{Error, ResultExt};
+
+use std::sync::Mutex;
+use std::sync::mpsc::Sender;
+
+use db::{models, query, CONN};
+use controller::{UuidParam, CORS};
+use controller::auth::{PlayUser, ReadUser};
+use errors::ControllerError;
+use websocket;
+
+const MESSAGE_MAX_LEN: usize = 1000;
+const PAGE_DEFAULT_LIMIT: i64 = 50;
+const PAGE_MAX_LIMIT: i64 = 200;
+
+fn find_chat_user(
+    chat_id: &Uuid,
+    user: &models::User,
+    conn: &PgConnection,
+) -> Result<models::ChatUser, ControllerError> {
+    query::chat::find_chat_user(chat_id, &user.id, conn)
+        .context("error finding chat user")?
+        .ok_or_else(|| ControllerError::bad_request("you are not a member of this chat"))
+}
+
+#[derive(Deserialize)]
+pub struct CreateMessageRequest {
+    message: String,
+}
+
+#[post("/<id>/messages", data = "<data>")]
+pub fn create_message(
+    id: UuidParam,
+    user: PlayUser,
+    pub_queue_tx: State<Mutex<Sender<websocket::Message>>>,
+    data: Json<CreateMessageRequest>,
+) -> Result<CORS<Json<models::PublicChatMessage>>, ControllerError> {
+    let id = id.into_uuid();
+    let message = data.into_inner().message.trim().to_string();
+    if message.is_empty() {
+        return Err(ControllerError::bad_request("message cannot be empty"));
+    }
+    if message.chars().count() > MESSAGE_MAX_LEN {
+        return Err(ControllerError::bad_request(format!(
+            "message cannot be longer than {} characters",
+            MESSAGE_MAX_LEN
+        )));
+    }
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+
+    conn.transaction::<_, ControllerError, _>(|| {
+        let chat_user = find_chat_user(&id, &user, conn)?;
+        let user_ids = query::chat::find_users_by_chat(&id, conn)
+            .context("error finding chat users")?
+            .into_iter()
+            .map(|cu| cu.user_id)
+            .collect::<Vec<Uuid>>();
+        if query::block::is_blocked_by_any(&user.id, &user_ids, conn)
+            .context("error checking blocked users")?
+        {
+            return Err(ControllerError::bad_request(
+                "a member of this chat is not accepting messages from you",
+            ));
+        }
+        let chat_message = query::chat::create_message(chat_user.id, &message, conn)
+            .context("error creating chat message")?;
+        // Posting a message implies the user has read everything before it.
+        query::chat::update_user_last_read_at(&chat_user.id, chat_message.created_at, conn)
+            .context("error updating last read at")?;
+
+        let game = query::game::find_by_chat_id(&id, conn).context("error finding game for chat")?;
+        websocket::enqueue_chat_message(
+            &id,
+            game.as_ref().map(|g| &g.id),
+            &chat_message,
+            &user.clone().into_public(),
+            &query::find_valid_user_auth_tokens_for_users(&user_ids, conn)?,
+            &pub_queue_tx
+                .inner()
+                .lock()
+                .map_err::<Error, _>(|e| format_err!("unable to get lock on pub_queue_tx: {}", e))?
+                .clone(),
+        )?;
+        Ok(CORS(Json(chat_message)))
+    })
+}
+
+#[derive(Serialize)]
+pub struct MessagesResponse {
+    pub chat_messages: Vec<models::PublicChatMessage>,
+    pub has_more: bool,
+}
+
+#[get("/<id>/messages", rank = 2)]
+pub fn messages(
+    id: UuidParam,
+    user: ReadUser,
+) -> Result<CORS<Json<MessagesResponse>>, ControllerError> {
+    let conn = &*CONN.r.get().context("unable to get connection")?;
+    Ok(CORS(Json(messages_page(
+        &id.into_uuid(),
+        &user,
+        None,
+        PAGE_DEFAULT_LIMIT,
+        conn,
+    )?)))
+}
+
+#[derive(FromForm)]
+pub struct MessagesQuery {
+    /// The ID of the oldest message already loaded.
+    before: Option<String>,
+    limit: Option<i64>,
+}
+
+#[get("/<id>/messages?<query>", rank = 1)]
+pub fn messages_with_query(
+    id: UuidParam,
+    query: MessagesQuery,
+    user: ReadUser,
+) -> Result<CORS<Json<MessagesResponse>>, ControllerError> {
+    let before = match query.before {
+        Some(ref b) => Some(Uuid::parse_str(b)
+            .map_err(|_| ControllerError::bad_request("before must be a message ID"))?),
+        None => None,
+    };
+    let limit = query.limit.unwrap_or(PAGE_DEFAULT_LIMIT);
+    if limit < 1 || limit > PAGE_MAX_LIMIT {
+        return Err(ControllerError::bad_request(format!(
+            "limit must be between 1 and {}",
+            PAGE_MAX_LIMIT
+        )));
+    }
+    let conn = &*CONN.r.get().context("unable to get connection")?;
+    Ok(CORS(Json(messages_page(
+        &id.into_uuid(),
+        &user,
+        before.as_ref(),
+        limit,
+        conn,
+    )?)))
+}
+
+fn messages_page(
+    chat_id: &Uuid,
+    user: &models::User,
+    before: Option<&Uuid>,
+    limit: i64,
+    conn: &PgConnection,
+) -> Result<MessagesResponse, ControllerError> {
+    find_chat_user(chat_id, user, conn)?;
+    let before = match before {
+        Some(before) => Some(query::chat::find_message_in_chat(chat_id, before, conn)
+            .context("error finding chat message")?
+            .ok_or_else(|| ControllerError::bad_request("message does not exist"))?),
+        None => None,
+    };
+    // Fetch one extra so we know whether there are older messages.
+    let mut chat_messages =
+        query::chat::find_messages_page(chat_id, before.as_ref(), limit + 1, conn)
+            .context("error finding chat messages")?;
+    let has_more = chat_messages.len() as i64 > limit;
+    if has_more {
+        chat_messages.remove(0);
+    }
+    Ok(MessagesResponse {
+        chat_messages,
+        has_more,
+    })
+}
+
+#[post("/<id>/mark_read")]
+pub fn mark_read(
+    id: UuidParam,
+    user: PlayUser,
+) -> Result<CORS<Json<models::PublicChatUser>>, ControllerError> {
+    let id = id.into_uuid();
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+    let chat_user = find_chat_user(&id, &user, conn)?;
+    Ok(CORS(Json(query::chat::update_user_last_read_at_now(&chat_user.id, conn)
+        .context("error marking chat read")?
+        .ok_or_else(|| ControllerError::bad_request("you are not a member of this chat"))?)))
+}
@@ -0,0 +1,128 @@
+use rocket_contrib::Json;
+use diesel::Connection;
+use failure::ResultExt;
+
+use db::{models, query, CONN};
+use controller::{UuidParam, CORS};
+use controller::auth::ReadUser;
+use errors::ControllerError;
+
+/// A friend or friend request along with the user on the other side of it.
+#[derive(Serialize)]
+pub struct FriendUser {
+    pub friend: models::PublicFriend,
+    pub user: models::PublicUser,
+}
+
+fn to_friend_users(rows: Vec<(models::Friend, models::User)>) -> Vec<FriendUser> {
+    rows.into_iter()
+        .map(|(friend, user)| FriendUser {
+            friend,
+            user: user.into_public(),
+        })
+        .collect()
+}
+
+/// Friends' user IDs can be used as `opponent_ids` when creating a game.
+#[get("/")]
+pub fn index(user: ReadUser) -> Result<CORS<Json<Vec<FriendUser>>>, ControllerError> {
+    let conn = &*CONN.r.get().context("unable to get connection")?;
+    Ok(CORS(Json(to_friend_users(query::friend::find_friends(
+        &user.id,
+        conn,
+    ).context("error finding friends")?))))
+}
+
+#[derive(Serialize)]
+pub struct PendingResponse {
+    pub incoming: Vec<FriendUser>,
+    pub outgoing: Vec<FriendUser>,
+}
+
+#[get("/pending")]
+pub fn pending(user: ReadUser) -> Result<CORS<Json<PendingResponse>>, ControllerError> {
+    let conn = &*CONN.r.get().context("unable to get connection")?;
+    Ok(CORS(Json(PendingResponse {
+        incoming: to_friend_users(query::friend::find_incoming(&user.id, conn)
+            .context("error finding incoming friend requests")?),
+        outgoing: to_friend_users(query::friend::find_outgoing(&user.id, conn)
+            .context("error finding outgoing friend requests")?),
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct CreateRequest {
+    name: String,
+}
+
+/// Sends a friend request by user name. If the other user has already sent
+/// one, it is accepted instead.
+#[post("/", data = "<data>")]
+pub fn create(
+    data: Json<CreateRequest>,
+    user: models::User,
+) -> Result<CORS<Json<models::PublicFriend>>, ControllerError> {
+    let name = data.into_inner().name.trim().to_string();
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+
+    conn.transaction::<_, ControllerError, _>(|| {
+        let target = query::user::find_by_name(&name, conn)
+            .context("error finding user by name")?
+            .ok_or_else(|| ControllerError::bad_request("could not find a user with that name"))?;
+        if target.id == user.id {
+            return Err(ControllerError::bad_request("you can't add yourself as a friend"));
+        }
+        if query::block::is_blocked_either_way(&user.id, &target.id, conn)
+            .context("error checking blocked users")?
+        {
+            return Err(ControllerError::bad_request(
+                "you can't send a friend request to this user",
+            ));
+        }
+        let friend = match query::friend::find_between(&user.id, &target.id, conn)
+            .context("error finding existing friend")?
+        {
+            Some(ref f) if f.has_accepted == Some(true) => {
+                return Err(ControllerError::bad_request("you are already friends"))
+            }
+            Some(ref f) if f.source_user_id == user.id => {
+                return Err(ControllerError::bad_request("you have already sent a request"))
+            }
+            Some(f) => query::friend::accept(&user.id, &f.id, conn)
+                .context("error accepting friend request")?
+                .ok_or_else(|| ControllerError::bad_request("friend request does not exist"))?,
+            None => query::friend::create_request(&user.id, &target.id, conn)
+                .context("error creating friend request")?,
+        };
+        Ok(CORS(Json(friend)))
+    })
+}
+
+#[post("/<id>/accept")]
+pub fn accept(
+    id: UuidParam,
+    user: models::User,
+) -> Result<CORS<Json<models::PublicFriend>>, ControllerError> {
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+    Ok(CORS(Json(query::friend::accept(&user.id, &id.into_uuid(), conn)
+        .context("error accepting friend request")?
+        .ok_or_else(|| ControllerError::bad_request("friend request does not exist"))?)))
+}
+
+#[post("/<id>/decline")]
+pub fn decline(id: UuidParam, user: models::User) -> Result<CORS<()>, ControllerError> {
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+    query::friend::decline(&user.id, &id.into_uuid(), conn)
+        .context("error declining friend request")?
+        .ok_or_else(|| ControllerError::bad_request("friend request does not exist"))?;
+    Ok(CORS(()))
+}
+
+#[delete("/<id>")]
+pub fn remove(id: UuidParam, user: models::User) -> Result<CORS<()>, ControllerError> {
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+    query::friend::remove(&user.id, &id.into_uuid(), conn)
+        .context("error removing friend")?
+        .ok_or_else(|| ControllerError::bad_request("friend does not exist"))?;
+    Ok(CORS(()))
+}
@@ -4,6 +4,7 @@ use uuid::Uuid;
 use diesel::Connection;
 use diesel::pg::PgConnection;
 use chrono::Utc;
+use lettre::email::EmailBuilder;
 use failure::{Error, ResultExt};
 
 use brdgme_cmd::cli;
@@ -11,16 +12,22 @@ use brdgme_game::{Stat, Status};
 use brdgme_game::command::Spec as CommandSpec;
 use brdgme_markup as markup;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::borrow::Cow;
+use std::str::FromStr;
 use std::sync::Mutex;
 use std::sync::mpsc::Sender;
 
+use config::CONFIG;
 use db::{models, query};
 use db::CONN;
+use db::history::HistoryAction;
+use db::time_control::{self, TimeControl, TimeoutPenalty};
 use game_client;
+use mail;
 use render;
 use controller::{UuidParam, CORS};
+use controller::auth::{PlayUser, ReadUser};
 use websocket;
 use errors::ControllerError;
 
@@ -29,99 +36,426 @@ pub struct CreateRequest {
     game_version_id: Uuid,
     opponent_ids: Option<Vec<Uuid>>,
     opponent_emails: Option<Vec<String>>,
+    time_control: Option<TimeControlRequest>,
+}
+
+#[derive(Deserialize)]
+pub struct TimeControlRequest {
+    turn_days: i32,
+    time_bank_hours: Option<i32>,
+    grace_hours: Option<i32>,
+    timeout_penalty: Option<String>,
+}
+
+const MAX_TURN_DAYS: i32 = 30;
+const MAX_TIME_BANK_HOURS: i32 = 24 * 30;
+const MAX_GRACE_HOURS: i32 = 24 * 7;
+
+impl TimeControlRequest {
+    fn into_time_control(self) -> Result<TimeControl, ControllerError> {
+        let time_control = TimeControl {
+            turn_days: self.turn_days,
+            time_bank_hours: self.time_bank_hours.unwrap_or(0),
+            grace_hours: self.grace_hours.unwrap_or(0),
+            timeout_penalty: match self.timeout_penalty {
+                Some(ref tp) => TimeoutPenalty::from_str(tp).map_err(|_| {
+                    ControllerError::bad_request(format!(
+                        "timeout_penalty must be one of: {}",
+                        time_control::TIMEOUT_PENALTIES
+                            .iter()
+                            .map(|tp| tp.to_string())
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    ))
+                })?,
+                None => TimeoutPenalty::Concede,
+            },
+        };
+        if time_control.turn_days < 1 || time_control.turn_days > MAX_TURN_DAYS {
+            return Err(ControllerError::bad_request(format!(
+                "turn_days must be between 1 and {}",
+                MAX_TURN_DAYS
+            )));
+        }
+        if time_control.time_bank_hours < 0 || time_control.time_bank_hours > MAX_TIME_BANK_HOURS {
+            return Err(ControllerError::bad_request(format!(
+                "time_bank_hours must be between 0 and {}",
+                MAX_TIME_BANK_HOURS
+            )));
+        }
+        if time_control.grace_hours < 0 || time_control.grace_hours > MAX_GRACE_HOURS {
+            return Err(ControllerError::bad_request(format!(
+                "grace_hours must be between 0 and {}",
+                MAX_GRACE_HOURS
+            )));
+        }
+        Ok(time_control)
+    }
 }
 
 #[post("/", data = "<data>")]
 pub fn create(
     data: Json<CreateRequest>,
-    user: models::User,
+    user: PlayUser,
     pub_queue_tx: State<Mutex<Sender<websocket::Message>>>,
 ) -> Result<CORS<Json<ShowResponse>>, ControllerError> {
     let user_id = user.id;
     let data = data.into_inner();
     let conn = &*CONN.w.get().context("unable to get connection")?;
+    let opponent_ids = data.opponent_ids.unwrap_or_else(|| vec![]);
+    let opponent_emails: Vec<String> = data.opponent_emails
+        .unwrap_or_else(|| vec![])
+        .iter()
+        .map(|e| e.trim().to_string())
+        .collect();
+    let game_version_id = data.game_version_id;
+    let time_control = match data.time_control {
+        Some(tc) => Some(tc.into_time_control()?),
+        None => None,
+    };
+    validate_create(
+        &user_id,
+        &game_version_id,
+        &opponent_ids,
+        &opponent_emails,
+        conn,
+    )?;
+    if query::block::invitation_blocked(&user_id, &opponent_ids, &opponent_emails, conn)
+        .context("error checking blocked users")?
+    {
+        return Err(ControllerError::bad_request(
+            "one of the invited players is not accepting invitations from you",
+        ));
+    }
 
-    let (created_game, created_logs, public_render, player_renders, user_ids) =
-        conn.transaction::<_, Error, _>(move || {
-            let opponent_ids = data.opponent_ids.unwrap_or_else(|| vec![]);
-            let opponent_emails = data.opponent_emails.unwrap_or_else(|| vec![]);
-            let player_count: usize = 1 + opponent_ids.len() + opponent_emails.len();
-            let game_version = query::find_game_version(&data.game_version_id, conn)
-                .context("error finding game version")?
-                .ok_or_else::<Error, _>(|| format_err!("could not find game version"))?;
-
-            let resp = game_client::request(
-                &game_version.uri,
-                &cli::Request::New {
-                    players: player_count,
-                },
-            )?;
-            let (game_info, logs, public_render, player_renders) = match resp {
-                cli::Response::New {
-                    game,
-                    logs,
-                    public_render,
-                    player_renders,
-                } => (game, logs, public_render, player_renders),
-                _ => bail!("expected cli::Response::New"),
-            };
-            let status = game_status_values(&game_info.status);
-            let created_game = query::create_game_with_users(
-                &query::CreateGameOpts {
-                    new_game: &models::NewGame {
-                        game_version_id: data.game_version_id,
-                        is_finished: status.is_finished,
-                        game_state: &game_info.state,
-                    },
-                    whose_turn: &status.whose_turn,
-                    eliminated: &status.eliminated,
-                    placings: &status.placings,
-                    points: &game_info.points,
-                    creator_id: &user_id,
-                    opponent_ids: &opponent_ids,
-                    opponent_emails: &opponent_emails,
-                    chat_id: None,
-                },
-                conn,
-            ).context("unable to create game")?;
-            let created_logs = query::create_game_logs_from_cli(&created_game.game.id, logs, conn)
-                .context("unable to create game logs")?;
-            let mut user_ids = opponent_ids.clone();
-            user_ids.push(user_id);
-            Ok((
-                created_game,
-                created_logs,
-                public_render,
-                player_renders,
-                user_ids,
-            ))
-        }).context("error committing transaction")?;
-    let game_extended = query::find_game_extended(&created_game.game.id, conn)
-        .context("unable to get extended game")?;
-    let player = created_game.players.iter().find(|p| p.user_id == user_id);
-    websocket::enqueue_game_update(
-        &game_extended.clone().into_public(),
-        &created_logs,
-        &public_render,
-        &player_renders,
-        &query::find_valid_user_auth_tokens_for_users(&user_ids, conn)?,
-        &pub_queue_tx
-            .inner()
-            .lock()
-            .map_err::<Error, _>(|e| format_err!("unable to get lock on pub_queue_tx: {}", e))?
-            .clone(),
+    let started = conn.transaction::<_, Error, _>(|| {
+        start_game(
+            &user_id,
+            &game_version_id,
+            &opponent_ids,
+            &opponent_emails,
+            time_control.as_ref(),
+            conn,
+        )
+    }).context("error committing transaction")?;
+    let tx = pub_queue_tx
+        .inner()
+        .lock()
+        .map_err::<Error, _>(|e| format_err!("unable to get lock on pub_queue_tx: {}", e))?
+        .clone();
+    let game_extended = publish_started_game(&started, &tx, conn)?;
+    websocket::enqueue_game_invitation(
+        &game_extended.game.id,
+        &game_extended.game_type,
+        &user.clone().into_public(),
+        &query::find_valid_user_auth_tokens_for_users(&opponent_ids, conn)?,
+        &tx,
     )?;
+    // Opponents invited by email come after those invited by ID.
+    let email_invitees: Vec<(String, models::User)> = started
+        .created_game
+        .opponents
+        .iter()
+        .skip(opponent_ids.len())
+        .map(|&(_, ref u)| u.to_owned())
+        .zip(opponent_emails.into_iter())
+        .map(|(u, email)| (email, u))
+        .collect();
+    if let Err(e) = send_invitation_emails(
+        &user,
+        &game_extended,
+        &email_invitees,
+        &started.player_renders,
+        conn,
+    ) {
+        warn!("error sending game invitation emails: {}", e);
+    }
+    let player = started
+        .created_game
+        .players
+        .iter()
+        .find(|p| p.user_id == user_id);
     Ok(CORS(Json(game_extended_to_show_response(
         player,
         &game_extended,
         player
-            .and_then(|p| player_renders.get(p.position as usize))
+            .and_then(|p| started.player_renders.get(p.position as usize))
             .map(|render| render.to_owned().into())
             .as_ref(),
+        &[],
         conn,
     )?)))
 }
 
+/// Checks a new game request before anything is sent to the game server.
+fn validate_create(
+    creator_id: &Uuid,
+    game_version_id: &Uuid,
+    opponent_ids: &[Uuid],
+    opponent_emails: &[String],
+    conn: &PgConnection,
+) -> Result<(), ControllerError> {
+    let version_type = query::find_game_version_type(game_version_id, conn)
+        .context("error finding game version")?
+        .ok_or_else(|| ControllerError::bad_request("could not find game version"))?;
+    if !version_type.game_version.is_public || version_type.game_version.is_deprecated {
+        return Err(ControllerError::bad_request(
+            "this game version is not available for new games",
+        ));
+    }
+
+    // Resolve everyone to user IDs so the same player can't be invited twice,
+    // whether by ID, by email, or both.
+    let mut seen: HashSet<Uuid> = HashSet::new();
+    seen.insert(*creator_id);
+    for id in opponent_ids {
+        if id == creator_id {
+            return Err(ControllerError::bad_request("you can't invite yourself"));
+        }
+        if query::find_user(id, conn)
+            .context("error finding opponent")?
+            .is_none()
+        {
+            return Err(ControllerError::bad_request(format!(
+                "could not find opponent {}",
+                id
+            )));
+        }
+        if !seen.insert(*id) {
+            return Err(ControllerError::bad_request("opponents can only be invited once"));
+        }
+    }
+    let mut seen_emails: HashSet<String> = HashSet::new();
+    for email in opponent_emails {
+        if email.is_empty() {
+            return Err(ControllerError::bad_request("opponent emails cannot be blank"));
+        }
+        if !seen_emails.insert(email.to_lowercase()) {
+            return Err(ControllerError::bad_request("opponents can only be invited once"));
+        }
+        if let Some((_, opponent)) =
+            query::find_user_by_email(email, conn).context("error finding opponent by email")?
+        {
+            if opponent.id == *creator_id {
+                return Err(ControllerError::bad_request("you can't invite yourself"));
+            }
+            if !seen.insert(opponent.id) {
+                return Err(ControllerError::bad_request("opponents can only be invited once"));
+            }
+        }
+    }
+
+    let player_count = 1 + opponent_ids.len() + opponent_emails.len();
+    if !version_type
+        .game_type
+        .player_counts
+        .contains(&(player_count as i32))
+    {
+        let mut counts = version_type.game_type.player_counts.to_owned();
+        counts.sort();
+        return Err(ControllerError::bad_request(format!(
+            "{} is for {} players, not {}",
+            version_type.game_type.name,
+            counts
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<String>>()
+                .join(", "),
+            player_count
+        )));
+    }
+    Ok(())
+}
+
+/// A game which has been started on the game server and saved.
+pub struct StartedGame {
+    pub created_game: query::CreatedGame,
+    pub created_logs: Vec<query::CreatedGameLog>,
+    pub public_render: cli::PubRender,
+    pub player_renders: Vec<cli::PlayerRender>,
+}
+
+/// Starts a new game on the game server and creates it along with its players, shared by
+/// direct creation, the lobby and matchmaking. Should be run inside a transaction.
+pub fn start_game(
+    creator_id: &Uuid,
+    game_version_id: &Uuid,
+    opponent_ids: &[Uuid],
+    opponent_emails: &[String],
+    time_control: Option<&TimeControl>,
+    conn: &PgConnection,
+) -> Result<StartedGame, Error> {
+    let player_count: usize = 1 + opponent_ids.len() + opponent_emails.len();
+    let game_version = query::find_game_version(game_version_id, conn)
+        .context("error finding game version")?
+        .ok_or_else::<Error, _>(|| format_err!("could not find game version"))?;
+
+    let resp = game_client::request(
+        &game_version.uri,
+        &cli::Request::New {
+            players: player_count,
+        },
+    )?;
+    let (game_info, logs, public_render, player_renders) = match resp {
+        cli::Response::New {
+            game,
+            logs,
+            public_render,
+            player_renders,
+        } => (game, logs, public_render, player_renders),
+        _ => bail!("expected cli::Response::New"),
+    };
+    let status = game_status_values(&game_info.status);
+    let mut created_game = query::create_game_with_users(
+        &query::CreateGameOpts {
+            new_game: &models::NewGame {
+                game_version_id: *game_version_id,
+                is_finished: status.is_finished,
+                game_state: &game_info.state,
+            },
+            whose_turn: &status.whose_turn,
+            eliminated: &status.eliminated,
+            placings: &status.placings,
+            points: &game_info.points,
+            creator_id,
+            opponent_ids,
+            opponent_emails,
+            chat_id: None,
+        },
+        conn,
+    ).context("unable to create game")?;
+    if let Some(time_control) = time_control {
+        let (game, players) =
+            query::game::update_time_control(&created_game.game.id, time_control, conn)
+                .context("unable to set game time control")?;
+        created_game.game = game;
+        created_game.players = players;
+    }
+    query::history::record(&created_game.game.id, None, HistoryAction::Start, None, conn)
+        .context("unable to record game history")?;
+    let mut created_logs = query::create_game_logs_from_cli(&created_game.game.id, logs, conn)
+        .context("unable to create game logs")?;
+    created_logs.extend(create_vacation_logs(
+        &created_game.game.id,
+        &created_game.players,
+        conn,
+    )?);
+    Ok(StartedGame {
+        created_game,
+        created_logs,
+        public_render,
+        player_renders,
+    })
+}
+
+/// Logs which players are away on vacation as a game starts, so everyone knows to expect slow
+/// turns from them.
+fn create_vacation_logs(
+    game_id: &Uuid,
+    players: &[models::GamePlayer],
+    conn: &PgConnection,
+) -> Result<Vec<query::CreatedGameLog>, Error> {
+    let now = Utc::now().naive_utc();
+    let user_ids: Vec<Uuid> = players.iter().map(|p| p.user_id).collect();
+    let vacations = query::vacation::find_current_by_users(&user_ids, now, conn)
+        .context("unable to find player vacations")?;
+    players
+        .iter()
+        .filter_map(|p| {
+            vacations
+                .iter()
+                .find(|v| v.user_id == p.user_id)
+                .map(|v| (p, v))
+        })
+        .map(|(p, v)| {
+            let text = format!(" is on vacation until {}", v.ends_at.format("%Y-%m-%d %H:%M UTC"));
+            query::create_game_log(
+                &models::NewGameLog {
+                    game_id: *game_id,
+                    body: &markup::to_string(&[
+                        markup::Node::Player(p.position as usize),
+                        markup::Node::text(text.as_str()),
+                    ]),
+                    is_public: true,
+                    logged_at: now,
+                },
+                &[],
+                conn,
+            )
+        })
+        .collect()
+}
+
+/// Sends the initial game update to all players of a new game.
+pub fn publish_started_game(
+    started: &StartedGame,
+    pub_queue_tx: &Sender<websocket::Message>,
+    conn: &PgConnection,
+) -> Result<query::GameExtended, Error> {
+    let game_extended = query::find_game_extended(&started.created_game.game.id, conn)
+        .context("unable to get extended game")?;
+    let user_ids: Vec<Uuid> = started
+        .created_game
+        .players
+        .iter()
+        .map(|p| p.user_id)
+        .collect();
+    websocket::enqueue_game_update(
+        &game_extended.clone().into_public(),
+        &started.created_logs,
+        &started.public_render,
+        &started.player_renders,
+        &query::find_valid_user_auth_tokens_for_users(&user_ids, conn)?,
+        pub_queue_tx,
+    )?;
+    Ok(game_extended)
+}
+
+/// Emails opponents who were invited by email address, including a preview of their board.
+fn send_invitation_emails(
+    inviter: &models::User,
+    game_extended: &query::GameExtended,
+    invitees: &[(String, models::User)],
+    player_renders: &[cli::PlayerRender],
+    conn: &PgConnection,
+) -> Result<(), Error> {
+    let markup_players = render::game_players_to_markup_players(&game_extended.game_players)?;
+    for &(ref email, ref opponent) in invitees {
+        let preview = match game_extended
+            .game_players
+            .iter()
+            .find(|gptu| gptu.user.id == opponent.id)
+            .and_then(|gptu| player_renders.get(gptu.game_player.position as usize))
+        {
+            Some(pr) => render::markup_html(&pr.render, &markup_players)?,
+            None => String::new(),
+        };
+        let message = EmailBuilder::new()
+            .to(email.as_ref())
+            .from(CONFIG.mail_from.as_ref())
+            .subject(&format!(
+                "{} invited you to play {}",
+                inviter.name, game_extended.game_type.name
+            ))
+            .html(&mail::html_layout(&format!(
+                "{} has invited you to play {} on brdg.me.
+
+<a href=\"{}/game/{}\">Log in to play</a> using this email address.
+
+{}",
+                inviter.name,
+                game_extended.game_type.name,
+                CONFIG.web_url,
+                game_extended.game.id,
+                preview
+            )))
+            .build()
+            .context("unable to create game invitation email")?;
+        mail::send(message, conn).context("unable to send game invitation email")?;
+    }
+    Ok(())
+}
+
 struct StatusValues {
     is_finished: bool,
     whose_turn: Vec<usize>,
@@ -164,20 +498,54 @@ pub struct ShowResponse {
     pub game_player: Option<models::PublicGamePlayer>,
     pub game_players: Vec<models::PublicGamePlayerTypeUser>,
     pub html: String,
+    /// Only included when requested with `render`, over HTTP or on a websocket render channel.
+    pub text: Option<String>,
+    pub ansi: Option<String>,
     pub game_logs: Vec<models::RenderedGameLog>,
     pub command_spec: Option<CommandSpec>,
     pub chat: Option<query::chat::PublicChatExtended>,
 }
 
-#[get("/<id>")]
+#[get("/<id>", rank = 2)]
 pub fn show(
     id: UuidParam,
-    user: Option<models::User>,
+    user: Option<ReadUser>,
 ) -> Result<CORS<Json<ShowResponse>>, ControllerError> {
     let id = id.into_uuid();
     let conn = &*CONN.r.get().context("error getting connection")?;
+    Ok(CORS(Json(show_response(&id, user.as_ref().map(|u| &u.0), &[], conn)?)))
+}
 
-    let game_extended = query::find_game_extended(&id, conn)?;
+#[derive(FromForm)]
+pub struct ShowQuery {
+    /// Extra renders to include as a comma separated list, such as `text,ansi`.
+    render: Option<String>,
+}
+
+#[get("/<id>?<query>", rank = 1)]
+pub fn show_with_query(
+    id: UuidParam,
+    query: ShowQuery,
+    user: Option<ReadUser>,
+) -> Result<CORS<Json<ShowResponse>>, ControllerError> {
+    let id = id.into_uuid();
+    let formats = match query.render {
+        Some(ref r) => render::Format::from_list(r).map_err(|_| {
+            ControllerError::bad_request("render must be a list of text or ansi")
+        })?,
+        None => vec![],
+    };
+    let conn = &*CONN.r.get().context("error getting connection")?;
+    Ok(CORS(Json(show_response(&id, user.as_ref().map(|u| &u.0), &formats, conn)?)))
+}
+
+pub fn show_response(
+    id: &Uuid,
+    user: Option<&models::User>,
+    formats: &[render::Format],
+    conn: &PgConnection,
+) -> Result<ShowResponse, ControllerError> {
+    let game_extended = query::find_game_extended(id, conn)?;
     let game_player: Option<&models::GamePlayer> = user.and_then(|u| {
         game_extended
             .game_players
@@ -185,18 +553,14 @@ pub fn show(
             .find(|&gptu| u.id == gptu.user.id)
             .map(|gptu| &gptu.game_player)
     });
-    Ok(CORS(Json(game_extended_to_show_response(
-        game_player,
-        &game_extended,
-        None,
-        conn,
-    )?)))
+    game_extended_to_show_response(game_player, &game_extended, None, formats, conn)
 }
 
 fn game_extended_to_show_response(
     game_player: Option<&models::GamePlayer>,
     game_extended: &query::GameExtended,
     render: Option<&game_client::RenderResponse>,
+    formats: &[render::Format],
     conn: &PgConnection,
 ) -> Result<ShowResponse, ControllerError> {
     let public = game_extended.clone().into_public();
@@ -212,6 +576,7 @@ fn game_extended_to_show_response(
     let (nodes, _) = markup::from_string(&render.render).context("error parsing render markup")?;
 
     let markup_players = render::game_players_to_markup_players(&game_extended.game_players)?;
+    let transformed = markup::transform(&nodes, &markup_players);
     let game_logs = match game_player {
         Some(gp) => query::find_game_logs_for_player(&gp.id, conn),
         None => query::find_public_game_logs_for_game(&game_extended.game.id, conn),
@@ -224,7 +589,17 @@ fn game_extended_to_show_response(
         game_version: public.game_version,
         game_type: public.game_type,
         game_players: public.game_players,
-        html: markup::html(&markup::transform(&nodes, &markup_players)),
+        html: markup::html(&transformed),
+        text: if formats.contains(&render::Format::Text) {
+            Some(markup::plain(&transformed))
+        } else {
+            None
+        },
+        ansi: if formats.contains(&render::Format::Ansi) {
+            Some(markup::ansi(&transformed))
+        } else {
+            None
+        },
         game_logs: game_logs
             .into_iter()
             .map(|gl| gl.into_rendered(&markup_players))
@@ -242,14 +617,37 @@ pub struct CommandRequest {
 #[post("/<id>/command", data = "<data>")]
 pub fn command(
     id: UuidParam,
-    user: models::User,
+    user: PlayUser,
     pub_queue_tx: State<Mutex<Sender<websocket::Message>>>,
     data: Json<CommandRequest>,
 ) -> Result<CORS<Json<ShowResponse>>, ControllerError> {
     let id = id.into_uuid();
     let conn = &*CONN.w.get().context("unable to get connection")?;
+    let pub_queue_tx = pub_queue_tx
+        .inner()
+        .lock()
+        .map_err::<Error, _>(|e| format_err!("unable to get lock on pub_queue_tx: {}", e))?
+        .clone();
+    Ok(CORS(Json(run_command(
+        &id,
+        &user,
+        &data.command,
+        &pub_queue_tx,
+        conn,
+    )?)))
+}
+
+/// Runs a command for a user in a game, shared by the HTTP endpoint and play-by-email.
+pub fn run_command(
+    id: &Uuid,
+    user: &models::User,
+    command: &str,
+    pub_queue_tx: &Sender<websocket::Message>,
+    conn: &PgConnection,
+) -> Result<ShowResponse, ControllerError> {
     conn.transaction::<_, ControllerError, _>(|| {
-        let (game, game_version) = query::find_game_with_version(&id, conn)
+        query::lock_game(id, conn).context("error locking game")?;
+        let (game, game_version) = query::find_game_with_version(id, conn)
             .context("error finding game")?
             .ok_or_else::<ControllerError, _>(|| {
                 ControllerError::bad_request("game does not exist")
@@ -257,15 +655,19 @@ pub fn command(
         if game.is_finished {
             return Err(ControllerError::bad_request("game is already finished"));
         }
+        check_all_accepted(id, conn)?;
 
         let players: Vec<(models::GamePlayer, models::User)> =
-            query::find_game_players_with_user_by_game(&id, conn)
+            query::find_game_players_with_user_by_game(id, conn)
                 .context("error finding game players")?;
         let player: &models::GamePlayer = &players
             .iter()
             .find(|&&(ref p, _)| p.user_id == user.id)
             .ok_or_else::<Error, _>(|| format_err!("you are not a player in this game"))?
             .0;
+        if player.resigned_at.is_some() {
+            return Err(ControllerError::bad_request("you have resigned from this game"));
+        }
         let position = player.position;
 
         let names = players
@@ -279,7 +681,7 @@ pub fn command(
                 &cli::Request::Play {
                     player: position as usize,
                     game: game.game_state.clone(),
-                    command: data.command.to_owned(),
+                    command: command.to_owned(),
                     names: names,
                 },
             )? {
@@ -312,7 +714,7 @@ pub fn command(
         let status = game_status_values(&game_response.status);
 
         let updated = query::update_game_command_success(
-            &id,
+            id,
             &player.id,
             &models::NewGame {
                 game_version_id: game.game_version_id,
@@ -330,11 +732,18 @@ pub fn command(
             &game_response.points,
             conn,
         ).context("error updating game")?;
+        query::history::record(
+            id,
+            Some(&player.id),
+            HistoryAction::Command,
+            Some(command),
+            conn,
+        ).context("unable to record game history")?;
 
-        let created_logs = query::create_game_logs_from_cli(&id, logs, conn)
+        let created_logs = query::create_game_logs_from_cli(id, logs, conn)
             .context("unable to create game logs")?;
         let game_extended =
-            query::find_game_extended(&id, conn).context("unable to get extended game")?;
+            query::find_game_extended(id, conn).context("unable to get extended game")?;
         let user_ids: Vec<Uuid> = game_extended
             .game_players
             .iter()
@@ -346,38 +755,49 @@ pub fn command(
             &public_render,
             &player_renders,
             &query::find_valid_user_auth_tokens_for_users(&user_ids, conn)?,
-            &pub_queue_tx
-                .inner()
-                .lock()
-                .map_err::<Error, _>(|e| format_err!("unable to get lock on pub_queue_tx: {}", e))?
-                .clone(),
+            pub_queue_tx,
         )?;
         let gp = game_extended
             .game_players
             .iter()
             .find(|gptu| gptu.game_player.id == player.id)
             .map(|gptu| &gptu.game_player);
-        Ok(CORS(Json(game_extended_to_show_response(
+        game_extended_to_show_response(
             gp,
             &game_extended,
             gp.and_then(|gp| player_renders.get(gp.position as usize))
                 .map(|render| render.clone().into())
                 .as_ref(),
+            &[],
             conn,
-        )?)))
+        )
     })
 }
 
+/// Games can't be played until every player has accepted, unless the server allows it.
+fn check_all_accepted(game_id: &Uuid, conn: &PgConnection) -> Result<(), ControllerError> {
+    if CONFIG.require_accept
+        && !query::game::all_players_accepted(game_id, conn)
+            .context("error checking whether players have accepted")?
+    {
+        return Err(ControllerError::bad_request(
+            "waiting for all players to accept the game",
+        ));
+    }
+    Ok(())
+}
+
 #[post("/<id>/undo")]
 pub fn undo(
     id: UuidParam,
-    user: models::User,
+    user: PlayUser,
     pub_queue_tx: State<Mutex<Sender<websocket::Message>>>,
 ) -> Result<CORS<Json<ShowResponse>>, ControllerError> {
     let id = id.into_uuid();
     let conn = &*CONN.w.get().context("unable to get connection")?;
 
     conn.transaction::<_, ControllerError, _>(|| {
+        query::lock_game(&id, conn).context("error locking game")?;
         let (game, game_version) = query::find_game_with_version(&id, conn)
             .context("error finding game")?
             .ok_or_else::<ControllerError, _>(|| {
@@ -386,6 +806,7 @@ pub fn undo(
         if game.is_finished {
             return Err(ControllerError::bad_request("game is already finished"));
         }
+        check_all_accepted(&id, conn)?;
 
         let player = query::find_game_player_by_user_and_game(&user.id, &id, conn)
             .context("error finding game player")?
@@ -431,6 +852,8 @@ pub fn undo(
         ).context("error updating game")?;
         query::player_cannot_undo_set_undo_game_state(&id, conn)
             .context("unable to clear undo_game_state for all players")?;
+        query::history::record(&id, Some(&player.id), HistoryAction::Undo, None, conn)
+            .context("unable to record game history")?;
         let created_log = query::create_game_log(
             &models::NewGameLog {
                 game_id: id,
@@ -474,6 +897,7 @@ pub fn undo(
             gp.and_then(|gp| player_renders.get(gp.position as usize))
                 .map(|render| render.clone().into())
                 .as_ref(),
+            &[],
             conn,
         )?)))
     })
@@ -482,7 +906,7 @@ pub fn undo(
 #[post("/<id>/mark_read")]
 pub fn mark_read(
     id: UuidParam,
-    user: models::User,
+    user: PlayUser,
 ) -> Result<CORS<Json<Option<models::PublicGamePlayer>>>, ControllerError> {
     let id = id.into_uuid();
     let conn = &*CONN.w.get().context("unable to get connection")?;
@@ -496,16 +920,36 @@ pub fn mark_read(
     })
 }
 
-#[post("/<id>/concede")]
-pub fn concede(
+pub fn status_renders(
+    uri: &str,
+    game_state: &str,
+) -> Result<(cli::PubRender, Vec<cli::PlayerRender>), Error> {
+    match game_client::request(
+        uri,
+        &cli::Request::Status {
+            game: game_state.to_owned(),
+        },
+    )? {
+        cli::Response::Status {
+            public_render,
+            player_renders,
+            ..
+        } => Ok((public_render, player_renders)),
+        _ => bail!("invalid response type"),
+    }
+}
+
+#[post("/<id>/accept")]
+pub fn accept(
     id: UuidParam,
-    user: models::User,
+    user: PlayUser,
     pub_queue_tx: State<Mutex<Sender<websocket::Message>>>,
 ) -> Result<CORS<Json<ShowResponse>>, ControllerError> {
     let id = id.into_uuid();
     let conn = &*CONN.w.get().context("unable to get connection")?;
 
-    Ok(conn.transaction::<_, ControllerError, _>(|| {
+    conn.transaction::<_, ControllerError, _>(|| {
+        query::lock_game(&id, conn).context("error locking game")?;
         let (game, game_version) = query::find_game_with_version(&id, conn)
             .context("error finding game")?
             .ok_or_else::<ControllerError, _>(|| {
@@ -515,48 +959,128 @@ pub fn concede(
             return Err(ControllerError::bad_request("game is already finished"));
         }
 
-        let player_count = query::find_player_count_by_game(&id, conn)
-            .context("error finding player count for game")?;
-        if player_count > 2 {
+        let player = query::find_game_player_by_user_and_game(&user.id, &id, conn)
+            .context("error finding game player")?
+            .ok_or_else::<ControllerError, _>(|| {
+                ControllerError::bad_request("you aren't a player in this game")
+            })?;
+        if player.has_accepted {
             return Err(ControllerError::bad_request(
-                "cannot concede games with more than two players",
+                "you have already accepted this game",
             ));
         }
+        query::game::update_has_accepted(&player.id, true, conn)
+            .context("error accepting game")?;
+        if CONFIG.require_accept
+            && query::game::all_players_accepted(&id, conn)
+                .context("error checking whether players have accepted")?
+        {
+            // Nobody could play until now, so the turn clock starts from here.
+            query::game::start_turn_clocks(&id, Utc::now().naive_utc(), conn)
+                .context("error starting turn clocks")?;
+        }
+
+        let (public_render, player_renders) = status_renders(&game_version.uri, &game.game_state)?;
+        let game_extended =
+            query::find_game_extended(&id, conn).context("unable to get extended game")?;
+        let user_ids: Vec<Uuid> = game_extended
+            .game_players
+            .iter()
+            .map(|gptu| gptu.user.id)
+            .collect();
+        let tokens = query::find_valid_user_auth_tokens_for_users(&user_ids, conn)?;
+        let tx = pub_queue_tx
+            .inner()
+            .lock()
+            .map_err::<Error, _>(|e| format_err!("unable to get lock on pub_queue_tx: {}", e))?
+            .clone();
+        websocket::enqueue_game_update(
+            &game_extended.clone().into_public(),
+            &[],
+            &public_render,
+            &player_renders,
+            &tokens,
+            &tx,
+        )?;
+        websocket::enqueue_game_invitation_response(
+            &id,
+            &user.clone().into_public(),
+            true,
+            &tokens,
+            &tx,
+        )?;
+        let gp = game_extended
+            .game_players
+            .iter()
+            .find(|gptu| gptu.game_player.id == player.id)
+            .map(|gptu| &gptu.game_player);
+        Ok(CORS(Json(game_extended_to_show_response(
+            gp,
+            &game_extended,
+            gp.and_then(|gp| player_renders.get(gp.position as usize))
+                .map(|render| render.clone().into())
+                .as_ref(),
+            &[],
+            conn,
+        )?)))
+    })
+}
+
+/// Declining an invitation cancels the game for everyone.
+#[post("/<id>/decline")]
+pub fn decline(
+    id: UuidParam,
+    user: PlayUser,
+    pub_queue_tx: State<Mutex<Sender<websocket::Message>>>,
+) -> Result<CORS<Json<ShowResponse>>, ControllerError> {
+    let id = id.into_uuid();
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+
+    conn.transaction::<_, ControllerError, _>(|| {
+        query::lock_game(&id, conn).context("error locking game")?;
+        let (game, game_version) = query::find_game_with_version(&id, conn)
+            .context("error finding game")?
+            .ok_or_else::<ControllerError, _>(|| {
+                ControllerError::bad_request("game does not exist")
+            })?;
+        if game.is_finished {
+            return Err(ControllerError::bad_request("game is already finished"));
+        }
 
         let player = query::find_game_player_by_user_and_game(&user.id, &id, conn)
             .context("error finding game player")?
             .ok_or_else::<ControllerError, _>(|| {
                 ControllerError::bad_request("you aren't a player in this game")
             })?;
+        if player.has_accepted {
+            return Err(ControllerError::bad_request(
+                "you have already accepted this game",
+            ));
+        }
+        // Players may have started without waiting for everyone to accept.
+        if query::history::has_moved_past_start(&id, conn)
+            .context("error checking whether the game has started")?
+        {
+            return Err(ControllerError::bad_request(
+                "the game has already started, concede instead",
+            ));
+        }
+        query::game::cancel(&id, conn).context("error cancelling game")?;
 
-        let updated = query::concede_game(&id, &player.id, conn).context("error conceding game")?;
-
-        let (public_render, player_renders) = match game_client::request(
-            &game_version.uri,
-            &cli::Request::Status {
-                game: game.game_state.clone(),
-            },
-        )? {
-            cli::Response::Status {
-                public_render,
-                player_renders,
-                ..
-            } => (public_render, player_renders),
-            _ => return Err(format_err!("invalid response type").into()),
-        };
+        let (public_render, player_renders) = status_renders(&game_version.uri, &game.game_state)?;
         let created_log = query::create_game_log(
             &models::NewGameLog {
                 game_id: id,
                 body: &markup::to_string(&[
                     markup::Node::Player(player.position as usize),
-                    markup::Node::text(" conceded"),
+                    markup::Node::text(" declined the game, it has been cancelled"),
                 ]),
                 is_public: true,
                 logged_at: Utc::now().naive_utc(),
             },
             &[],
             conn,
-        ).context("unable to create concede game log")?;
+        ).context("unable to create decline game log")?;
         let game_extended =
             query::find_game_extended(&id, conn).context("unable to get extended game")?;
         let user_ids: Vec<Uuid> = game_extended
@@ -564,17 +1088,26 @@ pub fn concede(
             .iter()
             .map(|gptu| gptu.user.id)
             .collect();
+        let tokens = query::find_valid_user_auth_tokens_for_users(&user_ids, conn)?;
+        let tx = pub_queue_tx
+            .inner()
+            .lock()
+            .map_err::<Error, _>(|e| format_err!("unable to get lock on pub_queue_tx: {}", e))?
+            .clone();
         websocket::enqueue_game_update(
             &game_extended.clone().into_public(),
             &[created_log],
             &public_render,
             &player_renders,
-            &query::find_valid_user_auth_tokens_for_users(&user_ids, conn)?,
-            &pub_queue_tx
-                .inner()
-                .lock()
-                .map_err::<Error, _>(|e| format_err!("unable to get lock on pub_queue_tx: {}", e))?
-                .clone(),
+            &tokens,
+            &tx,
+        )?;
+        websocket::enqueue_game_invitation_response(
+            &id,
+            &user.clone().into_public(),
+            false,
+            &tokens,
+            &tx,
         )?;
         let gp = game_extended
             .game_players
@@ -587,6 +1120,130 @@ pub fn concede(
             gp.and_then(|gp| player_renders.get(gp.position as usize))
                 .map(|render| render.clone().into())
                 .as_ref(),
+            &[],
+            conn,
+        )?)))
+    })
+}
+
+/// Takes a player out of a game, either by conceding the whole game or by resigning them and
+/// leaving the others to play on, then logs it and publishes the update. Should be run inside a
+/// transaction.
+pub fn concede_or_resign(
+    game: &models::Game,
+    game_version: &models::GameVersion,
+    player: &models::GamePlayer,
+    resign: bool,
+    log_text: &str,
+    pub_queue_tx: &Sender<websocket::Message>,
+    conn: &PgConnection,
+) -> Result<(query::GameExtended, Vec<cli::PlayerRender>), Error> {
+    let created_logs = vec![query::create_game_log(
+        &models::NewGameLog {
+            game_id: game.id,
+            body: &markup::to_string(&[
+                markup::Node::Player(player.position as usize),
+                markup::Node::text(log_text),
+            ]),
+            is_public: true,
+            logged_at: Utc::now().naive_utc(),
+        },
+        &[],
+        conn,
+    ).context("unable to create concede game log")?];
+    let action = if resign {
+        query::resign_player(&game.id, &player.id, conn).context("error resigning player")?;
+        HistoryAction::Resign
+    } else {
+        query::concede_game(&game.id, &player.id, conn).context("error conceding game")?;
+        HistoryAction::Concede
+    };
+    let (public_render, player_renders) = status_renders(&game_version.uri, &game.game_state)?;
+    query::history::record(&game.id, Some(&player.id), action, None, conn)
+        .context("unable to record game history")?;
+
+    let game_extended =
+        query::find_game_extended(&game.id, conn).context("unable to get extended game")?;
+    let user_ids: Vec<Uuid> = game_extended
+        .game_players
+        .iter()
+        .map(|gptu| gptu.user.id)
+        .collect();
+    websocket::enqueue_game_update(
+        &game_extended.clone().into_public(),
+        &created_logs,
+        &public_render,
+        &player_renders,
+        &query::find_valid_user_auth_tokens_for_users(&user_ids, conn)?,
+        pub_queue_tx,
+    )?;
+    Ok((game_extended, player_renders))
+}
+
+/// Concedes the game. With more than two players still playing, the player is eliminated and
+/// the rest play on while one of them has the turn, otherwise the game ends with the player
+/// coming last.
+#[post("/<id>/concede")]
+pub fn concede(
+    id: UuidParam,
+    user: PlayUser,
+    pub_queue_tx: State<Mutex<Sender<websocket::Message>>>,
+) -> Result<CORS<Json<ShowResponse>>, ControllerError> {
+    let id = id.into_uuid();
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+
+    Ok(conn.transaction::<_, ControllerError, _>(|| {
+        query::lock_game(&id, conn).context("error locking game")?;
+        let (game, game_version) = query::find_game_with_version(&id, conn)
+            .context("error finding game")?
+            .ok_or_else::<ControllerError, _>(|| {
+                ControllerError::bad_request("game does not exist")
+            })?;
+        if game.is_finished {
+            return Err(ControllerError::bad_request("game is already finished"));
+        }
+        check_all_accepted(&id, conn)?;
+
+        let player = query::find_game_player_by_user_and_game(&user.id, &id, conn)
+            .context("error finding game player")?
+            .ok_or_else::<ControllerError, _>(|| {
+                ControllerError::bad_request("you aren't a player in this game")
+            })?;
+        if player.resigned_at.is_some() {
+            return Err(ControllerError::bad_request("you have resigned from this game"));
+        }
+        let playing = query::find_game_players_by_game(&id, conn)
+            .context("error finding game players")?
+            .iter()
+            .filter(|gp| gp.resigned_at.is_none())
+            .count();
+
+        let tx = pub_queue_tx
+            .inner()
+            .lock()
+            .map_err::<Error, _>(|e| format_err!("unable to get lock on pub_queue_tx: {}", e))?
+            .clone();
+        let (game_extended, player_renders) = concede_or_resign(
+            &game,
+            &game_version,
+            &player,
+            playing > 2,
+            " conceded",
+            &tx,
+            conn,
+        )?;
+        let gp = game_extended
+            .game_players
+            .iter()
+            .find(|gptu| gptu.game_player.id == player.id)
+            .map(|gptu| &gptu.game_player);
+        Ok(CORS(Json(game_extended_to_show_response(
+            gp,
+            &game_extended,
+            gp.and_then(|gp| player_renders.get(gp.position as usize))
+                .map(|render| render.clone().into())
+                .as_ref(),
+            &[],
             conn,
         )?)))
     })?)
@@ -595,7 +1252,7 @@ pub fn concede(
 #[post("/<id>/restart")]
 pub fn restart(
     id: UuidParam,
-    user: models::User,
+    user: PlayUser,
     pub_queue_tx: State<Mutex<Sender<websocket::Message>>>,
 ) -> Result<CORS<Json<ShowResponse>>, ControllerError> {
     let id = id.into_uuid();
@@ -643,7 +1300,7 @@ pub fn restart(
                 _ => bail!("expected cli::Response::New"),
             };
             let status = game_status_values(&game_info.status);
-            let created_game = query::create_game_with_users(
+            let mut created_game = query::create_game_with_users(
                 &query::CreateGameOpts {
                     new_game: &models::NewGame {
                         game_version_id: game_extended.game_version.id,
@@ -661,6 +1318,16 @@ pub fn restart(
                 },
                 conn,
             ).context("unable to create game")?;
+            // Restarted games keep the same time control.
+            if let Some(time_control) = TimeControl::from_game(&game_extended.game)? {
+                let (game, players) =
+                    query::game::update_time_control(&created_game.game.id, &time_control, conn)
+                        .context("unable to set game time control")?;
+                created_game.game = game;
+                created_game.players = players;
+            }
+            query::history::record(&created_game.game.id, None, HistoryAction::Start, None, conn)
+                .context("unable to record game history")?;
             let created_logs = query::create_game_logs_from_cli(&created_game.game.id, logs, conn)
                 .context("unable to create game logs")?;
             query::game::update_restarted_game_id(
@@ -703,6 +1370,7 @@ pub fn restart(
             .and_then(|p| player_renders.get(p.position as usize))
             .map(|render| render.clone().into())
             .as_ref(),
+        &[],
         conn,
     )?)))
 }
@@ -0,0 +1,263 @@
+use rocket::State;
+use rocket_contrib::Json;
+use diesel::Connection;
+use diesel::pg::PgConnection;
+use chrono::Utc;
+use uuid::Uuid;
+use failure::{Error, ResultExt};
+
+use std::sync::Mutex;
+use std::sync::mpsc::Sender;
+
+use db::{models, query, CONN};
+use db::query::lobby::{OpenGameExtended, PublicOpenGameExtended};
+use controller::{UuidParam, CORS};
+use controller::auth::PlayUser;
+use controller::game::{publish_started_game, start_game, StartedGame};
+use errors::ControllerError;
+use websocket;
+
+#[get("/")]
+pub fn index() -> Result<CORS<Json<Vec<PublicOpenGameExtended>>>, ControllerError> {
+    let conn = &*CONN.r.get().context("unable to get connection")?;
+    Ok(CORS(Json(query::lobby::find_open(conn)
+        .context("error finding open games")?
+        .into_iter()
+        .map(|og| og.into_public())
+        .collect())))
+}
+
+#[derive(Deserialize)]
+pub struct CreateRequest {
+    game_version_id: Uuid,
+    player_count: i32,
+}
+
+/// Creates an open game with the creator seated, which other players can join
+/// until every seat is filled.
+#[post("/", data = "<data>")]
+pub fn create(
+    data: Json<CreateRequest>,
+    user: PlayUser,
+) -> Result<CORS<Json<PublicOpenGameExtended>>, ControllerError> {
+    let data = data.into_inner();
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+
+    conn.transaction::<_, ControllerError, _>(|| {
+        ensure_not_on_vacation(&user.id, conn)?;
+        let version_type = query::find_game_version_type(&data.game_version_id, conn)
+            .context("error finding game version")?
+            .ok_or_else(|| ControllerError::bad_request("could not find game version"))?;
+        if !version_type.game_version.is_public || version_type.game_version.is_deprecated {
+            return Err(ControllerError::bad_request(
+                "this game version is not available for new games",
+            ));
+        }
+        if !version_type
+            .game_type
+            .player_counts
+            .contains(&data.player_count)
+        {
+            return Err(ControllerError::bad_request(format!(
+                "{} can't be played with {} players",
+                version_type.game_type.name, data.player_count
+            )));
+        }
+        let open_game = query::lobby::create(
+            &data.game_version_id,
+            &user.id,
+            data.player_count,
+            conn,
+        ).context("error creating open game")?;
+        Ok(CORS(Json(find_extended(&open_game.id, conn)?.into_public())))
+    })
+}
+
+fn find_extended(id: &Uuid, conn: &PgConnection) -> Result<OpenGameExtended, ControllerError> {
+    query::lobby::find_extended(id, conn)
+        .context("error finding open game")?
+        .ok_or_else(|| ControllerError::bad_request("open game does not exist"))
+}
+
+/// Finds the open game after locking it, so seats can be checked and taken without racing
+/// other players. Should be run inside a transaction.
+fn lock_extended(id: &Uuid, conn: &PgConnection) -> Result<OpenGameExtended, ControllerError> {
+    query::lobby::lock(id, conn)
+        .context("error locking open game")?
+        .ok_or_else(|| ControllerError::bad_request("open game does not exist"))?;
+    find_extended(id, conn)
+}
+
+/// Players on vacation can't take a seat, as they'd hold up everyone else.
+fn ensure_not_on_vacation(user_id: &Uuid, conn: &PgConnection) -> Result<(), ControllerError> {
+    if query::vacation::find_current(user_id, Utc::now().naive_utc(), conn)
+        .context("error finding vacation")?
+        .is_some()
+    {
+        return Err(ControllerError::bad_request(
+            "you can't join games while you're on vacation",
+        ));
+    }
+    Ok(())
+}
+
+fn ensure_open(open_game: &OpenGameExtended) -> Result<(), ControllerError> {
+    if open_game.open_game.game_id.is_some() {
+        return Err(ControllerError::bad_request("this game has already started"));
+    }
+    if open_game.open_game.cancelled_at.is_some() {
+        return Err(ControllerError::bad_request("this game has been cancelled"));
+    }
+    Ok(())
+}
+
+/// Starts the game with the seated players, who have all implicitly accepted by
+/// joining. Should be run inside a transaction.
+fn start_open_game(
+    open_game: &OpenGameExtended,
+    conn: &PgConnection,
+) -> Result<StartedGame, Error> {
+    let creator_id = open_game.open_game.creator_id;
+    let opponent_ids: Vec<Uuid> = open_game
+        .players
+        .iter()
+        .map(|u| u.id)
+        .filter(|id| *id != creator_id)
+        .collect();
+    let started = start_game(
+        &creator_id,
+        &open_game.open_game.game_version_id,
+        &opponent_ids,
+        &[],
+        None,
+        conn,
+    )?;
+    if query::lobby::mark_started(&open_game.open_game.id, &started.created_game.game.id, conn)?
+        .is_none()
+    {
+        bail!("open game has already started");
+    }
+    for player in &started.created_game.players {
+        query::game::update_has_accepted(&player.id, true, conn)?;
+    }
+    Ok(started)
+}
+
+/// Publishes a game started from the lobby, once the transaction starting it has committed.
+fn publish(
+    started: &StartedGame,
+    pub_queue_tx: &State<Mutex<Sender<websocket::Message>>>,
+    conn: &PgConnection,
+) -> Result<(), ControllerError> {
+    publish_started_game(
+        started,
+        &pub_queue_tx
+            .inner()
+            .lock()
+            .map_err::<Error, _>(|e| format_err!("unable to get lock on pub_queue_tx: {}", e))?
+            .clone(),
+        conn,
+    )?;
+    Ok(())
+}
+
+/// Takes a seat in an open game, starting it once every seat is filled.
+#[post("/<id>/join")]
+pub fn join(
+    id: UuidParam,
+    user: PlayUser,
+    pub_queue_tx: State<Mutex<Sender<websocket::Message>>>,
+) -> Result<CORS<Json<PublicOpenGameExtended>>, ControllerError> {
+    let id = id.into_uuid();
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+
+    let started = conn.transaction::<_, ControllerError, _>(|| {
+        let open_game = lock_extended(&id, conn)?;
+        ensure_open(&open_game)?;
+        ensure_not_on_vacation(&user.id, conn)?;
+        if open_game.players.iter().any(|u| u.id == user.id) {
+            return Err(ControllerError::bad_request("you have already joined this game"));
+        }
+        if open_game.players.len() as i32 >= open_game.open_game.player_count {
+            return Err(ControllerError::bad_request("this game is already full"));
+        }
+        for player in &open_game.players {
+            if query::block::is_blocked_either_way(&user.id, &player.id, conn)
+                .context("error checking blocked users")?
+            {
+                return Err(ControllerError::bad_request("you can't join this game"));
+            }
+        }
+        query::lobby::join(&id, &user.id, conn).context("error joining open game")?;
+        let open_game = find_extended(&id, conn)?;
+        if open_game.players.len() as i32 == open_game.open_game.player_count {
+            return Ok(Some(start_open_game(&open_game, conn)
+                .context("error starting open game")?));
+        }
+        Ok(None)
+    })?;
+    if let Some(started) = started {
+        publish(&started, &pub_queue_tx, conn)?;
+    }
+    Ok(CORS(Json(find_extended(&id, conn)?.into_public())))
+}
+
+/// Gives up a seat in an open game. If the creator leaves, the open game is
+/// cancelled.
+#[post("/<id>/leave")]
+pub fn leave(
+    id: UuidParam,
+    user: PlayUser,
+) -> Result<CORS<Json<PublicOpenGameExtended>>, ControllerError> {
+    let id = id.into_uuid();
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+
+    conn.transaction::<_, ControllerError, _>(|| {
+        let open_game = lock_extended(&id, conn)?;
+        ensure_open(&open_game)?;
+        if open_game.open_game.creator_id == user.id {
+            query::lobby::cancel(&id, conn).context("error cancelling open game")?;
+        } else {
+            query::lobby::leave(&id, &user.id, conn)
+                .context("error leaving open game")?
+                .ok_or_else(|| ControllerError::bad_request("you haven't joined this game"))?;
+        }
+        Ok(CORS(Json(find_extended(&id, conn)?.into_public())))
+    })
+}
+
+/// Starts an open game early with the players seated so far, as long as the
+/// game supports that many players.
+#[post("/<id>/start")]
+pub fn start(
+    id: UuidParam,
+    user: PlayUser,
+    pub_queue_tx: State<Mutex<Sender<websocket::Message>>>,
+) -> Result<CORS<Json<PublicOpenGameExtended>>, ControllerError> {
+    let id = id.into_uuid();
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+
+    let started = conn.transaction::<_, ControllerError, _>(|| {
+        let open_game = lock_extended(&id, conn)?;
+        ensure_open(&open_game)?;
+        if open_game.open_game.creator_id != user.id {
+            return Err(ControllerError::bad_request(
+                "only the creator can start this game",
+            ));
+        }
+        if !open_game
+            .game_type
+            .player_counts
+            .contains(&(open_game.players.len() as i32))
+        {
+            return Err(ControllerError::bad_request(format!(
+                "{} can't be played with {} players",
+                open_game.game_type.name,
+                open_game.players.len()
+            )));
+        }
+        Ok(start_open_game(&open_game, conn).context("error starting open game")?)
+    })?;
+    publish(&started, &pub_queue_tx, conn)?;
+    Ok(CORS(Json(find_extended(&id, conn)?.into_public())))
+}
@@ -1,13 +1,127 @@
-use mail::handle_inbound_email;
-use rocket::Data;
-use failure::Error;
+use rocket::{Data, State};
+use lettre::email::EmailBuilder;
+use failure::{Error, ResultExt};
+
+use diesel::pg::PgConnection;
 
 use std::io::Read;
+use std::sync::Mutex;
+use std::sync::mpsc::Sender;
+
+use config::CONFIG;
+use controller::game::{run_command, show_response, ShowResponse};
+use db::{models, query, CONN};
+use errors::ControllerError;
+use mail;
+use websocket;
 
+/// Receives raw inbound email, running any commands in replies to turn emails.
 #[post("/", data = "<data>")]
-pub fn index(data: Data) -> Result<(), Error> {
+pub fn index(
+    data: Data,
+    pub_queue_tx: State<Mutex<Sender<websocket::Message>>>,
+) -> Result<(), Error> {
     let mut buffer = String::new();
     data.open().read_to_string(&mut buffer)?;
-    handle_inbound_email(&buffer);
+    let inbound = mail::parse_inbound_email(&buffer)?;
+
+    let token = match mail::reply_token(&inbound.to) {
+        Some(t) => t,
+        None => {
+            warn!("inbound email without a reply token: {:?}", inbound.to);
+            return Ok(());
+        }
+    };
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+    let game_player = match query::game::find_player_by_email_token(&token, conn)? {
+        Some(gp) => gp,
+        None => {
+            warn!("inbound email with unknown reply token: {}", token);
+            return Ok(());
+        }
+    };
+    let from = match inbound.from {
+        Some(ref f) => f,
+        None => return Ok(()),
+    };
+    // The sender must be one of the player's own addresses.
+    let user = match query::find_user_by_email(from, conn)? {
+        Some((_, ref u)) if u.id == game_player.user_id => u.clone(),
+        _ => {
+            warn!("inbound email for game player {} from {}", game_player.id, from);
+            return Ok(());
+        }
+    };
+
+    let pub_queue_tx = pub_queue_tx
+        .inner()
+        .lock()
+        .map_err::<Error, _>(|e| format_err!("unable to get lock on pub_queue_tx: {}", e))?
+        .clone();
+    let commands = mail::strip_quoted(&inbound.body);
+    let (error, show) = match run_commands(&game_player, &user, &commands, &pub_queue_tx, conn) {
+        Ok(show) => (None, show),
+        Err(ControllerError::BadRequest { message }) => {
+            (Some(message), show_response(&game_player.game_id, Some(&user), &[], conn)?)
+        }
+        Err(e) => {
+            warn!("error running emailed command for {}: {}", game_player.id, e);
+            (
+                Some("there was an error running your command, please try again".to_string()),
+                show_response(&game_player.game_id, Some(&user), &[], conn)?,
+            )
+        }
+    };
+    send_reply(from, &game_player, error, &show, conn)
+}
+
+fn run_commands(
+    game_player: &models::GamePlayer,
+    user: &models::User,
+    commands: &[String],
+    pub_queue_tx: &Sender<websocket::Message>,
+    conn: &PgConnection,
+) -> Result<ShowResponse, ControllerError> {
+    // Each line is run as its own command, stopping at the first error.
+    let mut show = None;
+    for command in commands {
+        show = Some(run_command(
+            &game_player.game_id,
+            user,
+            command,
+            pub_queue_tx,
+            conn,
+        )?);
+    }
+    show.ok_or_else(|| ControllerError::bad_request("no command found in your email"))
+}
+
+fn send_reply(
+    to: &str,
+    game_player: &models::GamePlayer,
+    error: Option<String>,
+    show: &ShowResponse,
+    conn: &PgConnection,
+) -> Result<(), Error> {
+    let status = match error {
+        Some(ref e) => format!("<b>Error:</b> {}", e),
+        None => "Your command was accepted.".to_string(),
+    };
+    let message = EmailBuilder::new()
+        .to(to)
+        .from(CONFIG.mail_from.as_ref())
+        .reply_to(mail::reply_address(&game_player.email_token).as_ref())
+        .subject(&format!("Re: {}", show.game_type.name))
+        .html(&mail::html_layout(&format!(
+            "{}
+
+{}
+
+<a href=\"{}/game/{}\">Play on brdg.me</a>",
+            status, show.html, CONFIG.web_url, show.game.id
+        )))
+        .build()
+        .context("unable to create play by email reply")?;
+    mail::send(message, conn).context("unable to send play by email reply")?;
     Ok(())
 }
@@ -0,0 +1,90 @@
+use rocket_contrib::Json;
+use diesel::Connection;
+use uuid::Uuid;
+use failure::ResultExt;
+
+use db::{models, query, CONN};
+use controller::{UuidParam, CORS};
+use controller::auth::{PlayUser, ReadUser};
+use errors::ControllerError;
+
+/// The queues the user is currently waiting in.
+#[get("/")]
+pub fn index(
+    user: ReadUser,
+) -> Result<CORS<Json<Vec<models::PublicMatchmakingEntry>>>, ControllerError> {
+    let conn = &*CONN.r.get().context("unable to get connection")?;
+    Ok(CORS(Json(query::matchmaking::find_queued_for_user(&user.id, conn)
+        .context("error finding matchmaking entries")?)))
+}
+
+#[derive(Deserialize)]
+pub struct CreateRequest {
+    game_type_id: Uuid,
+    player_count: i32,
+}
+
+/// Joins the matchmaking queue for a game type. Players are matched with
+/// others of a similar rating in the background, and are notified on their
+/// user channels once a game has been created.
+#[post("/", data = "<data>")]
+pub fn create(
+    data: Json<CreateRequest>,
+    user: PlayUser,
+) -> Result<CORS<Json<models::PublicMatchmakingEntry>>, ControllerError> {
+    let data = data.into_inner();
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+
+    conn.transaction::<_, ControllerError, _>(|| {
+        let game_type = query::find_game_type(&data.game_type_id, conn)
+            .context("error finding game type")?
+            .ok_or_else(|| ControllerError::bad_request("could not find game type"))?;
+        if !game_type.player_counts.contains(&data.player_count) {
+            return Err(ControllerError::bad_request(format!(
+                "{} can't be played with {} players",
+                game_type.name, data.player_count
+            )));
+        }
+        if query::find_latest_public_game_version(&game_type.id, conn)
+            .context("error finding game version")?
+            .is_none()
+        {
+            return Err(ControllerError::bad_request(format!(
+                "{} is not available for new games",
+                game_type.name
+            )));
+        }
+        if query::matchmaking::find_queued_for_user(&user.id, conn)
+            .context("error finding matchmaking entries")?
+            .iter()
+            .any(|e| e.game_type_id == game_type.id)
+        {
+            return Err(ControllerError::bad_request(format!(
+                "you are already queued for {}",
+                game_type.name
+            )));
+        }
+        // The queued check above can race with a double submit, which the unique index catches.
+        Ok(CORS(Json(query::matchmaking::create(
+            &user.id,
+            &game_type.id,
+            data.player_count,
+            conn,
+        ).context("error joining matchmaking queue")?
+            .ok_or_else(|| {
+                ControllerError::bad_request(format!(
+                    "you are already queued for {}",
+                    game_type.name
+                ))
+            })?)))
+    })
+}
+
+#[delete("/<id>")]
+pub fn remove(id: UuidParam, user: PlayUser) -> Result<CORS<()>, ControllerError> {
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+    query::matchmaking::remove(&user.id, &id.into_uuid(), conn)
+        .context("error leaving matchmaking queue")?
+        .ok_or_else(|| ControllerError::bad_request("you are not in this queue"))?;
+    Ok(CORS(()))
+}
@@ -1,4 +1,5 @@
-use rocket::request::{FromParam, Request};
+use rocket::request::{self, FromParam, FromRequest, Request};
+use rocket::Outcome;
 use rocket::response::{self, Responder};
 use rocket::http::RawStr;
 use rocket::http::hyper::header::{AccessControlAllowCredentials, AccessControlAllowHeaders,
@@ -9,14 +10,24 @@ use uuid::Uuid;
 use unicase::UniCase;
 use failure::{Error, ResultExt};
 
+use std::net::IpAddr;
 use std::str::FromStr;
 use std::path::PathBuf;
 
+pub mod admin;
 pub mod auth;
+pub mod chat;
+pub mod friend;
 pub mod game;
+pub mod lobby;
 pub mod mail;
+pub mod matchmaking;
+pub mod proposal;
+pub mod user;
 
+use config::CONFIG;
 use db::{models, query, CONN};
+use controller::auth::ReadUser;
 
 pub struct UuidParam(Uuid);
 
@@ -35,6 +46,45 @@ impl<'a> FromParam<'a> for UuidParam {
     }
 }
 
+/// The IP address of the client, taken from `X-Forwarded-For` when `TRUST_PROXY` is set.
+///
+/// Each proxy appends the address it received the request from, so the header is read from the
+/// right, skipping `TRUSTED_PROXIES`. Anything further left could have been sent by the client.
+pub struct ClientIp(Option<String>);
+
+impl ClientIp {
+    pub fn addr(&self) -> Option<&str> {
+        self.0.as_ref().map(|ip| ip.as_str())
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientIp {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        if CONFIG.trust_proxy {
+            if let Some(forwarded) = request.headers().get_one("X-Forwarded-For") {
+                if let Some(ip) = client_ip_from_forwarded(forwarded, &CONFIG.trusted_proxies) {
+                    return Outcome::Success(ClientIp(Some(ip.to_string())));
+                }
+            }
+        }
+        Outcome::Success(ClientIp(request.remote().map(|addr| addr.ip().to_string())))
+    }
+}
+
+fn client_ip_from_forwarded<'a>(forwarded: &'a str, trusted_proxies: &[IpAddr]) -> Option<&'a str> {
+    let mut client = None;
+    for ip in forwarded.rsplit(',').map(|ip| ip.trim()).filter(|ip| !ip.is_empty()) {
+        client = Some(ip);
+        match IpAddr::from_str(ip) {
+            Ok(addr) if trusted_proxies.contains(&addr) => continue,
+            _ => break,
+        }
+    }
+    client
+}
+
 pub struct CORS<R>(R);
 
 impl<'r, R: Responder<'r>> Responder<'r> for CORS<R> {
@@ -67,10 +117,12 @@ pub struct InitResponse {
     pub game_version_types: Vec<models::PublicGameVersionType>,
     pub games: Vec<query::PublicGameExtended>,
     pub user: Option<models::PublicUser>,
+    pub chat_unread_count: i64,
 }
 
 #[get("/init")]
-pub fn init(user: Option<models::User>) -> Result<CORS<Json<InitResponse>>, Error> {
+pub fn init(user: Option<ReadUser>) -> Result<CORS<Json<InitResponse>>, Error> {
+    let user = user.map(|u| u.0);
     let conn = &*CONN.r.get().context("unable to get connection")?;
 
     Ok(CORS(Json(InitResponse {
@@ -92,6 +144,31 @@ pub fn init(user: Option<models::User>) -> Result<CORS<Json<InitResponse>>, Erro
                     .collect()
             })
             .unwrap_or_else(|| vec![]),
+        chat_unread_count: match user {
+            Some(ref u) => query::chat::count_unread_for_user(&u.id, conn)
+                .context("unable to count unread chat messages")?,
+            None => 0,
+        },
         user: user.map(|u| u.into_public()),
     })))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_ip_from_forwarded_skips_trusted_proxies() {
+        let trusted = vec![IpAddr::from_str("10.0.0.1").unwrap()];
+        assert_eq!(
+            Some("2.2.2.2"),
+            client_ip_from_forwarded("1.1.1.1, 2.2.2.2, 10.0.0.1", &trusted)
+        );
+        assert_eq!(
+            Some("10.0.0.1"),
+            client_ip_from_forwarded("1.1.1.1, 10.0.0.1", &[])
+        );
+        assert_eq!(Some("10.0.0.1"), client_ip_from_forwarded("10.0.0.1", &trusted));
+        assert_eq!(None, client_ip_from_forwarded(" ", &trusted));
+    }
+}
@@ -0,0 +1,322 @@
+use rocket::State;
+use rocket_contrib::Json;
+use diesel::Connection;
+use diesel::pg::PgConnection;
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use failure::{Error, ResultExt};
+
+use brdgme_markup as markup;
+
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::mpsc::Sender;
+
+use db::{models, query, CONN};
+use db::proposal::{ProposalKind, ProposalOutcome, PROPOSAL_KINDS};
+use controller::{UuidParam, CORS};
+use controller::auth::{PlayUser, ReadUser};
+use controller::game::status_renders;
+use errors::ControllerError;
+use websocket;
+
+/// How long players have to vote before a proposal lapses.
+const EXPIRY_HOURS: i64 = 48;
+
+#[derive(Serialize)]
+pub struct ProposalResponse {
+    pub proposal: models::PublicGameProposal,
+    pub votes: Vec<models::PublicGameProposalVote>,
+}
+
+/// The proposal currently waiting on votes, if any. Only players in the game can see it.
+#[get("/<id>/proposal")]
+pub fn show(
+    id: UuidParam,
+    user: ReadUser,
+) -> Result<CORS<Json<Option<ProposalResponse>>>, ControllerError> {
+    let id = id.into_uuid();
+    let conn = &*CONN.r.get().context("unable to get connection")?;
+    query::find_game_player_by_user_and_game(&user.id, &id, conn)
+        .context("error finding game player")?
+        .ok_or_else(|| ControllerError::bad_request("you aren't a player in this game"))?;
+    let proposal = match query::proposal::find_open(&id, conn).context("error finding proposal")? {
+        Some(proposal) => proposal,
+        None => return Ok(CORS(Json(None))),
+    };
+    let votes = query::proposal::find_votes(&proposal.id, conn).context("error finding votes")?;
+    Ok(CORS(Json(Some(ProposalResponse { proposal, votes }))))
+}
+
+#[derive(Deserialize)]
+pub struct CreateRequest {
+    kind: String,
+}
+
+/// Proposes ending the game early, either by aborting it without any rating changes or by
+/// agreeing a draw. It goes ahead once everyone still playing has accepted.
+#[post("/<id>/proposal", data = "<data>")]
+pub fn create(
+    id: UuidParam,
+    data: Json<CreateRequest>,
+    user: PlayUser,
+    pub_queue_tx: State<Mutex<Sender<websocket::Message>>>,
+) -> Result<CORS<Json<ProposalResponse>>, ControllerError> {
+    let id = id.into_uuid();
+    let kind = ProposalKind::from_str(&data.into_inner().kind).map_err(|_| {
+        ControllerError::bad_request(format!(
+            "invalid proposal kind, valid options are: {}",
+            PROPOSAL_KINDS
+                .iter()
+                .map(|pk| pk.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        ))
+    })?;
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+    let tx = pub_queue_tx
+        .inner()
+        .lock()
+        .map_err::<Error, _>(|e| format_err!("unable to get lock on pub_queue_tx: {}", e))?
+        .clone();
+
+    conn.transaction::<_, ControllerError, _>(|| {
+        let (game, game_version, player) = find_playing(&id, &user.id, conn)?;
+        let now = Utc::now().naive_utc();
+        if let Some(open) = query::proposal::lock_open(&id, conn)
+            .context("error finding open proposal")?
+        {
+            if open.expires_at > now {
+                return Err(ControllerError::bad_request(
+                    "there is already a proposal waiting on votes",
+                ));
+            }
+            expire(&open, &tx, conn)?;
+        }
+        let (proposal, _) = query::proposal::create(
+            &id,
+            &player.id,
+            kind,
+            now + Duration::hours(EXPIRY_HOURS),
+            conn,
+        ).context("error creating proposal")?;
+        Ok(CORS(Json(tally(
+            &game,
+            &game_version,
+            &proposal,
+            &player,
+            &format!(" proposed {}", kind.description()),
+            &tx,
+            conn,
+        )?)))
+    })
+}
+
+#[derive(Deserialize)]
+pub struct VoteRequest {
+    accept: bool,
+}
+
+#[post("/<id>/proposal/vote", data = "<data>")]
+pub fn vote(
+    id: UuidParam,
+    data: Json<VoteRequest>,
+    user: PlayUser,
+    pub_queue_tx: State<Mutex<Sender<websocket::Message>>>,
+) -> Result<CORS<Json<ProposalResponse>>, ControllerError> {
+    let id = id.into_uuid();
+    let accept = data.into_inner().accept;
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+    let tx = pub_queue_tx
+        .inner()
+        .lock()
+        .map_err::<Error, _>(|e| format_err!("unable to get lock on pub_queue_tx: {}", e))?
+        .clone();
+
+    conn.transaction::<_, ControllerError, _>(|| {
+        let (game, game_version, player) = find_playing(&id, &user.id, conn)?;
+        // Locking the proposal makes concurrent votes wait, so each tally sees the votes before it.
+        let proposal = query::proposal::lock_open(&id, conn)
+            .context("error finding open proposal")?
+            .ok_or_else(|| ControllerError::bad_request("there is no proposal to vote on"))?;
+        if proposal.expires_at <= Utc::now().naive_utc() {
+            return Err(ControllerError::bad_request("the proposal has expired"));
+        }
+        if query::proposal::find_votes(&proposal.id, conn)
+            .context("error finding votes")?
+            .iter()
+            .any(|v| v.game_player_id == player.id)
+        {
+            return Err(ControllerError::bad_request(
+                "you have already voted on this proposal",
+            ));
+        }
+        query::proposal::vote(&proposal.id, &player.id, accept, conn)
+            .context("error voting on proposal")?;
+        let kind = ProposalKind::from_str(&proposal.kind)?;
+        Ok(CORS(Json(tally(
+            &game,
+            &game_version,
+            &proposal,
+            &player,
+            &format!(
+                " {} {}",
+                if accept { "accepted" } else { "rejected" },
+                kind.description()
+            ),
+            &tx,
+            conn,
+        )?)))
+    })
+}
+
+/// The unfinished game and the user's player in it, as long as they haven't resigned.
+fn find_playing(
+    game_id: &Uuid,
+    user_id: &Uuid,
+    conn: &PgConnection,
+) -> Result<(models::Game, models::GameVersion, models::GamePlayer), ControllerError> {
+    let (game, game_version) = query::find_game_with_version(game_id, conn)
+        .context("error finding game")?
+        .ok_or_else(|| ControllerError::bad_request("game does not exist"))?;
+    if game.is_finished {
+        return Err(ControllerError::bad_request("game is already finished"));
+    }
+    let player = query::find_game_player_by_user_and_game(user_id, game_id, conn)
+        .context("error finding game player")?
+        .ok_or_else(|| ControllerError::bad_request("you aren't a player in this game"))?;
+    if player.resigned_at.is_some() {
+        return Err(ControllerError::bad_request("you have resigned from this game"));
+    }
+    Ok((game, game_version, player))
+}
+
+/// Logs a player's action on a proposal, then settles it if the votes are in, ending the
+/// game if everyone agreed. Should be run inside a transaction.
+fn tally(
+    game: &models::Game,
+    game_version: &models::GameVersion,
+    proposal: &models::GameProposal,
+    player: &models::GamePlayer,
+    log_text: &str,
+    pub_queue_tx: &Sender<websocket::Message>,
+    conn: &PgConnection,
+) -> Result<ProposalResponse, Error> {
+    let kind = ProposalKind::from_str(&proposal.kind)?;
+    let votes = query::proposal::find_votes(&proposal.id, conn)?;
+    let mut log_bodies = vec![
+        markup::to_string(&[
+            markup::Node::Player(player.position as usize),
+            markup::Node::text(log_text),
+        ]),
+    ];
+    let game_players = query::find_game_players_by_game(&game.id, conn)?;
+    let proposal = match query::proposal::outcome(&game_players, &votes) {
+        Some(outcome) => {
+            let settled = query::proposal::settle(&proposal.id, outcome, conn)?
+                .ok_or_else::<Error, _>(|| format_err!("proposal has already been settled"))?;
+            if outcome == ProposalOutcome::Accepted {
+                log_bodies.push(markup::to_string(&[
+                    markup::Node::text(match kind {
+                        ProposalKind::Abort => {
+                            query::game::cancel(&game.id, conn)?;
+                            "All players agreed to abort the game, ratings are unchanged"
+                        }
+                        ProposalKind::Draw => {
+                            query::draw_game(&game.id, conn)?;
+                            "All players agreed to a draw"
+                        }
+                    }),
+                ]));
+            }
+            settled
+        }
+        None => proposal.to_owned(),
+    };
+    publish(game, game_version, &proposal, &votes, &log_bodies, pub_queue_tx, conn)?;
+    Ok(ProposalResponse { proposal, votes })
+}
+
+/// Settles a proposal nobody finished voting on. Proposals on games which have since finished
+/// are settled quietly. Should be run inside a transaction.
+pub fn expire(
+    proposal: &models::GameProposal,
+    pub_queue_tx: &Sender<websocket::Message>,
+    conn: &PgConnection,
+) -> Result<(), Error> {
+    // A vote being counted at the same time may settle the proposal first.
+    match query::proposal::lock_open(&proposal.game_id, conn)? {
+        Some(ref open) if open.id == proposal.id => {}
+        _ => return Ok(()),
+    }
+    let (game, game_version) = query::find_game_with_version(&proposal.game_id, conn)?
+        .ok_or_else::<Error, _>(|| format_err!("could not find game"))?;
+    let settled = match query::proposal::settle(&proposal.id, ProposalOutcome::Expired, conn)? {
+        Some(settled) => settled,
+        None => return Ok(()),
+    };
+    if game.is_finished {
+        return Ok(());
+    }
+    let log_text = format!(
+        "The proposal for {} expired",
+        ProposalKind::from_str(&proposal.kind)?.description()
+    );
+    publish(
+        &game,
+        &game_version,
+        &settled,
+        &query::proposal::find_votes(&proposal.id, conn)?,
+        &[markup::to_string(&[markup::Node::text(log_text.as_str())])],
+        pub_queue_tx,
+        conn,
+    )
+}
+
+/// Logs the proposal's progress, then publishes the game, which may have just ended, and the
+/// proposal itself.
+fn publish(
+    game: &models::Game,
+    game_version: &models::GameVersion,
+    proposal: &models::GameProposal,
+    votes: &[models::GameProposalVote],
+    log_bodies: &[String],
+    pub_queue_tx: &Sender<websocket::Message>,
+    conn: &PgConnection,
+) -> Result<(), Error> {
+    let logged_at = Utc::now().naive_utc();
+    let created_logs = log_bodies
+        .iter()
+        .map(|body| {
+            query::create_game_log(
+                &models::NewGameLog {
+                    game_id: game.id,
+                    body,
+                    is_public: true,
+                    logged_at,
+                },
+                &[],
+                conn,
+            )
+        })
+        .collect::<Result<Vec<query::CreatedGameLog>, Error>>()
+        .context("unable to create proposal game logs")?;
+    let (public_render, player_renders) = status_renders(&game_version.uri, &game.game_state)?;
+    let game_extended =
+        query::find_game_extended(&game.id, conn).context("unable to get extended game")?;
+    let user_ids: Vec<Uuid> = game_extended
+        .game_players
+        .iter()
+        .map(|gptu| gptu.user.id)
+        .collect();
+    let tokens = query::find_valid_user_auth_tokens_for_users(&user_ids, conn)?;
+    websocket::enqueue_game_update(
+        &game_extended.into_public(),
+        &created_logs,
+        &public_render,
+        &player_renders,
+        &tokens,
+        pub_queue_tx,
+    )?;
+    websocket::enqueue_game_proposal(proposal, votes, &tokens, pub_queue_tx)
+}
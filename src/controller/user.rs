@@ -0,0 +1,452 @@
+use rocket_contrib::Json;
+use diesel::Connection;
+use diesel::pg::PgConnection;
+use lettre::email::EmailBuilder;
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use failure::{Error, ResultExt};
+
+use std::cmp;
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use config::CONFIG;
+use db::{models, query, CONN};
+use db::color::{Color, COLORS};
+use db::notification::{TurnNotification, TURN_NOTIFICATIONS};
+use controller::{ClientIp, UuidParam, CORS};
+use controller::auth::ReadUser;
+use errors::ControllerError;
+use mail;
+
+const NAME_MIN_LEN: usize = 2;
+const NAME_MAX_LEN: usize = 20;
+
+#[get("/profile")]
+pub fn profile(user: ReadUser) -> Result<CORS<Json<models::PublicUser>>, ControllerError> {
+    Ok(CORS(Json(user.0.into_public())))
+}
+
+#[derive(Deserialize)]
+pub struct UpdateProfileRequest {
+    name: Option<String>,
+    pref_colors: Option<Vec<String>>,
+}
+
+#[put("/profile", data = "<data>")]
+pub fn update_profile(
+    data: Json<UpdateProfileRequest>,
+    user: models::User,
+) -> Result<CORS<Json<models::PublicUser>>, ControllerError> {
+    let data = data.into_inner();
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+
+    conn.transaction::<_, ControllerError, _>(|| {
+        let mut user = user.clone();
+        if let Some(ref name) = data.name {
+            let name = name.trim();
+            validate_name(name)?;
+            if name != user.name {
+                if query::user::find_by_name(name, conn)
+                    .context("error finding user by name")?
+                    .is_some()
+                {
+                    return Err(ControllerError::bad_request("that name is already taken"));
+                }
+                user = query::user::update_name(&user.id, name, conn)
+                    .context("error updating name")?;
+            }
+        }
+        if let Some(ref pref_colors) = data.pref_colors {
+            validate_pref_colors(pref_colors)?;
+            user = query::user::update_pref_colors(&user.id, pref_colors, conn)
+                .context("error updating color preferences")?;
+        }
+        Ok(CORS(Json(user.into_public())))
+    })
+}
+
+#[derive(Serialize)]
+pub struct EmailsResponse {
+    pub emails: Vec<models::PublicUserEmail>,
+    pub pending_emails: Vec<String>,
+}
+
+#[get("/emails")]
+pub fn emails(user: models::User) -> Result<CORS<Json<EmailsResponse>>, ControllerError> {
+    let conn = &*CONN.r.get().context("unable to get connection")?;
+
+    Ok(CORS(Json(EmailsResponse {
+        emails: query::user::find_emails(&user.id, conn).context("error finding emails")?,
+        pending_emails: query::user::find_pending_emails(&user.id, conn)
+            .context("error finding pending emails")?
+            .into_iter()
+            .map(|pue| pue.email)
+            .collect(),
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct AddEmailRequest {
+    email: String,
+}
+
+#[post("/emails", data = "<data>")]
+pub fn add_email(
+    data: Json<AddEmailRequest>,
+    user: models::User,
+    ip: ClientIp,
+) -> Result<CORS<()>, ControllerError> {
+    let email = data.into_inner().email.trim().to_string();
+    if !email.contains('@') {
+        return Err(ControllerError::bad_request("invalid email address"));
+    }
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+    if query::find_user_by_email(&email, conn)
+        .context("error finding user by email")?
+        .is_some()
+    {
+        return Err(ControllerError::bad_request("that email is already in use"));
+    }
+    if query::login::request_limit_exceeded(&email, ip.addr(), conn)
+        .context("unable to check confirmation request limit")?
+    {
+        return Err(ControllerError::too_many_requests(
+            "too many confirmation requests, please try again later",
+        ));
+    }
+    let confirmation = query::user::create_pending_email(&user.id, &email, conn)
+        .context("unable to create pending email")?;
+    query::login::create_request(&email, ip.addr(), conn)
+        .context("unable to record confirmation request")?;
+
+    let message = EmailBuilder::new()
+        .to(email.as_ref())
+        .from(CONFIG.mail_from.as_ref())
+        .subject("brdg.me email confirmation")
+        .html(&mail::html_layout(&format!(
+            "Your brdg.me confirmation to add this email to {} is <b>{}</b>
+
+This confirmation will expire in 30 minutes if not used.",
+            user.name, confirmation
+        )))
+        .build()
+        .context("unable to create email confirmation email")?;
+    mail::send(message, conn).context("unable to send email confirmation email")?;
+
+    Ok(CORS(()))
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmEmailRequest {
+    email: String,
+    code: String,
+}
+
+#[post("/emails/confirm", data = "<data>")]
+pub fn confirm_email(
+    data: Json<ConfirmEmailRequest>,
+    user: models::User,
+    ip: ClientIp,
+) -> Result<CORS<Json<models::PublicUserEmail>>, ControllerError> {
+    let data = data.into_inner();
+    let email = data.email.trim();
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+    if query::login::attempt_limit_exceeded(email, ip.addr(), conn)
+        .context("unable to check confirmation attempt limit")?
+    {
+        return Err(ControllerError::too_many_requests(
+            "too many failed confirmation attempts, please try again later",
+        ));
+    }
+    if query::find_user_by_email(email, conn)
+        .context("error finding user by email")?
+        .is_some()
+    {
+        return Err(ControllerError::bad_request("that email is already in use"));
+    }
+
+    match query::user::confirm_pending_email(&user.id, email, &data.code, conn)
+        .context("unable to confirm email")?
+    {
+        Some(user_email) => {
+            query::login::create_attempt(email, ip.addr(), true, conn)
+                .context("unable to record confirmation attempt")?;
+            Ok(CORS(Json(user_email)))
+        }
+        None => {
+            query::login::create_attempt(email, ip.addr(), false, conn)
+                .context("unable to record confirmation attempt")?;
+            Err(ControllerError::unauthorized(
+                "invalid or expired confirmation code",
+            ))
+        }
+    }
+}
+
+#[delete("/emails/<id>")]
+pub fn delete_email(
+    id: UuidParam,
+    user: models::User,
+) -> Result<CORS<Json<models::PublicUserEmail>>, ControllerError> {
+    let id = id.into_uuid();
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+
+    match query::user::delete_email(&user.id, &id, conn).context("unable to delete email")? {
+        Some(user_email) => Ok(CORS(Json(user_email))),
+        None => Err(ControllerError::bad_request(
+            "email does not exist or is your primary email",
+        )),
+    }
+}
+
+#[post("/emails/<id>/primary")]
+pub fn make_primary_email(
+    id: UuidParam,
+    user: models::User,
+) -> Result<CORS<Json<models::PublicUserEmail>>, ControllerError> {
+    let id = id.into_uuid();
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+
+    match query::user::set_primary_email(&user.id, &id, conn)
+        .context("unable to set primary email")?
+    {
+        Some(user_email) => Ok(CORS(Json(user_email))),
+        None => Err(ControllerError::bad_request("email does not exist")),
+    }
+}
+
+#[derive(Serialize)]
+pub struct SettingsResponse {
+    pub turn_notification: String,
+}
+
+impl SettingsResponse {
+    fn from_user(user: &models::User) -> Self {
+        SettingsResponse {
+            turn_notification: user.turn_notification.to_owned(),
+        }
+    }
+}
+
+#[get("/settings")]
+pub fn settings(user: models::User) -> Result<CORS<Json<SettingsResponse>>, ControllerError> {
+    Ok(CORS(Json(SettingsResponse::from_user(&user))))
+}
+
+#[derive(Deserialize)]
+pub struct UpdateSettingsRequest {
+    turn_notification: Option<String>,
+}
+
+#[put("/settings", data = "<data>")]
+pub fn update_settings(
+    data: Json<UpdateSettingsRequest>,
+    user: models::User,
+) -> Result<CORS<Json<SettingsResponse>>, ControllerError> {
+    let data = data.into_inner();
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+
+    conn.transaction::<_, ControllerError, _>(|| {
+        let mut user = user.clone();
+        if let Some(ref turn_notification) = data.turn_notification {
+            let turn_notification = TurnNotification::from_str(turn_notification).map_err(|_| {
+                ControllerError::bad_request(format!(
+                    "invalid turn notification, valid options are: {}",
+                    TURN_NOTIFICATIONS
+                        .iter()
+                        .map(|tn| tn.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ))
+            })?;
+            user = query::notification::update_turn_notification(
+                &user.id,
+                turn_notification,
+                conn,
+            ).context("error updating turn notification")?;
+        }
+        Ok(CORS(Json(SettingsResponse::from_user(&user))))
+    })
+}
+
+#[derive(Serialize)]
+pub struct BlockUser {
+    pub block: models::PublicUserBlock,
+    pub user: models::PublicUser,
+}
+
+#[get("/blocks")]
+pub fn blocks(user: models::User) -> Result<CORS<Json<Vec<BlockUser>>>, ControllerError> {
+    let conn = &*CONN.r.get().context("unable to get connection")?;
+    Ok(CORS(Json(query::block::find_by_user(&user.id, conn)
+        .context("error finding blocked users")?
+        .into_iter()
+        .map(|(block, u)| BlockUser {
+            block,
+            user: u.into_public(),
+        })
+        .collect())))
+}
+
+#[derive(Deserialize)]
+pub struct CreateBlockRequest {
+    name: String,
+}
+
+/// Blocked users can't invite the user to games, message them or send them friend requests.
+#[post("/blocks", data = "<data>")]
+pub fn create_block(
+    data: Json<CreateBlockRequest>,
+    user: models::User,
+) -> Result<CORS<Json<models::PublicUserBlock>>, ControllerError> {
+    let name = data.into_inner().name.trim().to_string();
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+
+    conn.transaction::<_, ControllerError, _>(|| {
+        let target = query::user::find_by_name(&name, conn)
+            .context("error finding user by name")?
+            .ok_or_else(|| ControllerError::bad_request("could not find a user with that name"))?;
+        if target.id == user.id {
+            return Err(ControllerError::bad_request("you can't block yourself"));
+        }
+        if query::block::is_blocked_by_any(&target.id, &[user.id], conn)
+            .context("error checking blocked users")?
+        {
+            return Err(ControllerError::bad_request("you have already blocked this user"));
+        }
+        Ok(CORS(Json(query::block::create(&user.id, &target.id, conn)
+            .context("error blocking user")?)))
+    })
+}
+
+#[delete("/blocks/<id>")]
+pub fn delete_block(id: UuidParam, user: models::User) -> Result<CORS<()>, ControllerError> {
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+    query::block::remove(&user.id, &id.into_uuid(), conn)
+        .context("error removing block")?
+        .ok_or_else(|| ControllerError::bad_request("block does not exist"))?;
+    Ok(CORS(()))
+}
+
+#[derive(Serialize)]
+pub struct VacationResponse {
+    pub vacation: Option<models::PublicUserVacation>,
+    pub allowance_days: i64,
+    /// Whole days of the allowance left to take in the coming year.
+    pub remaining_days: i64,
+}
+
+impl VacationResponse {
+    fn for_user(user_id: &Uuid, conn: &PgConnection) -> Result<Self, Error> {
+        let now = Utc::now().naive_utc();
+        let used = query::vacation::allowance_used(user_id, now, conn)?;
+        Ok(VacationResponse {
+            vacation: query::vacation::find_current(user_id, now, conn)?,
+            allowance_days: query::vacation::ALLOWANCE_DAYS,
+            remaining_days: cmp::max(
+                (Duration::days(query::vacation::ALLOWANCE_DAYS) - used).num_days(),
+                0,
+            ),
+        })
+    }
+}
+
+#[get("/vacation")]
+pub fn vacation(user: models::User) -> Result<CORS<Json<VacationResponse>>, ControllerError> {
+    let conn = &*CONN.r.get().context("unable to get connection")?;
+    Ok(CORS(Json(VacationResponse::for_user(&user.id, conn)
+        .context("error finding vacation")?)))
+}
+
+#[derive(Deserialize)]
+pub struct CreateVacationRequest {
+    days: i64,
+}
+
+/// Starts a vacation immediately. Turn clocks in timed games are frozen until the user gets
+/// back, and opponents see when that will be.
+#[post("/vacation", data = "<data>")]
+pub fn create_vacation(
+    data: Json<CreateVacationRequest>,
+    user: models::User,
+) -> Result<CORS<Json<VacationResponse>>, ControllerError> {
+    let days = data.into_inner().days;
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+
+    conn.transaction::<_, ControllerError, _>(|| {
+        let now = Utc::now().naive_utc();
+        if query::vacation::find_current(&user.id, now, conn)
+            .context("error finding current vacation")?
+            .is_some()
+        {
+            return Err(ControllerError::bad_request("you are already on vacation"));
+        }
+        let remaining = Duration::days(query::vacation::ALLOWANCE_DAYS)
+            - query::vacation::allowance_used(&user.id, now, conn)
+                .context("error finding vacation allowance")?;
+        if days < 1 || Duration::days(days) > remaining {
+            return Err(ControllerError::bad_request(format!(
+                "vacations must be between 1 and {} days, you have {} days left this year",
+                query::vacation::ALLOWANCE_DAYS,
+                cmp::max(remaining.num_days(), 0)
+            )));
+        }
+        query::vacation::create(&user.id, now + Duration::days(days), conn)
+            .context("error starting vacation")?;
+        Ok(CORS(Json(VacationResponse::for_user(&user.id, conn)
+            .context("error finding vacation")?)))
+    })
+}
+
+/// Ends the current vacation early, returning the unused days to the allowance.
+#[delete("/vacation")]
+pub fn delete_vacation(
+    user: models::User,
+) -> Result<CORS<Json<VacationResponse>>, ControllerError> {
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+    query::vacation::end(&user.id, Utc::now().naive_utc(), conn)
+        .context("error ending vacation")?
+        .ok_or_else(|| ControllerError::bad_request("you are not on vacation"))?;
+    Ok(CORS(Json(VacationResponse::for_user(&user.id, conn)
+        .context("error finding vacation")?)))
+}
+
+fn validate_name(name: &str) -> Result<(), ControllerError> {
+    let len = name.chars().count();
+    if len < NAME_MIN_LEN || len > NAME_MAX_LEN {
+        return Err(ControllerError::bad_request(format!(
+            "name must be between {} and {} characters long",
+            NAME_MIN_LEN, NAME_MAX_LEN
+        )));
+    }
+    if !name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') {
+        return Err(ControllerError::bad_request(
+            "name may only contain letters, numbers, underscores and dashes",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_pref_colors(pref_colors: &[String]) -> Result<(), ControllerError> {
+    let colors = Color::from_strings(pref_colors).map_err(|_| {
+        ControllerError::bad_request(format!(
+            "invalid color, valid colors are: {}",
+            COLORS
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        ))
+    })?;
+    let mut seen: HashSet<Color> = HashSet::new();
+    for c in colors {
+        if !seen.insert(c) {
+            return Err(ControllerError::bad_request(format!(
+                "{} is listed more than once",
+                c.to_string()
+            )));
+        }
+    }
+    Ok(())
+}
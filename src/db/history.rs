@@ -0,0 +1,61 @@
+use failure::Error;
+
+use std::str::FromStr;
+
+/// What changed a game's state, recorded in its history.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum HistoryAction {
+    /// The game was created, recording the initial state.
+    Start,
+    Command,
+    Undo,
+    /// A player conceded, ending the game.
+    Concede,
+    /// A player left a multi-player game and the rest played on.
+    Resign,
+}
+
+impl ToString for HistoryAction {
+    fn to_string(&self) -> String {
+        match *self {
+            HistoryAction::Start => "start",
+            HistoryAction::Command => "command",
+            HistoryAction::Undo => "undo",
+            HistoryAction::Concede => "concede",
+            HistoryAction::Resign => "resign",
+        }.to_string()
+    }
+}
+
+impl FromStr for HistoryAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Ok(match s {
+            "start" => HistoryAction::Start,
+            "command" => HistoryAction::Command,
+            "undo" => HistoryAction::Undo,
+            "concede" => HistoryAction::Concede,
+            "resign" => HistoryAction::Resign,
+            _ => bail!("Invalid history action"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_action_strings_round_trip() {
+        for ha in &[
+            HistoryAction::Start,
+            HistoryAction::Command,
+            HistoryAction::Undo,
+            HistoryAction::Concede,
+            HistoryAction::Resign,
+        ] {
+            assert_eq!(*ha, HistoryAction::from_str(&ha.to_string()).unwrap());
+        }
+    }
+}
@@ -3,64 +3,52 @@ use failure::{Error, ResultExt};
 pub mod query;
 pub mod models;
 pub mod color;
+pub mod history;
+pub mod notification;
+pub mod proposal;
+pub mod scope;
+pub mod time_control;
 pub mod schema;
 
-//use r2d2;
-//use r2d2_diesel::ConnectionManager;
+use r2d2;
+use r2d2_diesel::ConnectionManager;
 use diesel::pg::PgConnection;
-use std::env;
 
-lazy_static! {
-    pub static ref CONN: Connections = Connections {
-        w: Connection{},
-        r: Connection{},
-    };
-}
-
-pub struct Connection {}
-
-impl Connection {
-    pub fn get(&self) -> Result<Box<PgConnection>, Error> {
-        use diesel::Connection;
-        Ok(Box::new(
-            PgConnection::establish(&env::var("DATABASE_URL").context("DATABASE_URL not set")?)
-                .context("Unable to connect to database")?,
-        ))
-    }
-}
+use config::CONFIG;
 
-pub struct Connections {
-    pub w: Connection,
-    pub r: Connection,
-}
-
-/*lazy_static! {
+lazy_static! {
     pub static ref CONN: Connections = connect_env().unwrap();
 }
 
+pub type Pool = r2d2::Pool<ConnectionManager<PgConnection>>;
+
 pub struct Connections {
-    pub w: r2d2::Pool<ConnectionManager<PgConnection>>,
-    pub r: r2d2::Pool<ConnectionManager<PgConnection>>,
+    pub w: Pool,
+    pub r: Pool,
 }
 
 pub fn connect(w_addr: &str, r_addr: &str) -> Result<Connections, Error> {
     Ok(Connections {
-           w: conn(w_addr)?,
-           r: conn(r_addr)?,
-       })
+        w: conn(w_addr)?,
+        r: conn(r_addr)?,
+    })
 }
 
+/// Connects using `DATABASE_URL` for writes and `DATABASE_URL_R` for reads, falling back to
+/// `DATABASE_URL` for reads when a replica isn't configured.
 pub fn connect_env() -> Result<Connections, Error> {
-    let w_addr = env::var("DATABASE_URL")
-        .context("DATABASE_URL not set")?;
-    connect(&w_addr,
-            &env::var("DATABASE_URL_R").unwrap_or_else(|_| w_addr.to_owned()))
+    let w_addr = &CONFIG.database_url;
+    connect(
+        w_addr,
+        CONFIG.database_url_r.as_ref().unwrap_or(w_addr),
+    )
 }
 
-fn conn(addr: &str)
-        -> Result<r2d2::Pool<ConnectionManager<PgConnection>>, Error> {
-    r2d2::Pool::new(r2d2::Config::default(),
-                    ConnectionManager::<PgConnection>::new(addr))
-            .context("unable to connect to database")
+fn conn(addr: &str) -> Result<Pool, Error> {
+    let config = r2d2::Config::builder()
+        .pool_size(CONFIG.database_pool_size)
+        .connection_timeout(CONFIG.database_pool_timeout)
+        .build();
+    Ok(r2d2::Pool::new(config, ConnectionManager::<PgConnection>::new(addr))
+        .context("unable to connect to database")?)
 }
-*/
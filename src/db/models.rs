@@ -15,6 +15,9 @@ pub struct User {
     pub pref_colors: Vec<String>,
     pub login_confirmation: Option<String>,
     pub login_confirmation_at: Option<NaiveDateTime>,
+    pub turn_notification: String,
+    pub turn_digest_at: Option<NaiveDateTime>,
+    pub login_confirmation_failures: i32,
 }
 
 impl User {
@@ -47,7 +50,7 @@ pub struct NewUser<'a> {
     pub login_confirmation_at: Option<NaiveDateTime>,
 }
 
-#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations)]
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations, Serialize)]
 #[belongs_to(User)]
 pub struct UserEmail {
     pub id: Uuid,
@@ -58,6 +61,8 @@ pub struct UserEmail {
     pub is_primary: bool,
 }
 
+pub type PublicUserEmail = UserEmail;
+
 #[derive(Insertable)]
 #[table_name = "user_emails"]
 pub struct NewUserEmail<'a> {
@@ -68,11 +73,107 @@ pub struct NewUserEmail<'a> {
 
 #[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations)]
 #[belongs_to(User)]
+pub struct PendingUserEmail {
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub user_id: Uuid,
+    pub email: String,
+    pub confirmation: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "pending_user_emails"]
+pub struct NewPendingUserEmail<'a> {
+    pub user_id: Uuid,
+    pub email: &'a str,
+    pub confirmation: &'a str,
+}
+
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations, Serialize)]
+#[belongs_to(User)]
 pub struct UserAuthToken {
     pub id: Uuid,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
     pub user_id: Uuid,
+    pub last_used_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub session_id: Uuid,
+}
+
+impl UserAuthToken {
+    pub fn into_public(self) -> PublicUserAuthToken {
+        PublicUserAuthToken {
+            session_id: self.session_id,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            user_id: self.user_id,
+            last_used_at: self.last_used_at,
+            revoked_at: self.revoked_at,
+        }
+    }
+}
+
+/// A token without the token itself, which is a bearer credential. Sessions are referred to by
+/// `session_id` instead.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PublicUserAuthToken {
+    pub session_id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub user_id: Uuid,
+    pub last_used_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations, Serialize)]
+#[belongs_to(User)]
+pub struct UserApiKey {
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub user_id: Uuid,
+    pub name: String,
+    pub key: Uuid,
+    pub scopes: Vec<String>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+impl UserApiKey {
+    pub fn into_public(self) -> PublicUserApiKey {
+        PublicUserApiKey {
+            id: self.id,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            user_id: self.user_id,
+            name: self.name,
+            scopes: self.scopes,
+            last_used_at: self.last_used_at,
+            revoked_at: self.revoked_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PublicUserApiKey {
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub user_id: Uuid,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "user_api_keys"]
+pub struct NewUserApiKey<'a> {
+    pub user_id: Uuid,
+    pub name: &'a str,
+    pub scopes: &'a [String],
 }
 
 #[derive(Insertable)]
@@ -81,6 +182,171 @@ pub struct NewUserAuthToken {
     pub user_id: Uuid,
 }
 
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations, Serialize)]
+#[belongs_to(User)]
+pub struct UserBlock {
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub user_id: Uuid,
+    pub blocked_user_id: Uuid,
+}
+
+pub type PublicUserBlock = UserBlock;
+
+#[derive(Insertable)]
+#[table_name = "user_blocks"]
+pub struct NewUserBlock {
+    pub user_id: Uuid,
+    pub blocked_user_id: Uuid,
+}
+
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations, Serialize)]
+#[belongs_to(User)]
+pub struct UserVacation {
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub user_id: Uuid,
+    pub starts_at: NaiveDateTime,
+    pub ends_at: NaiveDateTime,
+}
+
+pub type PublicUserVacation = UserVacation;
+
+#[derive(Insertable)]
+#[table_name = "user_vacations"]
+pub struct NewUserVacation {
+    pub user_id: Uuid,
+    pub ends_at: NaiveDateTime,
+}
+
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations)]
+pub struct LoginRequest {
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub email: String,
+    pub ip: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "login_requests"]
+pub struct NewLoginRequest<'a> {
+    pub email: &'a str,
+    pub ip: Option<&'a str>,
+}
+
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations)]
+pub struct LoginAttempt {
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub email: String,
+    pub ip: Option<String>,
+    pub is_success: bool,
+}
+
+#[derive(Insertable)]
+#[table_name = "login_attempts"]
+pub struct NewLoginAttempt<'a> {
+    pub email: &'a str,
+    pub ip: Option<&'a str>,
+    pub is_success: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Queryable, QueryableByName, Identifiable, Associations,
+         Serialize)]
+#[belongs_to(User)]
+#[table_name = "matchmaking_entries"]
+pub struct MatchmakingEntry {
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub user_id: Uuid,
+    pub game_type_id: Uuid,
+    pub player_count: i32,
+    pub rating: i32,
+    pub game_id: Option<Uuid>,
+}
+
+pub type PublicMatchmakingEntry = MatchmakingEntry;
+
+#[derive(Insertable)]
+#[table_name = "matchmaking_entries"]
+pub struct NewMatchmakingEntry {
+    pub user_id: Uuid,
+    pub game_type_id: Uuid,
+    pub player_count: i32,
+    pub rating: i32,
+}
+
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations, Serialize)]
+#[belongs_to(GameVersion)]
+pub struct OpenGame {
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub game_version_id: Uuid,
+    pub creator_id: Uuid,
+    pub player_count: i32,
+    pub game_id: Option<Uuid>,
+    pub cancelled_at: Option<NaiveDateTime>,
+}
+
+pub type PublicOpenGame = OpenGame;
+
+#[derive(Insertable)]
+#[table_name = "open_games"]
+pub struct NewOpenGame {
+    pub game_version_id: Uuid,
+    pub creator_id: Uuid,
+    pub player_count: i32,
+}
+
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations, Serialize)]
+#[belongs_to(OpenGame)]
+#[belongs_to(User)]
+pub struct OpenGamePlayer {
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub open_game_id: Uuid,
+    pub user_id: Uuid,
+}
+
+#[derive(Insertable)]
+#[table_name = "open_game_players"]
+pub struct NewOpenGamePlayer {
+    pub open_game_id: Uuid,
+    pub user_id: Uuid,
+}
+
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Serialize)]
+pub struct OutboundEmail {
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub message_id: String,
+    pub from_address: String,
+    pub to_addresses: Vec<String>,
+    pub message: String,
+    pub attempts: i32,
+    pub next_attempt_at: NaiveDateTime,
+    pub last_error: Option<String>,
+    pub sent_at: Option<NaiveDateTime>,
+    pub failed_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "outbound_emails"]
+pub struct NewOutboundEmail<'a> {
+    pub message_id: &'a str,
+    pub from_address: &'a str,
+    pub to_addresses: &'a [String],
+    pub message: &'a str,
+}
+
 #[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations, Serialize, Deserialize)]
 pub struct GameType {
     pub id: Uuid,
@@ -182,6 +448,10 @@ pub struct Game {
     pub game_state: String,
     pub chat_id: Option<Uuid>,
     pub restarted_game_id: Option<Uuid>,
+    pub turn_days: Option<i32>,
+    pub time_bank_hours: i32,
+    pub grace_hours: i32,
+    pub timeout_penalty: String,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -194,6 +464,10 @@ pub struct PublicGame {
     pub finished_at: Option<NaiveDateTime>,
     pub chat_id: Option<Uuid>,
     pub restarted_game_id: Option<Uuid>,
+    pub turn_days: Option<i32>,
+    pub time_bank_hours: i32,
+    pub grace_hours: i32,
+    pub timeout_penalty: String,
 }
 
 impl Game {
@@ -207,6 +481,10 @@ impl Game {
             finished_at: self.finished_at,
             chat_id: self.chat_id,
             restarted_game_id: self.restarted_game_id,
+            turn_days: self.turn_days,
+            time_bank_hours: self.time_bank_hours,
+            grace_hours: self.grace_hours,
+            timeout_penalty: self.timeout_penalty,
         }
     }
 }
@@ -240,6 +518,11 @@ pub struct GamePlayer {
     pub undo_game_state: Option<String>,
     pub place: Option<i32>,
     pub rating_change: Option<i32>,
+    pub turn_notified_at: Option<NaiveDateTime>,
+    pub email_token: Uuid,
+    pub time_bank_secs: i32,
+    pub turn_warned_at: Option<NaiveDateTime>,
+    pub resigned_at: Option<NaiveDateTime>,
 }
 
 impl GamePlayer {
@@ -262,6 +545,9 @@ impl GamePlayer {
             can_undo: self.undo_game_state.is_some(),
             place: self.place,
             rating_change: self.rating_change,
+            time_bank_secs: self.time_bank_secs,
+            resigned_at: self.resigned_at,
+            on_vacation_until: None,
         }
     }
 }
@@ -285,6 +571,11 @@ pub struct PublicGamePlayer {
     pub can_undo: bool,
     pub place: Option<i32>,
     pub rating_change: Option<i32>,
+    pub time_bank_secs: i32,
+    pub resigned_at: Option<NaiveDateTime>,
+    /// When the player gets back, if they're currently on vacation and their turn clock is
+    /// paused.
+    pub on_vacation_until: Option<NaiveDateTime>,
 }
 
 #[derive(Insertable)]
@@ -311,12 +602,16 @@ pub struct GamePlayerTypeUser {
     pub game_player: GamePlayer,
     pub user: User,
     pub game_type_user: GameTypeUser,
+    pub on_vacation_until: Option<NaiveDateTime>,
 }
 
 impl GamePlayerTypeUser {
     pub fn into_public(self) -> PublicGamePlayerTypeUser {
         PublicGamePlayerTypeUser {
-            game_player: self.game_player.into_public(),
+            game_player: PublicGamePlayer {
+                on_vacation_until: self.on_vacation_until,
+                ..self.game_player.into_public()
+            },
             user: self.user.into_public(),
             game_type_user: self.game_type_user,
         }
@@ -351,7 +646,7 @@ pub struct RenderedGameLog {
 }
 
 impl GameLog {
-    fn render(&self, players: &[markup::Player]) -> Result<String, Error> {
+    pub fn render(&self, players: &[markup::Player]) -> Result<String, Error> {
         let (parsed, _) = markup::from_string(&self.body).context("error parsing log body")?;
         Ok(markup::html(&markup::transform(&parsed, players)))
     }
@@ -392,6 +687,82 @@ pub struct NewGameLogTarget {
     pub game_player_id: Uuid,
 }
 
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations, Serialize)]
+#[belongs_to(Game)]
+#[table_name = "game_history_entries"]
+pub struct GameHistoryEntry {
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub game_id: Uuid,
+    pub sequence: i32,
+    pub game_player_id: Option<Uuid>,
+    pub action: String,
+    pub command: Option<String>,
+    pub game_state: String,
+    pub whose_turn: Vec<i32>,
+}
+
+#[derive(Insertable)]
+#[table_name = "game_history_entries"]
+pub struct NewGameHistoryEntry<'a> {
+    pub game_id: Uuid,
+    pub sequence: i32,
+    pub game_player_id: Option<Uuid>,
+    pub action: &'a str,
+    pub command: Option<&'a str>,
+    pub game_state: &'a str,
+    pub whose_turn: Vec<i32>,
+}
+
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations, Serialize)]
+#[belongs_to(Game)]
+#[belongs_to(GamePlayer)]
+pub struct GameProposal {
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub game_id: Uuid,
+    pub game_player_id: Uuid,
+    pub kind: String,
+    pub expires_at: NaiveDateTime,
+    pub outcome: Option<String>,
+    pub settled_at: Option<NaiveDateTime>,
+}
+
+pub type PublicGameProposal = GameProposal;
+
+#[derive(Insertable)]
+#[table_name = "game_proposals"]
+pub struct NewGameProposal<'a> {
+    pub game_id: Uuid,
+    pub game_player_id: Uuid,
+    pub kind: &'a str,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations, Serialize)]
+#[belongs_to(GameProposal)]
+#[belongs_to(GamePlayer)]
+pub struct GameProposalVote {
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub game_proposal_id: Uuid,
+    pub game_player_id: Uuid,
+    pub is_accepted: bool,
+}
+
+pub type PublicGameProposalVote = GameProposalVote;
+
+#[derive(Insertable)]
+#[table_name = "game_proposal_votes"]
+pub struct NewGameProposalVote {
+    pub game_proposal_id: Uuid,
+    pub game_player_id: Uuid,
+    pub is_accepted: bool,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Queryable, Identifiable, Associations)]
 #[belongs_to(GameType)]
 #[belongs_to(User)]
@@ -418,7 +789,7 @@ pub struct NewGameTypeUser {
     pub peak_rating: Option<i32>,
 }
 
-#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations)]
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations, Serialize)]
 #[belongs_to(User, foreign_key = "target_user_id")]
 pub struct Friend {
     pub id: Uuid,
@@ -429,6 +800,8 @@ pub struct Friend {
     pub has_accepted: Option<bool>,
 }
 
+pub type PublicFriend = Friend;
+
 #[derive(Insertable)]
 #[table_name = "friends"]
 pub struct NewFriend {
@@ -0,0 +1,52 @@
+use failure::Error;
+
+use std::str::FromStr;
+
+/// How a user wants to be told that it's their turn.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum TurnNotification {
+    Off,
+    Immediately,
+    Daily,
+}
+
+pub static TURN_NOTIFICATIONS: &'static [TurnNotification] = &[
+    TurnNotification::Off,
+    TurnNotification::Immediately,
+    TurnNotification::Daily,
+];
+
+impl ToString for TurnNotification {
+    fn to_string(&self) -> String {
+        match *self {
+            TurnNotification::Off => "off",
+            TurnNotification::Immediately => "immediately",
+            TurnNotification::Daily => "daily",
+        }.to_string()
+    }
+}
+
+impl FromStr for TurnNotification {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Ok(match s {
+            "off" => TurnNotification::Off,
+            "immediately" => TurnNotification::Immediately,
+            "daily" => TurnNotification::Daily,
+            _ => bail!("Invalid turn notification"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turn_notification_strings_round_trip() {
+        for tn in TURN_NOTIFICATIONS {
+            assert_eq!(*tn, TurnNotification::from_str(&tn.to_string()).unwrap());
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use failure::Error;
+
+use std::str::FromStr;
+
+/// What players can agree to end a game early with.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum ProposalKind {
+    /// The game is cancelled without any placings, so ratings aren't affected.
+    Abort,
+    /// Everyone still playing shares first place.
+    Draw,
+}
+
+pub static PROPOSAL_KINDS: &'static [ProposalKind] = &[ProposalKind::Abort, ProposalKind::Draw];
+
+impl ProposalKind {
+    /// How the proposal reads in game logs, eg. "proposed aborting the game".
+    pub fn description(&self) -> &'static str {
+        match *self {
+            ProposalKind::Abort => "aborting the game",
+            ProposalKind::Draw => "a draw",
+        }
+    }
+}
+
+impl ToString for ProposalKind {
+    fn to_string(&self) -> String {
+        match *self {
+            ProposalKind::Abort => "abort",
+            ProposalKind::Draw => "draw",
+        }.to_string()
+    }
+}
+
+impl FromStr for ProposalKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Ok(match s {
+            "abort" => ProposalKind::Abort,
+            "draw" => ProposalKind::Draw,
+            _ => bail!("Invalid proposal kind"),
+        })
+    }
+}
+
+/// How a proposal was settled.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum ProposalOutcome {
+    Accepted,
+    Rejected,
+    Expired,
+}
+
+impl ToString for ProposalOutcome {
+    fn to_string(&self) -> String {
+        match *self {
+            ProposalOutcome::Accepted => "accepted",
+            ProposalOutcome::Rejected => "rejected",
+            ProposalOutcome::Expired => "expired",
+        }.to_string()
+    }
+}
+
+impl FromStr for ProposalOutcome {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Ok(match s {
+            "accepted" => ProposalOutcome::Accepted,
+            "rejected" => ProposalOutcome::Rejected,
+            "expired" => ProposalOutcome::Expired,
+            _ => bail!("Invalid proposal outcome"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proposal_strings_round_trip() {
+        for pk in PROPOSAL_KINDS {
+            assert_eq!(*pk, ProposalKind::from_str(&pk.to_string()).unwrap());
+        }
+        for po in &[
+            ProposalOutcome::Accepted,
+            ProposalOutcome::Rejected,
+            ProposalOutcome::Expired,
+        ] {
+            assert_eq!(*po, ProposalOutcome::from_str(&po.to_string()).unwrap());
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use uuid::Uuid;
+use chrono::Utc;
+use failure::{Error, ResultExt};
+
+use db::models::*;
+use super::TOKEN_TOUCH_INTERVAL;
+
+pub fn create(
+    user_id: &Uuid,
+    name: &str,
+    scopes: &[String],
+    conn: &PgConnection,
+) -> Result<UserApiKey, Error> {
+    use db::schema::user_api_keys;
+
+    Ok(diesel::insert_into(user_api_keys::table)
+        .values(&NewUserApiKey {
+            user_id: *user_id,
+            name,
+            scopes,
+        })
+        .get_result(conn)
+        .context("error creating API key")?)
+}
+
+/// API keys don't expire, they are valid until they are revoked.
+pub fn find_valid_by_key(key: &Uuid, conn: &PgConnection) -> Result<Option<UserApiKey>, Error> {
+    use db::schema::user_api_keys;
+
+    Ok(user_api_keys::table
+        .filter(user_api_keys::key.eq(key))
+        .filter(user_api_keys::revoked_at.is_null())
+        .first(conn)
+        .optional()
+        .context("error finding API key")?)
+}
+
+pub fn find_valid_by_user(user_id: &Uuid, conn: &PgConnection) -> Result<Vec<UserApiKey>, Error> {
+    use db::schema::user_api_keys;
+
+    Ok(user_api_keys::table
+        .filter(user_api_keys::user_id.eq(user_id))
+        .filter(user_api_keys::revoked_at.is_null())
+        .order(user_api_keys::created_at)
+        .get_results(conn)
+        .context("error finding API keys for user")?)
+}
+
+pub fn needs_touch(api_key: &UserApiKey) -> bool {
+    api_key
+        .last_used_at
+        .map(|at| at + *TOKEN_TOUCH_INTERVAL < Utc::now().naive_utc())
+        .unwrap_or(true)
+}
+
+pub fn touch(id: &Uuid, conn: &PgConnection) -> Result<Option<UserApiKey>, Error> {
+    use db::schema::user_api_keys;
+
+    Ok(diesel::update(user_api_keys::table.find(id))
+        .set(user_api_keys::last_used_at.eq(Utc::now().naive_utc()))
+        .get_result(conn)
+        .optional()
+        .context("error updating API key last_used_at")?)
+}
+
+pub fn revoke(user_id: &Uuid, id: &Uuid, conn: &PgConnection) -> Result<Option<UserApiKey>, Error> {
+    use db::schema::user_api_keys;
+
+    Ok(diesel::update(
+        user_api_keys::table
+            .find(id)
+            .filter(user_api_keys::user_id.eq(user_id))
+            .filter(user_api_keys::revoked_at.is_null()),
+    ).set(user_api_keys::revoked_at.eq(Utc::now().naive_utc()))
+        .get_result(conn)
+        .optional()
+        .context("error revoking API key")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use db::query::*;
+    use super::*;
+
+    #[test]
+    #[ignore]
+    fn revoke_works() {
+        with_db(|conn| {
+            let user = create_user_by_name("beefsack", conn).unwrap();
+            let api_key = create(&user.id, "bot", &["play".to_string()], conn).unwrap();
+            assert!(find_valid_by_key(&api_key.key, conn).unwrap().is_some());
+            assert!(revoke(&user.id, &api_key.id, conn).unwrap().is_some());
+            assert!(find_valid_by_key(&api_key.key, conn).unwrap().is_none());
+            assert!(find_valid_by_user(&user.id, conn).unwrap().is_empty());
+        });
+    }
+}
@@ -0,0 +1,157 @@
+use diesel;
+use diesel::dsl::count;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use uuid::Uuid;
+use failure::{Error, ResultExt};
+
+use std::collections::HashMap;
+
+use db::models::*;
+
+/// Blocks a user, removing any friendship or pending friend request between them.
+pub fn create(
+    user_id: &Uuid,
+    blocked_user_id: &Uuid,
+    conn: &PgConnection,
+) -> Result<UserBlock, Error> {
+    use db::schema::{friends, user_blocks};
+
+    conn.transaction(|| {
+        diesel::delete(
+            friends::table.filter(
+                friends::source_user_id
+                    .eq(user_id)
+                    .and(friends::target_user_id.eq(blocked_user_id))
+                    .or(friends::source_user_id
+                        .eq(blocked_user_id)
+                        .and(friends::target_user_id.eq(user_id))),
+            ),
+        ).execute(conn)
+            .context("error removing friend for blocked user")?;
+        Ok(diesel::insert_into(user_blocks::table)
+            .values(&NewUserBlock {
+                user_id: *user_id,
+                blocked_user_id: *blocked_user_id,
+            })
+            .get_result(conn)
+            .context("error creating user block")?)
+    })
+}
+
+pub fn remove(user_id: &Uuid, id: &Uuid, conn: &PgConnection) -> Result<Option<UserBlock>, Error> {
+    use db::schema::user_blocks;
+
+    Ok(diesel::delete(
+        user_blocks::table
+            .find(id)
+            .filter(user_blocks::user_id.eq(user_id)),
+    ).get_result(conn)
+        .optional()
+        .context("error removing user block")?)
+}
+
+/// The users blocked by a user, along with each blocked user.
+pub fn find_by_user(user_id: &Uuid, conn: &PgConnection) -> Result<Vec<(UserBlock, User)>, Error> {
+    use db::schema::{user_blocks, users};
+
+    let blocks: Vec<UserBlock> = user_blocks::table
+        .filter(user_blocks::user_id.eq(user_id))
+        .order(user_blocks::created_at)
+        .get_results(conn)
+        .context("error finding user blocks")?;
+    let mut blocked: HashMap<Uuid, User> = users::table
+        .filter(users::id.eq_any(blocks
+            .iter()
+            .map(|b| b.blocked_user_id)
+            .collect::<Vec<Uuid>>()))
+        .get_results::<User>(conn)
+        .context("error finding blocked users")?
+        .into_iter()
+        .map(|u| (u.id, u))
+        .collect();
+    Ok(blocks
+        .into_iter()
+        .filter_map(|b| blocked.remove(&b.blocked_user_id).map(|u| (b, u)))
+        .collect())
+}
+
+/// Whether any of the users have blocked `blocked_user_id`.
+pub fn is_blocked_by_any(
+    blocked_user_id: &Uuid,
+    user_ids: &[Uuid],
+    conn: &PgConnection,
+) -> Result<bool, Error> {
+    use db::schema::user_blocks;
+
+    if user_ids.is_empty() {
+        return Ok(false);
+    }
+    let blocks: i64 = user_blocks::table
+        .select(count(user_blocks::id))
+        .filter(user_blocks::blocked_user_id.eq(blocked_user_id))
+        .filter(user_blocks::user_id.eq_any(user_ids))
+        .get_result(conn)
+        .context("error counting user blocks")?;
+    Ok(blocks > 0)
+}
+
+/// Whether either user has blocked the other.
+pub fn is_blocked_either_way(
+    user_id: &Uuid,
+    other_user_id: &Uuid,
+    conn: &PgConnection,
+) -> Result<bool, Error> {
+    Ok(is_blocked_by_any(user_id, &[*other_user_id], conn)?
+        || is_blocked_by_any(other_user_id, &[*user_id], conn)?)
+}
+
+/// Whether any of the invited users, by ID or by any of their email addresses,
+/// have blocked the inviter.
+pub fn invitation_blocked(
+    inviter_id: &Uuid,
+    invitee_ids: &[Uuid],
+    invitee_emails: &[String],
+    conn: &PgConnection,
+) -> Result<bool, Error> {
+    let mut user_ids = invitee_ids.to_owned();
+    for email in invitee_emails {
+        if let Some((_, user)) = super::find_user_by_email(email, conn)? {
+            user_ids.push(user.id);
+        }
+    }
+    is_blocked_by_any(inviter_id, &user_ids, conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use db::query::*;
+    use super::*;
+
+    #[test]
+    #[ignore]
+    fn block_works() {
+        with_db(|conn| {
+            let (_, user1) = create_user_by_email("blah@example.com", conn).unwrap();
+            let (_, user2) = create_user_by_email("egg@example.com", conn).unwrap();
+            friend::create_request(&user2.id, &user1.id, conn).unwrap();
+
+            let block = create(&user1.id, &user2.id, conn).unwrap();
+            assert!(friend::find_between(&user1.id, &user2.id, conn).unwrap().is_none());
+            assert!(is_blocked_by_any(&user2.id, &[user1.id], conn).unwrap());
+            assert!(!is_blocked_by_any(&user1.id, &[user2.id], conn).unwrap());
+            assert!(is_blocked_either_way(&user1.id, &user2.id, conn).unwrap());
+            assert!(invitation_blocked(
+                &user2.id,
+                &[],
+                &["blah@example.com".to_string()],
+                conn
+            ).unwrap());
+            assert!(!invitation_blocked(&user1.id, &[user2.id], &[], conn).unwrap());
+            assert_eq!(user2.id, find_by_user(&user1.id, conn).unwrap()[0].1.id);
+
+            assert!(remove(&user1.id, &block.id, conn).unwrap().is_some());
+            assert!(!is_blocked_either_way(&user1.id, &user2.id, conn).unwrap());
+        });
+    }
+}
@@ -1,10 +1,13 @@
 use diesel;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
+use diesel::sql_types::BigInt;
 use uuid::Uuid;
 use chrono::{NaiveDateTime, Utc};
 use failure::{Error, ResultExt};
 
+use std::collections::HashMap;
+
 use db::models::*;
 
 pub fn create(conn: &PgConnection) -> Result<Chat, Error> {
@@ -85,11 +88,101 @@ pub fn find_messages_by_chat(
     Ok(chat_messages::table
         .inner_join(chat_users::table)
         .filter(chat_users::chat_id.eq(chat_id))
+        .order(chat_messages::created_at)
         .get_results::<(ChatMessage, ChatUser)>(conn)
         .map(|rows| rows.into_iter().map(|row| row.0).collect())
         .context("error finding chat users for chat")?)
 }
 
+/// A message, as long as it was posted in the chat.
+pub fn find_message_in_chat(
+    chat_id: &Uuid,
+    id: &Uuid,
+    conn: &PgConnection,
+) -> Result<Option<ChatMessage>, Error> {
+    use db::schema::{chat_messages, chat_users};
+
+    Ok(chat_messages::table
+        .find(id)
+        .inner_join(chat_users::table)
+        .filter(chat_users::chat_id.eq(chat_id))
+        .first::<(ChatMessage, ChatUser)>(conn)
+        .optional()
+        .context("error finding chat message in chat")?
+        .map(|row| row.0))
+}
+
+/// Finds up to `limit` of the most recent messages posted before `before`, in
+/// the order they were posted. Messages posted at the same time are ordered by
+/// ID so pages never skip or repeat them.
+pub fn find_messages_page(
+    chat_id: &Uuid,
+    before: Option<&ChatMessage>,
+    limit: i64,
+    conn: &PgConnection,
+) -> Result<Vec<ChatMessage>, Error> {
+    use db::schema::{chat_messages, chat_users};
+
+    let mut query = chat_messages::table
+        .inner_join(chat_users::table)
+        .filter(chat_users::chat_id.eq(chat_id))
+        .into_boxed();
+    if let Some(before) = before {
+        query = query.filter(
+            chat_messages::created_at.lt(before.created_at).or(chat_messages::created_at
+                .eq(before.created_at)
+                .and(chat_messages::id.lt(before.id))),
+        );
+    }
+    let mut messages = query
+        .order((chat_messages::created_at.desc(), chat_messages::id.desc()))
+        .limit(limit)
+        .get_results::<(ChatMessage, ChatUser)>(conn)
+        .map(|rows| rows.into_iter().map(|row| row.0).collect::<Vec<ChatMessage>>())
+        .context("error finding chat messages page")?;
+    messages.reverse();
+    Ok(messages)
+}
+
+pub fn find_chat_user(
+    chat_id: &Uuid,
+    user_id: &Uuid,
+    conn: &PgConnection,
+) -> Result<Option<ChatUser>, Error> {
+    use db::schema::chat_users;
+
+    Ok(chat_users::table
+        .filter(chat_users::chat_id.eq(chat_id))
+        .filter(chat_users::user_id.eq(user_id))
+        .first(conn)
+        .optional()
+        .context("error finding chat user")?)
+}
+
+#[derive(QueryableByName)]
+struct UnreadCount {
+    #[sql_type = "BigInt"]
+    unread: i64,
+}
+
+/// Counts messages from others posted since the user last read each of their chats.
+pub fn count_unread_for_user(user_id: &Uuid, conn: &PgConnection) -> Result<i64, Error> {
+    // Messages only know who posted them, so chat_users is joined twice: once for the author,
+    // to find the chat, and once for the reader.
+    Ok(diesel::sql_query(
+        "SELECT COUNT(*) AS unread
+        FROM chat_messages
+        INNER JOIN chat_users AS authors ON authors.id = chat_messages.chat_user_id
+        INNER JOIN chat_users AS readers ON readers.chat_id = authors.chat_id
+        WHERE readers.user_id = $1
+        AND chat_messages.chat_user_id <> readers.id
+        AND chat_messages.created_at > readers.last_read_at",
+    ).bind::<diesel::sql_types::Uuid, _>(user_id)
+        .get_result::<UnreadCount>(conn)
+        .context("error counting unread chat messages")?
+        .unread)
+}
+
 pub fn update_user_last_read_at(
     chat_user_id: &Uuid,
     at: NaiveDateTime,
@@ -120,12 +213,30 @@ pub struct ChatExtended {
 
 impl ChatExtended {
     pub fn into_public(self) -> PublicChatExtended {
+        let unread_counts = self.unread_counts();
         PublicChatExtended {
             chat: self.chat,
             chat_users: self.chat_users.into_iter().collect(),
             chat_messages: self.chat_messages.into_iter().collect(),
+            unread_counts,
         }
     }
+
+    /// Unread message counts keyed by user ID, not counting users' own messages.
+    pub fn unread_counts(&self) -> HashMap<Uuid, usize> {
+        self.chat_users
+            .iter()
+            .map(|cu| {
+                (
+                    cu.user_id,
+                    self.chat_messages
+                        .iter()
+                        .filter(|cm| cm.chat_user_id != cu.id && cm.created_at > cu.last_read_at)
+                        .count(),
+                )
+            })
+            .collect()
+    }
 }
 
 #[derive(Serialize, Debug, Clone)]
@@ -133,6 +244,7 @@ pub struct PublicChatExtended {
     pub chat: PublicChat,
     pub chat_users: Vec<PublicChatUser>,
     pub chat_messages: Vec<PublicChatMessage>,
+    pub unread_counts: HashMap<Uuid, usize>,
 }
 
 pub fn find_extended(id: &Uuid, conn: &PgConnection) -> Result<ChatExtended, Error> {
@@ -145,6 +257,8 @@ pub fn find_extended(id: &Uuid, conn: &PgConnection) -> Result<ChatExtended, Err
 
 #[cfg(test)]
 mod tests {
+    use chrono::Duration;
+
     use db::query::*;
     use super::*;
 
@@ -195,4 +309,33 @@ mod tests {
             find(&chat.id, conn).expect("expected to find chat extended");
         });
     }
+
+    #[test]
+    #[ignore]
+    fn unread_counts_work() {
+        with_db(|conn| {
+            let user1 = create_user_by_name("blah", conn).expect("expected to create a user");
+            let user2 = create_user_by_name("egg", conn).expect("expected to create a user");
+            let chat = create(conn).expect("expected to create a chat");
+            let chat_users = add_users(chat.id, &[user1.id, user2.id], conn)
+                .expect("expected to add users to chat");
+            update_user_last_read_at(
+                &chat_users[1].id,
+                Utc::now().naive_utc() - Duration::minutes(1),
+                conn,
+            ).expect("expected to update last read at");
+            create_message(chat_users[0].id, "this is the message", conn)
+                .expect("expected to create a chat message");
+
+            let extended = find_extended(&chat.id, conn).expect("expected to find chat extended");
+            let counts = extended.unread_counts();
+            assert_eq!(Some(&0), counts.get(&user1.id));
+            assert_eq!(Some(&1), counts.get(&user2.id));
+            assert_eq!(1, count_unread_for_user(&user2.id, conn).unwrap());
+            assert_eq!(
+                1,
+                find_messages_page(&chat.id, None, 10, conn).unwrap().len()
+            );
+        });
+    }
 }
@@ -0,0 +1,217 @@
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use uuid::Uuid;
+use failure::{Error, ResultExt};
+
+use std::collections::HashMap;
+
+use db::models::*;
+
+/// Friend requests are pending while `has_accepted` is null, and a single row
+/// represents the friendship in both directions once accepted.
+pub fn find_between(
+    user_id: &Uuid,
+    other_user_id: &Uuid,
+    conn: &PgConnection,
+) -> Result<Option<Friend>, Error> {
+    use db::schema::friends;
+
+    Ok(friends::table
+        .filter(
+            friends::source_user_id
+                .eq(user_id)
+                .and(friends::target_user_id.eq(other_user_id))
+                .or(friends::source_user_id
+                    .eq(other_user_id)
+                    .and(friends::target_user_id.eq(user_id))),
+        )
+        .first(conn)
+        .optional()
+        .context("error finding friend between users")?)
+}
+
+pub fn are_friends(
+    user_id: &Uuid,
+    other_user_id: &Uuid,
+    conn: &PgConnection,
+) -> Result<bool, Error> {
+    Ok(find_between(user_id, other_user_id, conn)?
+        .map(|f| f.has_accepted == Some(true))
+        .unwrap_or(false))
+}
+
+pub fn create_request(
+    source_user_id: &Uuid,
+    target_user_id: &Uuid,
+    conn: &PgConnection,
+) -> Result<Friend, Error> {
+    use db::schema::friends;
+
+    Ok(diesel::insert_into(friends::table)
+        .values(&NewFriend {
+            source_user_id: *source_user_id,
+            target_user_id: *target_user_id,
+            has_accepted: None,
+        })
+        .get_result(conn)
+        .context("error creating friend request")?)
+}
+
+/// Accepts a pending request sent to the user.
+pub fn accept(user_id: &Uuid, id: &Uuid, conn: &PgConnection) -> Result<Option<Friend>, Error> {
+    use db::schema::friends;
+
+    Ok(diesel::update(
+        friends::table
+            .find(id)
+            .filter(friends::target_user_id.eq(user_id))
+            .filter(friends::has_accepted.is_null()),
+    ).set(friends::has_accepted.eq(true))
+        .get_result(conn)
+        .optional()
+        .context("error accepting friend request")?)
+}
+
+/// Declines a pending request sent to the user, removing it so it can be sent
+/// again later.
+pub fn decline(user_id: &Uuid, id: &Uuid, conn: &PgConnection) -> Result<Option<Friend>, Error> {
+    use db::schema::friends;
+
+    Ok(diesel::delete(
+        friends::table
+            .find(id)
+            .filter(friends::target_user_id.eq(user_id))
+            .filter(friends::has_accepted.is_null()),
+    ).get_result(conn)
+        .optional()
+        .context("error declining friend request")?)
+}
+
+/// Removes a friend, or cancels a request the user sent.
+pub fn remove(user_id: &Uuid, id: &Uuid, conn: &PgConnection) -> Result<Option<Friend>, Error> {
+    use db::schema::friends;
+
+    Ok(diesel::delete(
+        friends::table.find(id).filter(
+            friends::source_user_id
+                .eq(user_id)
+                .or(friends::target_user_id
+                    .eq(user_id)
+                    .and(friends::has_accepted.eq(true))),
+        ),
+    ).get_result(conn)
+        .optional()
+        .context("error removing friend")?)
+}
+
+/// Pairs each friend row with the user on the other side of it.
+fn with_other_users(
+    user_id: &Uuid,
+    rows: Vec<Friend>,
+    conn: &PgConnection,
+) -> Result<Vec<(Friend, User)>, Error> {
+    use db::schema::users;
+
+    let other_id = |f: &Friend| {
+        if f.source_user_id == *user_id {
+            f.target_user_id
+        } else {
+            f.source_user_id
+        }
+    };
+    let mut others: HashMap<Uuid, User> = users::table
+        .filter(users::id.eq_any(rows.iter().map(&other_id).collect::<Vec<Uuid>>()))
+        .get_results::<User>(conn)
+        .context("error finding friend users")?
+        .into_iter()
+        .map(|u| (u.id, u))
+        .collect();
+    Ok(rows.into_iter()
+        .filter_map(|f| others.remove(&other_id(&f)).map(|u| (f, u)))
+        .collect())
+}
+
+pub fn find_friends(user_id: &Uuid, conn: &PgConnection) -> Result<Vec<(Friend, User)>, Error> {
+    use db::schema::friends;
+
+    let rows = friends::table
+        .filter(
+            friends::source_user_id
+                .eq(user_id)
+                .or(friends::target_user_id.eq(user_id)),
+        )
+        .filter(friends::has_accepted.eq(true))
+        .get_results(conn)
+        .context("error finding friends")?;
+    let mut friends = with_other_users(user_id, rows, conn)?;
+    friends.sort_by(|a, b| a.1.name.to_lowercase().cmp(&b.1.name.to_lowercase()));
+    Ok(friends)
+}
+
+/// Pending requests sent to the user.
+pub fn find_incoming(user_id: &Uuid, conn: &PgConnection) -> Result<Vec<(Friend, User)>, Error> {
+    use db::schema::friends;
+
+    let rows = friends::table
+        .filter(friends::target_user_id.eq(user_id))
+        .filter(friends::has_accepted.is_null())
+        .order(friends::created_at)
+        .get_results(conn)
+        .context("error finding incoming friend requests")?;
+    with_other_users(user_id, rows, conn)
+}
+
+/// Pending requests the user has sent.
+pub fn find_outgoing(user_id: &Uuid, conn: &PgConnection) -> Result<Vec<(Friend, User)>, Error> {
+    use db::schema::friends;
+
+    let rows = friends::table
+        .filter(friends::source_user_id.eq(user_id))
+        .filter(friends::has_accepted.is_null())
+        .order(friends::created_at)
+        .get_results(conn)
+        .context("error finding outgoing friend requests")?;
+    with_other_users(user_id, rows, conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use db::query::*;
+    use super::*;
+
+    #[test]
+    #[ignore]
+    fn friend_request_flow_works() {
+        with_db(|conn| {
+            let user1 = create_user_by_name("blah", conn).unwrap();
+            let user2 = create_user_by_name("egg", conn).unwrap();
+            let request = create_request(&user1.id, &user2.id, conn).unwrap();
+            assert!(!are_friends(&user1.id, &user2.id, conn).unwrap());
+            assert_eq!(1, find_outgoing(&user1.id, conn).unwrap().len());
+            assert_eq!(user1.id, find_incoming(&user2.id, conn).unwrap()[0].1.id);
+
+            // Only the target can accept.
+            assert!(accept(&user1.id, &request.id, conn).unwrap().is_none());
+            assert!(accept(&user2.id, &request.id, conn).unwrap().is_some());
+            assert!(are_friends(&user2.id, &user1.id, conn).unwrap());
+            assert_eq!(user2.id, find_friends(&user1.id, conn).unwrap()[0].1.id);
+            assert_eq!(user1.id, find_friends(&user2.id, conn).unwrap()[0].1.id);
+
+            assert!(remove(&user2.id, &request.id, conn).unwrap().is_some());
+            assert!(find_friends(&user1.id, conn).unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn decline_works() {
+        with_db(|conn| {
+            let user1 = create_user_by_name("blah", conn).unwrap();
+            let user2 = create_user_by_name("egg", conn).unwrap();
+            let request = create_request(&user1.id, &user2.id, conn).unwrap();
+            assert!(decline(&user2.id, &request.id, conn).unwrap().is_some());
+            assert!(find_between(&user1.id, &user2.id, conn).unwrap().is_none());
+        });
+    }
+}
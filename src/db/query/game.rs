@@ -2,9 +2,11 @@ use diesel;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use uuid::Uuid;
+use chrono::{NaiveDateTime, Utc};
 use failure::{Error, ResultExt};
 
 use db::models::*;
+use db::time_control::TimeControl;
 
 pub fn update_chat_id(
     game_id: &Uuid,
@@ -33,3 +35,234 @@ pub fn update_restarted_game_id(
         .optional()
         .context("error updating restarted_game_id for game")?)
 }
+
+pub fn update_has_accepted(
+    game_player_id: &Uuid,
+    has_accepted: bool,
+    conn: &PgConnection,
+) -> Result<Option<GamePlayer>, Error> {
+    use db::schema::game_players;
+
+    Ok(diesel::update(game_players::table.find(game_player_id))
+        .set(game_players::has_accepted.eq(has_accepted))
+        .get_result(conn)
+        .optional()
+        .context("error updating has_accepted for game player")?)
+}
+
+pub fn all_players_accepted(game_id: &Uuid, conn: &PgConnection) -> Result<bool, Error> {
+    use diesel::dsl::count;
+    use db::schema::game_players;
+
+    let not_accepted: i64 = game_players::table
+        .select(count(game_players::id))
+        .filter(game_players::game_id.eq(game_id))
+        .filter(game_players::has_accepted.eq(false))
+        .get_result(conn)
+        .context("error counting players who haven't accepted")?;
+    Ok(not_accepted == 0)
+}
+
+/// Cancels a game by finishing it without any placings, so ratings aren't affected.
+pub fn cancel(game_id: &Uuid, conn: &PgConnection) -> Result<Option<Game>, Error> {
+    conn.transaction(|| {
+        super::update_game_whose_turn(game_id, &[], conn)?;
+        super::update_game_is_finished(game_id, true, conn)
+    })
+}
+
+pub fn find_by_chat_id(chat_id: &Uuid, conn: &PgConnection) -> Result<Option<Game>, Error> {
+    use db::schema::games;
+
+    Ok(games::table
+        .filter(games::chat_id.eq(chat_id))
+        .first(conn)
+        .optional()
+        .context("error finding game by chat_id")?)
+}
+
+pub fn find_player_by_email_token(
+    email_token: &Uuid,
+    conn: &PgConnection,
+) -> Result<Option<GamePlayer>, Error> {
+    use db::schema::game_players;
+
+    Ok(game_players::table
+        .filter(game_players::email_token.eq(email_token))
+        .first(conn)
+        .optional()
+        .context("error finding game player by email token")?)
+}
+
+/// Sets the turn time controls for a new game, filling each player's time bank.
+pub fn update_time_control(
+    game_id: &Uuid,
+    time_control: &TimeControl,
+    conn: &PgConnection,
+) -> Result<(Game, Vec<GamePlayer>), Error> {
+    use db::schema::{game_players, games};
+
+    conn.transaction(|| {
+        let game = diesel::update(games::table.find(game_id))
+            .set((
+                games::turn_days.eq(time_control.turn_days),
+                games::time_bank_hours.eq(time_control.time_bank_hours),
+                games::grace_hours.eq(time_control.grace_hours),
+                games::timeout_penalty.eq(time_control.timeout_penalty.to_string()),
+            ))
+            .get_result(conn)
+            .context("error updating game time control")?;
+        let mut game_players: Vec<GamePlayer> = diesel::update(
+            game_players::table.filter(game_players::game_id.eq(game_id)),
+        ).set(game_players::time_bank_secs.eq(time_control.time_bank_hours * 3600))
+            .get_results(conn)
+            .context("error updating game player time banks")?;
+        game_players.sort_by_key(|gp| gp.position);
+        Ok((game, game_players))
+    })
+}
+
+/// Players whose turn is on the clock in unfinished games with a turn time limit. When
+/// `require_accept` is set, games still waiting on players to accept are skipped as nobody can
+/// play them yet.
+pub fn find_timed_turns(
+    require_accept: bool,
+    conn: &PgConnection,
+) -> Result<Vec<(GamePlayer, Game)>, Error> {
+    use db::schema::{game_players, games};
+
+    let mut query = game_players::table
+        .inner_join(games::table)
+        .filter(games::is_finished.eq(false))
+        .filter(games::turn_days.is_not_null())
+        .filter(game_players::is_turn.eq(true))
+        .filter(game_players::resigned_at.is_null())
+        .into_boxed();
+    if require_accept {
+        let awaiting_accept = game_players::table
+            .select(game_players::game_id)
+            .filter(game_players::has_accepted.eq(false));
+        query = query.filter(games::id.ne_all(awaiting_accept));
+    }
+    Ok(query
+        .get_results(conn)
+        .context("error finding timed turns")?)
+}
+
+/// Starts the clock from `at` for the players whose turn it is, used once everyone has accepted
+/// the game.
+pub fn start_turn_clocks(
+    game_id: &Uuid,
+    at: NaiveDateTime,
+    conn: &PgConnection,
+) -> Result<Vec<GamePlayer>, Error> {
+    use db::schema::game_players;
+
+    Ok(diesel::update(
+        game_players::table
+            .filter(game_players::game_id.eq(game_id))
+            .filter(game_players::is_turn.eq(true)),
+    ).set((
+        game_players::is_turn_at.eq(at),
+        game_players::turn_warned_at.eq(None::<NaiveDateTime>),
+    ))
+        .get_results(conn)
+        .context("error starting turn clocks")?)
+}
+
+pub fn mark_turn_warned(
+    game_player_id: &Uuid,
+    conn: &PgConnection,
+) -> Result<Option<GamePlayer>, Error> {
+    use db::schema::game_players;
+
+    Ok(diesel::update(game_players::table.find(game_player_id))
+        .set(game_players::turn_warned_at.eq(Utc::now().naive_utc()))
+        .get_result(conn)
+        .optional()
+        .context("error marking turn warned")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use db::query::*;
+    use db::time_control::TimeoutPenalty;
+    use super::*;
+
+    #[test]
+    #[ignore]
+    fn all_players_accepted_works() {
+        with_db(|conn| {
+            let game_extended = create_test_game(2, conn);
+            assert!(!all_players_accepted(&game_extended.game.id, conn).unwrap());
+            for gptu in &game_extended.game_players {
+                update_has_accepted(&gptu.game_player.id, true, conn).unwrap();
+            }
+            assert!(all_players_accepted(&game_extended.game.id, conn).unwrap());
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn time_control_works() {
+        with_db(|conn| {
+            let game_extended = create_test_game(2, conn);
+            let game_id = game_extended.game.id;
+            assert!(find_timed_turns(false, conn).unwrap().is_empty());
+            let (game, game_players) = update_time_control(
+                &game_id,
+                &TimeControl {
+                    turn_days: 3,
+                    time_bank_hours: 2,
+                    grace_hours: 1,
+                    timeout_penalty: TimeoutPenalty::Eliminate,
+                },
+                conn,
+            ).unwrap();
+            assert_eq!(Some(3), game.turn_days);
+            assert_eq!("eliminate", game.timeout_penalty);
+            assert!(game_players.iter().all(|gp| gp.time_bank_secs == 7200));
+
+            update_game_whose_turn(&game_id, &[0], conn).unwrap();
+            let timed = find_timed_turns(false, conn).unwrap();
+            assert_eq!(1, timed.len());
+            assert_eq!(0, timed[0].0.position);
+            assert!(mark_turn_warned(&timed[0].0.id, conn)
+                .unwrap()
+                .unwrap()
+                .turn_warned_at
+                .is_some());
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn find_timed_turns_skips_games_awaiting_accept() {
+        with_db(|conn| {
+            let game_extended = create_test_game(2, conn);
+            let game_id = game_extended.game.id;
+            update_time_control(
+                &game_id,
+                &TimeControl {
+                    turn_days: 1,
+                    time_bank_hours: 0,
+                    grace_hours: 0,
+                    timeout_penalty: TimeoutPenalty::Concede,
+                },
+                conn,
+            ).unwrap();
+            update_game_whose_turn(&game_id, &[0], conn).unwrap();
+            let gp_id = game_extended.game_players[0].game_player.id;
+            update_has_accepted(&gp_id, true, conn).unwrap();
+            assert!(find_timed_turns(true, conn).unwrap().is_empty());
+
+            update_has_accepted(&game_extended.game_players[1].game_player.id, true, conn)
+                .unwrap();
+            start_turn_clocks(&game_id, test_now(), conn).unwrap();
+            let timed = find_timed_turns(true, conn).unwrap();
+            assert_eq!(1, timed.len());
+            assert_eq!(gp_id, timed[0].0.id);
+            assert_eq!(test_now(), timed[0].0.is_turn_at);
+        });
+    }
+}
@@ -0,0 +1,121 @@
+use diesel;
+use diesel::dsl::{count, max};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use uuid::Uuid;
+use failure::{Error, ResultExt};
+
+use db::models::*;
+use db::history::HistoryAction;
+
+/// Appends the game's current state to its history, along with who changed it and how. Should
+/// be called after the game and its players have been updated, and locks the game so entries are
+/// numbered one at a time.
+pub fn record(
+    game_id: &Uuid,
+    game_player_id: Option<&Uuid>,
+    action: HistoryAction,
+    command: Option<&str>,
+    conn: &PgConnection,
+) -> Result<GameHistoryEntry, Error> {
+    use db::schema::game_history_entries;
+
+    conn.transaction(|| {
+        let game = super::lock_game(game_id, conn)?
+            .ok_or_else::<Error, _>(|| format_err!("could not find game"))?;
+        let whose_turn: Vec<i32> = super::find_game_players_by_game(game_id, conn)?
+            .iter()
+            .filter(|gp| gp.is_turn)
+            .map(|gp| gp.position)
+            .collect();
+        let last_sequence: Option<i32> = game_history_entries::table
+            .select(max(game_history_entries::sequence))
+            .filter(game_history_entries::game_id.eq(game_id))
+            .get_result(conn)
+            .context("error finding last game history entry")?;
+        Ok(diesel::insert_into(game_history_entries::table)
+            .values(&NewGameHistoryEntry {
+                game_id: *game_id,
+                sequence: last_sequence.map_or(0, |s| s + 1),
+                game_player_id: game_player_id.cloned(),
+                action: &action.to_string(),
+                command,
+                game_state: &game.game_state,
+                whose_turn,
+            })
+            .get_result(conn)
+            .context("error creating game history entry")?)
+    })
+}
+
+/// Whether anything has changed the game's state since it started.
+pub fn has_moved_past_start(game_id: &Uuid, conn: &PgConnection) -> Result<bool, Error> {
+    use db::schema::game_history_entries;
+
+    let changes: i64 = game_history_entries::table
+        .select(count(game_history_entries::id))
+        .filter(game_history_entries::game_id.eq(game_id))
+        .filter(game_history_entries::action.ne(HistoryAction::Start.to_string()))
+        .get_result(conn)
+        .context("error counting game history entries")?;
+    Ok(changes > 0)
+}
+
+/// Every recorded state of the game, oldest first.
+pub fn find_by_game(game_id: &Uuid, conn: &PgConnection) -> Result<Vec<GameHistoryEntry>, Error> {
+    use db::schema::game_history_entries;
+
+    Ok(game_history_entries::table
+        .filter(game_history_entries::game_id.eq(game_id))
+        .order(game_history_entries::sequence)
+        .get_results(conn)
+        .context("error finding game history entries")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use db::query::*;
+    use super::*;
+
+    #[test]
+    #[ignore]
+    fn history_works() {
+        with_db(|conn| {
+            let game_extended = create_test_game(2, conn);
+            let game_id = game_extended.game.id;
+            let player_id = game_extended.game_players[0].game_player.id;
+            record(&game_id, None, HistoryAction::Start, None, conn).unwrap();
+            assert!(!has_moved_past_start(&game_id, conn).unwrap());
+
+            update_game(
+                &game_id,
+                &NewGame {
+                    game_version_id: game_extended.game.game_version_id,
+                    is_finished: false,
+                    game_state: "moved",
+                },
+                conn,
+            ).unwrap();
+            update_game_whose_turn(&game_id, &[1], conn).unwrap();
+            record(
+                &game_id,
+                Some(&player_id),
+                HistoryAction::Command,
+                Some("play"),
+                conn,
+            ).unwrap();
+
+            assert!(has_moved_past_start(&game_id, conn).unwrap());
+            let history = find_by_game(&game_id, conn).unwrap();
+            assert_eq!(2, history.len());
+            assert_eq!(vec![0, 1], history.iter().map(|h| h.sequence).collect::<Vec<i32>>());
+            assert_eq!(None, history[0].game_player_id);
+            assert_eq!(vec![0], history[0].whose_turn);
+            assert_eq!(Some(player_id), history[1].game_player_id);
+            assert_eq!("command", history[1].action);
+            assert_eq!(Some("play".to_string()), history[1].command);
+            assert_eq!("moved", history[1].game_state);
+            assert_eq!(vec![1], history[1].whose_turn);
+        });
+    }
+}
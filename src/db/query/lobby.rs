@@ -0,0 +1,235 @@
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use uuid::Uuid;
+use chrono::Utc;
+use failure::{Error, ResultExt};
+
+use db::models::*;
+
+/// Creates an open game with the creator in the first seat.
+pub fn create(
+    game_version_id: &Uuid,
+    creator_id: &Uuid,
+    player_count: i32,
+    conn: &PgConnection,
+) -> Result<OpenGame, Error> {
+    use db::schema::open_games;
+
+    conn.transaction(|| {
+        let open_game: OpenGame = diesel::insert_into(open_games::table)
+            .values(&NewOpenGame {
+                game_version_id: *game_version_id,
+                creator_id: *creator_id,
+                player_count,
+            })
+            .get_result(conn)
+            .context("error creating open game")?;
+        join(&open_game.id, creator_id, conn)?;
+        Ok(open_game)
+    })
+}
+
+pub fn find(id: &Uuid, conn: &PgConnection) -> Result<Option<OpenGame>, Error> {
+    use db::schema::open_games;
+
+    Ok(open_games::table
+        .find(id)
+        .first(conn)
+        .optional()
+        .context("error finding open game")?)
+}
+
+/// Locks the open game until the transaction ends, so players join, leave and start it one at a
+/// time.
+pub fn lock(id: &Uuid, conn: &PgConnection) -> Result<Option<OpenGame>, Error> {
+    use db::schema::open_games;
+
+    Ok(open_games::table
+        .find(id)
+        .for_update()
+        .first(conn)
+        .optional()
+        .context("error locking open game")?)
+}
+
+/// The seated players in the order they joined.
+pub fn find_players(open_game_id: &Uuid, conn: &PgConnection) -> Result<Vec<User>, Error> {
+    use db::schema::{open_game_players, users};
+
+    Ok(open_game_players::table
+        .filter(open_game_players::open_game_id.eq(open_game_id))
+        .inner_join(users::table)
+        .order(open_game_players::created_at)
+        .get_results::<(OpenGamePlayer, User)>(conn)
+        .context("error finding open game players")?
+        .into_iter()
+        .map(|(_, u)| u)
+        .collect())
+}
+
+#[derive(Clone)]
+pub struct OpenGameExtended {
+    pub open_game: OpenGame,
+    pub game_version: GameVersion,
+    pub game_type: GameType,
+    pub players: Vec<User>,
+}
+
+impl OpenGameExtended {
+    pub fn into_public(self) -> PublicOpenGameExtended {
+        PublicOpenGameExtended {
+            open_game: self.open_game,
+            game_version: self.game_version.into_public(),
+            game_type: self.game_type,
+            players: self.players.into_iter().map(|u| u.into_public()).collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PublicOpenGameExtended {
+    pub open_game: PublicOpenGame,
+    pub game_version: PublicGameVersion,
+    pub game_type: PublicGameType,
+    pub players: Vec<PublicUser>,
+}
+
+pub fn find_extended(id: &Uuid, conn: &PgConnection) -> Result<Option<OpenGameExtended>, Error> {
+    use db::schema::{game_types, game_versions, open_games};
+
+    Ok(match open_games::table
+        .find(id)
+        .inner_join(game_versions::table.inner_join(game_types::table))
+        .first::<(OpenGame, (GameVersion, GameType))>(conn)
+        .optional()
+        .context("error finding open game")?
+    {
+        Some((open_game, (game_version, game_type))) => Some(OpenGameExtended {
+            players: find_players(&open_game.id, conn)?,
+            open_game,
+            game_version,
+            game_type,
+        }),
+        None => None,
+    })
+}
+
+/// Open games which haven't started or been cancelled, oldest first.
+pub fn find_open(conn: &PgConnection) -> Result<Vec<OpenGameExtended>, Error> {
+    use db::schema::{game_types, game_versions, open_games};
+
+    open_games::table
+        .filter(open_games::game_id.is_null())
+        .filter(open_games::cancelled_at.is_null())
+        .inner_join(game_versions::table.inner_join(game_types::table))
+        .order(open_games::created_at)
+        .get_results::<(OpenGame, (GameVersion, GameType))>(conn)
+        .context("error finding open games")?
+        .into_iter()
+        .map(|(open_game, (game_version, game_type))| {
+            Ok(OpenGameExtended {
+                players: find_players(&open_game.id, conn)?,
+                open_game,
+                game_version,
+                game_type,
+            })
+        })
+        .collect()
+}
+
+pub fn join(
+    open_game_id: &Uuid,
+    user_id: &Uuid,
+    conn: &PgConnection,
+) -> Result<OpenGamePlayer, Error> {
+    use db::schema::open_game_players;
+
+    Ok(diesel::insert_into(open_game_players::table)
+        .values(&NewOpenGamePlayer {
+            open_game_id: *open_game_id,
+            user_id: *user_id,
+        })
+        .get_result(conn)
+        .context("error joining open game")?)
+}
+
+pub fn leave(
+    open_game_id: &Uuid,
+    user_id: &Uuid,
+    conn: &PgConnection,
+) -> Result<Option<OpenGamePlayer>, Error> {
+    use db::schema::open_game_players;
+
+    Ok(diesel::delete(
+        open_game_players::table
+            .filter(open_game_players::open_game_id.eq(open_game_id))
+            .filter(open_game_players::user_id.eq(user_id)),
+    ).get_result(conn)
+        .optional()
+        .context("error leaving open game")?)
+}
+
+/// Links the open game to the game it started, returning `None` if it had
+/// already been started or cancelled.
+pub fn mark_started(
+    id: &Uuid,
+    game_id: &Uuid,
+    conn: &PgConnection,
+) -> Result<Option<OpenGame>, Error> {
+    use db::schema::open_games;
+
+    Ok(diesel::update(
+        open_games::table
+            .find(id)
+            .filter(open_games::game_id.is_null())
+            .filter(open_games::cancelled_at.is_null()),
+    ).set(open_games::game_id.eq(game_id))
+        .get_result(conn)
+        .optional()
+        .context("error marking open game started")?)
+}
+
+pub fn cancel(id: &Uuid, conn: &PgConnection) -> Result<Option<OpenGame>, Error> {
+    use db::schema::open_games;
+
+    Ok(diesel::update(
+        open_games::table
+            .find(id)
+            .filter(open_games::game_id.is_null())
+            .filter(open_games::cancelled_at.is_null()),
+    ).set(open_games::cancelled_at.eq(Utc::now().naive_utc()))
+        .get_result(conn)
+        .optional()
+        .context("error cancelling open game")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use db::query::*;
+    use super::*;
+
+    #[test]
+    #[ignore]
+    fn join_and_leave_works() {
+        with_db(|conn| {
+            let game = create_test_game(2, conn);
+            let creator = create_user_by_name("blah", conn).unwrap();
+            let joiner = create_user_by_name("egg", conn).unwrap();
+            let open_game = create(&game.game_version.id, &creator.id, 2, conn).unwrap();
+            assert_eq!(1, find_open(conn).unwrap().len());
+
+            join(&open_game.id, &joiner.id, conn).unwrap();
+            let players = find_players(&open_game.id, conn).unwrap();
+            assert_eq!(2, players.len());
+            assert!(players.iter().any(|u| u.id == joiner.id));
+            assert!(leave(&open_game.id, &joiner.id, conn).unwrap().is_some());
+            assert_eq!(1, find_players(&open_game.id, conn).unwrap().len());
+
+            assert!(mark_started(&open_game.id, &game.game.id, conn).unwrap().is_some());
+            assert!(mark_started(&open_game.id, &game.game.id, conn).unwrap().is_none());
+            assert!(cancel(&open_game.id, conn).unwrap().is_none());
+            assert!(find_open(conn).unwrap().is_empty());
+        });
+    }
+}
@@ -0,0 +1,185 @@
+use diesel;
+use diesel::dsl::count;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use chrono::{Duration, Utc};
+use failure::{Error, ResultExt};
+
+use db::models::*;
+
+lazy_static! {
+    static ref REQUEST_WINDOW: Duration = Duration::hours(1);
+    static ref ATTEMPT_WINDOW: Duration = Duration::minutes(15);
+}
+
+const MAX_REQUESTS_PER_EMAIL: i64 = 5;
+const MAX_REQUESTS_PER_IP: i64 = 20;
+const MAX_FAILED_ATTEMPTS_PER_EMAIL_AND_IP: i64 = 5;
+const MAX_FAILED_ATTEMPTS_PER_IP: i64 = 20;
+
+/// Emails are counted case insensitively so changing case doesn't dodge the limits.
+fn normalise_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+pub fn create_request(
+    email: &str,
+    ip: Option<&str>,
+    conn: &PgConnection,
+) -> Result<LoginRequest, Error> {
+    use db::schema::login_requests;
+
+    let email = &normalise_email(email);
+    Ok(diesel::insert_into(login_requests::table)
+        .values(&NewLoginRequest { email, ip })
+        .get_result(conn)
+        .context("error creating login request")?)
+}
+
+pub fn create_attempt(
+    email: &str,
+    ip: Option<&str>,
+    is_success: bool,
+    conn: &PgConnection,
+) -> Result<LoginAttempt, Error> {
+    use db::schema::login_attempts;
+
+    let email = &normalise_email(email);
+    Ok(diesel::insert_into(login_attempts::table)
+        .values(&NewLoginAttempt {
+            email,
+            ip,
+            is_success,
+        })
+        .get_result(conn)
+        .context("error creating login attempt")?)
+}
+
+/// Whether too many confirmation emails have recently been requested for the email or from the IP.
+pub fn request_limit_exceeded(
+    email: &str,
+    ip: Option<&str>,
+    conn: &PgConnection,
+) -> Result<bool, Error> {
+    use db::schema::login_requests;
+
+    let since = Utc::now().naive_utc() - *REQUEST_WINDOW;
+    let by_email: i64 = login_requests::table
+        .select(count(login_requests::id))
+        .filter(login_requests::email.eq(normalise_email(email)))
+        .filter(login_requests::created_at.gt(since))
+        .get_result(conn)
+        .context("error counting login requests for email")?;
+    if by_email >= MAX_REQUESTS_PER_EMAIL {
+        return Ok(true);
+    }
+    Ok(match ip {
+        Some(ip) => {
+            let by_ip: i64 = login_requests::table
+                .select(count(login_requests::id))
+                .filter(login_requests::ip.eq(ip))
+                .filter(login_requests::created_at.gt(since))
+                .get_result(conn)
+                .context("error counting login requests for IP")?;
+            by_ip >= MAX_REQUESTS_PER_IP
+        }
+        None => false,
+    })
+}
+
+/// Whether there have recently been too many failed confirmation attempts for the email from the
+/// IP, or from the IP overall. Attempts are keyed on the IP so failures from elsewhere can't lock
+/// a user out of their own account.
+pub fn attempt_limit_exceeded(
+    email: &str,
+    ip: Option<&str>,
+    conn: &PgConnection,
+) -> Result<bool, Error> {
+    use db::schema::login_attempts;
+
+    let since = Utc::now().naive_utc() - *ATTEMPT_WINDOW;
+    let mut by_email_query = login_attempts::table
+        .select(count(login_attempts::id))
+        .filter(login_attempts::email.eq(normalise_email(email)))
+        .filter(login_attempts::is_success.eq(false))
+        .filter(login_attempts::created_at.gt(since))
+        .into_boxed();
+    by_email_query = match ip {
+        Some(ip) => by_email_query.filter(login_attempts::ip.eq(ip)),
+        None => by_email_query.filter(login_attempts::ip.is_null()),
+    };
+    let by_email: i64 = by_email_query
+        .get_result(conn)
+        .context("error counting failed login attempts for email")?;
+    if by_email >= MAX_FAILED_ATTEMPTS_PER_EMAIL_AND_IP {
+        return Ok(true);
+    }
+    Ok(match ip {
+        Some(ip) => {
+            let by_ip: i64 = login_attempts::table
+                .select(count(login_attempts::id))
+                .filter(login_attempts::ip.eq(ip))
+                .filter(login_attempts::is_success.eq(false))
+                .filter(login_attempts::created_at.gt(since))
+                .get_result(conn)
+                .context("error counting failed login attempts for IP")?;
+            by_ip >= MAX_FAILED_ATTEMPTS_PER_IP
+        }
+        None => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use db::query::*;
+    use super::*;
+
+    #[test]
+    #[ignore]
+    fn request_limit_exceeded_works() {
+        with_db(|conn| {
+            for _ in 0..MAX_REQUESTS_PER_EMAIL {
+                assert!(!request_limit_exceeded("beefsack@gmail.com", None, conn).unwrap());
+                create_request("BeefSack@gmail.com", Some("127.0.0.1"), conn).unwrap();
+            }
+            assert!(request_limit_exceeded("beefsack@gmail.com", None, conn).unwrap());
+            assert!(!request_limit_exceeded("beefsack+two@gmail.com", None, conn).unwrap());
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn attempt_limit_exceeded_works() {
+        with_db(|conn| {
+            let ip = Some("127.0.0.1");
+            create_attempt("beefsack@gmail.com", ip, true, conn).unwrap();
+            for _ in 0..MAX_FAILED_ATTEMPTS_PER_EMAIL_AND_IP {
+                assert!(!attempt_limit_exceeded("beefsack@gmail.com", ip, conn).unwrap());
+                create_attempt("Beefsack@Gmail.com", ip, false, conn).unwrap();
+            }
+            assert!(attempt_limit_exceeded("beefsack@gmail.com", ip, conn).unwrap());
+            let other_ip = Some("127.0.0.2");
+            assert!(!attempt_limit_exceeded("beefsack@gmail.com", other_ip, conn).unwrap());
+            assert!(!attempt_limit_exceeded("beefsack+two@gmail.com", ip, conn).unwrap());
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn confirmation_cleared_after_failures_from_many_ips() {
+        with_db(|conn| {
+            let email = "beefsack@gmail.com";
+            let code = user_login_request(email, conn).unwrap();
+            for i in 0..MAX_CONFIRMATION_FAILURES {
+                let ip = format!("127.0.0.{}", i);
+                assert!(!attempt_limit_exceeded(email, Some(&ip), conn).unwrap());
+                // Codes never start with a zero, so this is always wrong.
+                assert!(user_login_confirm(email, "000000", conn).unwrap().is_none());
+                create_attempt(email, Some(&ip), false, conn).unwrap();
+            }
+            let (_, user) = find_user_by_email(email, conn).unwrap().unwrap();
+            assert!(user.login_confirmation.is_none());
+            assert!(user_login_confirm(email, &code, conn).unwrap().is_none());
+        });
+    }
+}
@@ -0,0 +1,173 @@
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use chrono::{Duration, NaiveDateTime, Utc};
+use uuid::Uuid;
+use failure::{Error, ResultExt};
+
+use db::models::*;
+
+lazy_static! {
+    /// How long a worker has to deliver an email it has claimed before another worker may.
+    static ref CLAIM_LEASE: Duration = Duration::minutes(10);
+    static ref BACKOFF_BASE: Duration = Duration::seconds(30);
+    static ref BACKOFF_MAX: Duration = Duration::hours(6);
+}
+
+/// Emails which fail this many times are moved to the dead letter state.
+pub const MAX_ATTEMPTS: i32 = 10;
+
+pub fn enqueue(new_email: &NewOutboundEmail, conn: &PgConnection) -> Result<OutboundEmail, Error> {
+    use db::schema::outbound_emails;
+
+    Ok(diesel::insert_into(outbound_emails::table)
+        .values(new_email)
+        .get_result(conn)
+        .context("error enqueuing outbound email")?)
+}
+
+pub fn find_due(limit: i64, conn: &PgConnection) -> Result<Vec<OutboundEmail>, Error> {
+    use db::schema::outbound_emails;
+
+    Ok(outbound_emails::table
+        .filter(outbound_emails::sent_at.is_null())
+        .filter(outbound_emails::failed_at.is_null())
+        .filter(outbound_emails::next_attempt_at.le(Utc::now().naive_utc()))
+        .order(outbound_emails::next_attempt_at)
+        .limit(limit)
+        .get_results(conn)
+        .context("error finding due outbound emails")?)
+}
+
+/// Claims an email for delivery by pushing back its next attempt, returning
+/// false if another worker got to it first.
+pub fn claim(email: &OutboundEmail, conn: &PgConnection) -> Result<bool, Error> {
+    use db::schema::outbound_emails;
+
+    Ok(diesel::update(
+        outbound_emails::table
+            .find(email.id)
+            .filter(outbound_emails::next_attempt_at.eq(email.next_attempt_at)),
+    ).set(outbound_emails::next_attempt_at.eq(Utc::now().naive_utc() + *CLAIM_LEASE))
+        .execute(conn)
+        .context("error claiming outbound email")? == 1)
+}
+
+pub fn mark_sent(id: &Uuid, conn: &PgConnection) -> Result<OutboundEmail, Error> {
+    use db::schema::outbound_emails;
+
+    Ok(diesel::update(outbound_emails::table.find(id))
+        .set(outbound_emails::sent_at.eq(Utc::now().naive_utc()))
+        .get_result(conn)
+        .context("error marking outbound email sent")?)
+}
+
+/// Records a failed delivery, scheduling a retry or moving the email to the
+/// dead letter state once it has run out of attempts.
+pub fn mark_attempt_failed(
+    email: &OutboundEmail,
+    error: &str,
+    conn: &PgConnection,
+) -> Result<OutboundEmail, Error> {
+    use db::schema::outbound_emails;
+
+    let attempts = email.attempts + 1;
+    let now = Utc::now().naive_utc();
+    Ok(diesel::update(outbound_emails::table.find(email.id))
+        .set((
+            outbound_emails::attempts.eq(attempts),
+            outbound_emails::last_error.eq(error),
+            outbound_emails::next_attempt_at.eq(now + backoff(attempts)),
+            outbound_emails::failed_at.eq(if attempts >= MAX_ATTEMPTS {
+                Some(now)
+            } else {
+                None
+            }),
+        ))
+        .get_result(conn)
+        .context("error marking outbound email attempt failed")?)
+}
+
+/// Doubles the delay after each attempt, up to a maximum.
+pub fn backoff(attempts: i32) -> Duration {
+    let exp = attempts.max(1).min(16) as u32 - 1;
+    let delay = *BACKOFF_BASE * 2i32.pow(exp);
+    if delay > *BACKOFF_MAX {
+        *BACKOFF_MAX
+    } else {
+        delay
+    }
+}
+
+pub fn find_failed(conn: &PgConnection) -> Result<Vec<OutboundEmail>, Error> {
+    use db::schema::outbound_emails;
+
+    Ok(outbound_emails::table
+        .filter(outbound_emails::failed_at.is_not_null())
+        .order(outbound_emails::failed_at.desc())
+        .get_results(conn)
+        .context("error finding failed outbound emails")?)
+}
+
+/// Moves a dead letter back into the queue for immediate delivery.
+pub fn retry(id: &Uuid, conn: &PgConnection) -> Result<Option<OutboundEmail>, Error> {
+    use db::schema::outbound_emails;
+
+    Ok(diesel::update(
+        outbound_emails::table
+            .find(id)
+            .filter(outbound_emails::failed_at.is_not_null()),
+    ).set((
+        outbound_emails::attempts.eq(0),
+        outbound_emails::next_attempt_at.eq(Utc::now().naive_utc()),
+        outbound_emails::failed_at.eq(None::<NaiveDateTime>),
+    ))
+        .get_result(conn)
+        .optional()
+        .context("error retrying outbound email")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use db::query::*;
+    use super::*;
+
+    #[test]
+    fn backoff_works() {
+        assert_eq!(Duration::seconds(30), backoff(1));
+        assert_eq!(Duration::seconds(60), backoff(2));
+        assert_eq!(Duration::seconds(240), backoff(4));
+        assert_eq!(Duration::hours(6), backoff(12));
+    }
+
+    #[test]
+    #[ignore]
+    fn dead_letter_works() {
+        with_db(|conn| {
+            let email = enqueue(
+                &NewOutboundEmail {
+                    message_id: "test",
+                    from_address: "play@brdg.me",
+                    to_addresses: &["beefsack@gmail.com".to_string()],
+                    message: "hello",
+                },
+                conn,
+            ).unwrap();
+            assert_eq!(1, find_due(10, conn).unwrap().len());
+            assert!(claim(&email, conn).unwrap());
+            assert!(!claim(&email, conn).unwrap());
+            assert!(find_due(10, conn).unwrap().is_empty());
+
+            let mut failed = email.clone();
+            for _ in 0..MAX_ATTEMPTS {
+                failed = mark_attempt_failed(&failed, "connection refused", conn).unwrap();
+            }
+            assert!(failed.failed_at.is_some());
+            assert_eq!(1, find_failed(conn).unwrap().len());
+
+            assert!(retry(&email.id, conn).unwrap().is_some());
+            assert!(find_failed(conn).unwrap().is_empty());
+            assert_eq!(1, find_due(10, conn).unwrap().len());
+        });
+    }
+}
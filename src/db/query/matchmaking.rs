@@ -0,0 +1,178 @@
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::sql_types::Timestamp;
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+use failure::{Error, ResultExt};
+
+use db::models::*;
+
+/// Queues a user for a game type, taking a snapshot of their current rating. Returns `None` if
+/// they're already queued for the game type.
+pub fn create(
+    user_id: &Uuid,
+    game_type_id: &Uuid,
+    player_count: i32,
+    conn: &PgConnection,
+) -> Result<Option<MatchmakingEntry>, Error> {
+    use db::schema::matchmaking_entries;
+
+    let game_type_user = super::find_or_create_game_type_user(game_type_id, user_id, conn)?;
+    match diesel::insert_into(matchmaking_entries::table)
+        .values(&NewMatchmakingEntry {
+            user_id: *user_id,
+            game_type_id: *game_type_id,
+            player_count,
+            rating: game_type_user.rating,
+        })
+        .get_result(conn)
+    {
+        Ok(entry) => Ok(Some(entry)),
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Ok(None),
+        Err(e) => Err(e).context("error creating matchmaking entry")?,
+    }
+}
+
+/// All entries still waiting for a match, oldest first, locked until the transaction ends.
+/// Entries already locked by another matcher are skipped so they can't be matched twice. Users
+/// who are on vacation at `now` stay queued but aren't matched until they're back.
+pub fn find_queued(
+    now: NaiveDateTime,
+    conn: &PgConnection,
+) -> Result<Vec<MatchmakingEntry>, Error> {
+    Ok(diesel::sql_query(
+        "SELECT matchmaking_entries.*
+        FROM matchmaking_entries
+        WHERE matchmaking_entries.game_id IS NULL
+        AND matchmaking_entries.user_id NOT IN (
+            SELECT user_vacations.user_id
+            FROM user_vacations
+            WHERE user_vacations.starts_at <= $1
+            AND user_vacations.ends_at > $1
+        )
+        ORDER BY matchmaking_entries.created_at
+        FOR UPDATE SKIP LOCKED",
+    ).bind::<Timestamp, _>(now)
+        .load(conn)
+        .context("error finding queued matchmaking entries")?)
+}
+
+pub fn find_queued_for_user(
+    user_id: &Uuid,
+    conn: &PgConnection,
+) -> Result<Vec<MatchmakingEntry>, Error> {
+    use db::schema::matchmaking_entries;
+
+    Ok(matchmaking_entries::table
+        .filter(matchmaking_entries::user_id.eq(user_id))
+        .filter(matchmaking_entries::game_id.is_null())
+        .order(matchmaking_entries::created_at)
+        .get_results(conn)
+        .context("error finding queued matchmaking entries for user")?)
+}
+
+/// Takes a user out of the queue, as long as they haven't been matched yet.
+pub fn remove(
+    user_id: &Uuid,
+    id: &Uuid,
+    conn: &PgConnection,
+) -> Result<Option<MatchmakingEntry>, Error> {
+    use db::schema::matchmaking_entries;
+
+    Ok(diesel::delete(
+        matchmaking_entries::table
+            .find(id)
+            .filter(matchmaking_entries::user_id.eq(user_id))
+            .filter(matchmaking_entries::game_id.is_null()),
+    ).get_result(conn)
+        .optional()
+        .context("error removing matchmaking entry")?)
+}
+
+/// Links queued entries to the game they were matched into, returning how many
+/// were still queued so callers can detect users leaving mid-match.
+pub fn mark_matched(ids: &[Uuid], game_id: &Uuid, conn: &PgConnection) -> Result<usize, Error> {
+    use db::schema::matchmaking_entries;
+
+    Ok(diesel::update(
+        matchmaking_entries::table
+            .filter(matchmaking_entries::id.eq_any(ids))
+            .filter(matchmaking_entries::game_id.is_null()),
+    ).set(matchmaking_entries::game_id.eq(game_id))
+        .execute(conn)
+        .context("error marking matchmaking entries matched")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use db::query::*;
+    use super::*;
+    use chrono::{Duration, Utc};
+
+    #[test]
+    #[ignore]
+    fn queue_works() {
+        with_db(|conn| {
+            let game = create_test_game(2, conn);
+            let user1 = create_user_by_name("blah", conn).unwrap();
+            let user2 = create_user_by_name("egg", conn).unwrap();
+            let game_type_id = game.game_type.id;
+            let entry1 = create(&user1.id, &game_type_id, 2, conn).unwrap().unwrap();
+            let entry2 = create(&user2.id, &game_type_id, 2, conn).unwrap().unwrap();
+            let now = Utc::now().naive_utc();
+            assert_eq!(2, find_queued(now, conn).unwrap().len());
+            assert_eq!(1, find_queued_for_user(&user1.id, conn).unwrap().len());
+
+            // Only the user who queued can remove the entry.
+            assert!(remove(&user1.id, &entry2.id, conn).unwrap().is_none());
+            assert_eq!(
+                2,
+                mark_matched(&[entry1.id, entry2.id], &game.game.id, conn).unwrap()
+            );
+            assert_eq!(
+                0,
+                mark_matched(&[entry1.id, entry2.id], &game.game.id, conn).unwrap()
+            );
+            assert!(find_queued(now, conn).unwrap().is_empty());
+            assert!(remove(&user1.id, &entry1.id, conn).unwrap().is_none());
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn create_returns_none_when_already_queued() {
+        with_db(|conn| {
+            let game = create_test_game(2, conn);
+            let user = create_user_by_name("blah", conn).unwrap();
+            assert!(create(&user.id, &game.game_type.id, 2, conn).unwrap().is_some());
+            // The failed insert aborts the transaction, so this must come last.
+            assert!(create(&user.id, &game.game_type.id, 2, conn).unwrap().is_none());
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn find_queued_skips_users_on_vacation() {
+        with_db(|conn| {
+            let game = create_test_game(2, conn);
+            let user1 = create_user_by_name("blah", conn).unwrap();
+            let user2 = create_user_by_name("egg", conn).unwrap();
+            create(&user1.id, &game.game_type.id, 2, conn).unwrap();
+            let entry2 = create(&user2.id, &game.game_type.id, 2, conn).unwrap().unwrap();
+            let now = Utc::now().naive_utc();
+            vacation::create(&user1.id, now + Duration::days(7), conn).unwrap();
+            assert_eq!(
+                vec![entry2.id],
+                find_queued(now + Duration::hours(1), conn)
+                    .unwrap()
+                    .iter()
+                    .map(|e| e.id)
+                    .collect::<Vec<Uuid>>()
+            );
+            // They're matched again once they're back.
+            assert_eq!(2, find_queued(now + Duration::days(8), conn).unwrap().len());
+        });
+    }
+}
@@ -3,7 +3,7 @@ use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use uuid::Uuid;
 use rand::{self, Rng};
-use chrono::{Duration, Utc};
+use chrono::{Duration, NaiveDateTime, Utc};
 use failure::{Error, ResultExt};
 
 use brdgme_cmd::cli::CliLog;
@@ -18,16 +18,34 @@ use db::color::{self, Color};
 
 #[cfg(test)]
 use db::CONN;
+#[cfg(test)]
+use db::time_control::TimeoutPenalty;
 
+pub mod api_key;
+pub mod block;
 pub mod chat;
+pub mod friend;
 pub mod game;
+pub mod history;
+pub mod lobby;
+pub mod login;
+pub mod mail;
+pub mod matchmaking;
+pub mod notification;
+pub mod proposal;
+pub mod user;
+pub mod vacation;
 
 lazy_static! {
     static ref CONFIRMATION_EXPIRY: Duration = Duration::minutes(30);
     static ref TOKEN_EXPIRY: Duration = Duration::days(30);
+    static ref TOKEN_TOUCH_INTERVAL: Duration = Duration::minutes(5);
     static ref FINISHED_GAME_RELEVANCE: Duration = Duration::days(3);
 }
 
+/// Wrong guesses from any IP before a confirmation is cleared and a new one has to be emailed.
+pub const MAX_CONFIRMATION_FAILURES: i32 = 5;
+
 pub fn create_user_by_name(name: &str, conn: &PgConnection) -> Result<User, Error> {
     use db::schema::users;
     Ok(diesel::insert_into(users::table)
@@ -51,6 +69,9 @@ pub fn find_user(find_id: &Uuid, conn: &PgConnection) -> Result<Option<User>, Er
         .context("error finding user")?)
 }
 
+sql_function!(lower, lower_t, (x: diesel::sql_types::Text) -> diesel::sql_types::Text);
+
+/// Finds a user by any of their emails, ignoring case.
 pub fn find_user_by_email(
     by_email: &str,
     conn: &PgConnection,
@@ -58,7 +79,7 @@ pub fn find_user_by_email(
     use db::schema::{user_emails, users};
 
     Ok(user_emails::table
-        .filter(user_emails::email.eq(by_email))
+        .filter(lower(user_emails::email).eq(by_email.to_lowercase()))
         .limit(1)
         .inner_join(users::table)
         .first::<(UserEmail, User)>(conn)
@@ -119,6 +140,7 @@ pub fn generate_user_login_confirmation(
         .set((
             users::login_confirmation.eq(&code),
             users::login_confirmation_at.eq(Utc::now().naive_utc()),
+            users::login_confirmation_failures.eq(0),
         ))
         .execute(conn)?;
     Ok(code)
@@ -147,18 +169,57 @@ pub fn user_login_confirm(
         Some((_, u)) => u,
         None => return Ok(None),
     };
+    let user_id = user.id;
     Ok(
         match (user.login_confirmation, user.login_confirmation_at) {
             (Some(ref uc), Some(at))
                 if at + *CONFIRMATION_EXPIRY > Utc::now().naive_utc() && uc == confirmation =>
             {
-                Some(create_auth_token(&user.id, conn)?)
+                conn.transaction::<_, Error, _>(|| {
+                    // Confirmations are single use.
+                    clear_user_login_confirmation(&user_id, conn)?;
+                    create_auth_token(&user_id, conn)
+                }).map(Some)?
+            }
+            (Some(_), Some(_)) => {
+                record_login_confirmation_failure(&user_id, conn)?;
+                None
             }
             _ => None,
         },
     )
 }
 
+/// Counts a wrong guess at the user's confirmation, clearing it once there have been
+/// `MAX_CONFIRMATION_FAILURES` so guesses spread over many IPs can't run the code down.
+fn record_login_confirmation_failure(user_id: &Uuid, conn: &PgConnection) -> Result<(), Error> {
+    use db::schema::users;
+
+    let failures: i32 = diesel::update(users::table.find(user_id))
+        .set(users::login_confirmation_failures.eq(users::login_confirmation_failures + 1))
+        .returning(users::login_confirmation_failures)
+        .get_result(conn)
+        .context("error recording login confirmation failure")?;
+    if failures >= MAX_CONFIRMATION_FAILURES {
+        clear_user_login_confirmation(user_id, conn)?;
+    }
+    Ok(())
+}
+
+pub fn clear_user_login_confirmation(user_id: &Uuid, conn: &PgConnection) -> Result<(), Error> {
+    use db::schema::users;
+
+    diesel::update(users::table.find(user_id))
+        .set((
+            users::login_confirmation.eq(None::<String>),
+            users::login_confirmation_at.eq(None::<NaiveDateTime>),
+            users::login_confirmation_failures.eq(0),
+        ))
+        .execute(conn)
+        .context("error clearing user login confirmation")?;
+    Ok(())
+}
+
 pub fn create_auth_token(for_user_id: &Uuid, conn: &PgConnection) -> Result<UserAuthToken, Error> {
     use db::schema::user_auth_tokens;
 
@@ -171,14 +232,9 @@ pub fn create_auth_token(for_user_id: &Uuid, conn: &PgConnection) -> Result<User
 }
 
 pub fn authenticate(search_token: &Uuid, conn: &PgConnection) -> Result<Option<User>, Error> {
-    use db::schema::{user_auth_tokens, users};
+    use db::schema::users;
 
-    let uat: UserAuthToken = match user_auth_tokens::table
-        .find(search_token)
-        .filter(user_auth_tokens::created_at.gt(Utc::now().naive_utc() - *TOKEN_EXPIRY))
-        .first(conn)
-        .optional()?
-    {
+    let uat = match find_valid_user_auth_token(search_token, conn)? {
         Some(v) => v,
         None => return Ok(None),
     };
@@ -189,6 +245,21 @@ pub fn authenticate(search_token: &Uuid, conn: &PgConnection) -> Result<Option<U
         .context("error finding user")?))
 }
 
+pub fn find_valid_user_auth_token(
+    search_token: &Uuid,
+    conn: &PgConnection,
+) -> Result<Option<UserAuthToken>, Error> {
+    use db::schema::user_auth_tokens;
+
+    Ok(user_auth_tokens::table
+        .find(search_token)
+        .filter(user_auth_tokens::created_at.gt(Utc::now().naive_utc() - *TOKEN_EXPIRY))
+        .filter(user_auth_tokens::revoked_at.is_null())
+        .first(conn)
+        .optional()
+        .context("error finding user auth token")?)
+}
+
 pub fn find_valid_user_auth_tokens_for_users(
     user_ids: &[Uuid],
     conn: &PgConnection,
@@ -198,10 +269,64 @@ pub fn find_valid_user_auth_tokens_for_users(
     Ok(user_auth_tokens::table
         .filter(user_auth_tokens::user_id.eq_any(user_ids))
         .filter(user_auth_tokens::created_at.gt(Utc::now().naive_utc() - *TOKEN_EXPIRY))
+        .filter(user_auth_tokens::revoked_at.is_null())
+        .order(user_auth_tokens::last_used_at.desc())
         .get_results(conn)
         .context("error finding user auth tokens for user")?)
 }
 
+/// Whether the token's `last_used_at` is old enough that it should be touched again, so we aren't
+/// writing on every single request.
+pub fn user_auth_token_needs_touch(uat: &UserAuthToken) -> bool {
+    uat.last_used_at + *TOKEN_TOUCH_INTERVAL < Utc::now().naive_utc()
+}
+
+pub fn touch_user_auth_token(
+    token_id: &Uuid,
+    conn: &PgConnection,
+) -> Result<Option<UserAuthToken>, Error> {
+    use db::schema::user_auth_tokens;
+
+    Ok(diesel::update(user_auth_tokens::table.find(token_id))
+        .set(user_auth_tokens::last_used_at.eq(Utc::now().naive_utc()))
+        .get_result(conn)
+        .optional()
+        .context("error updating user auth token last_used_at")?)
+}
+
+pub fn revoke_user_auth_token(
+    user_id: &Uuid,
+    session_id: &Uuid,
+    conn: &PgConnection,
+) -> Result<Option<UserAuthToken>, Error> {
+    use db::schema::user_auth_tokens;
+
+    Ok(diesel::update(
+        user_auth_tokens::table
+            .filter(user_auth_tokens::session_id.eq(session_id))
+            .filter(user_auth_tokens::user_id.eq(user_id))
+            .filter(user_auth_tokens::revoked_at.is_null()),
+    ).set(user_auth_tokens::revoked_at.eq(Utc::now().naive_utc()))
+        .get_result(conn)
+        .optional()
+        .context("error revoking user auth token")?)
+}
+
+pub fn revoke_user_auth_tokens_for_user(
+    user_id: &Uuid,
+    conn: &PgConnection,
+) -> Result<Vec<UserAuthToken>, Error> {
+    use db::schema::user_auth_tokens;
+
+    Ok(diesel::update(
+        user_auth_tokens::table
+            .filter(user_auth_tokens::user_id.eq(user_id))
+            .filter(user_auth_tokens::revoked_at.is_null()),
+    ).set(user_auth_tokens::revoked_at.eq(Utc::now().naive_utc()))
+        .get_results(conn)
+        .context("error revoking user auth tokens for user")?)
+}
+
 pub fn find_game(id: &Uuid, conn: &PgConnection) -> Result<Game, Error> {
     use db::schema::games;
 
@@ -211,6 +336,16 @@ pub fn find_game(id: &Uuid, conn: &PgConnection) -> Result<Game, Error> {
         .context("error finding game")?)
 }
 
+pub fn find_game_type(id: &Uuid, conn: &PgConnection) -> Result<Option<GameType>, Error> {
+    use db::schema::game_types;
+
+    Ok(game_types::table
+        .find(id)
+        .first(conn)
+        .optional()
+        .context("error finding game type")?)
+}
+
 pub fn find_game_version(id: &Uuid, conn: &PgConnection) -> Result<Option<GameVersion>, Error> {
     use db::schema::game_versions;
 
@@ -221,6 +356,24 @@ pub fn find_game_version(id: &Uuid, conn: &PgConnection) -> Result<Option<GameVe
         .context("error finding game version")?)
 }
 
+pub fn find_game_version_type(
+    id: &Uuid,
+    conn: &PgConnection,
+) -> Result<Option<GameVersionType>, Error> {
+    use db::schema::{game_types, game_versions};
+
+    Ok(game_versions::table
+        .find(id)
+        .inner_join(game_types::table)
+        .first::<(GameVersion, GameType)>(conn)
+        .optional()
+        .context("error finding game version and type")?
+        .map(|(game_version, game_type)| GameVersionType {
+            game_version,
+            game_type,
+        }))
+}
+
 pub fn find_game_with_version(
     id: &Uuid,
     conn: &PgConnection,
@@ -235,6 +388,19 @@ pub fn find_game_with_version(
         .context("error finding game")?)
 }
 
+/// Locks the game until the transaction ends, so changes to its state and history are made one
+/// at a time.
+pub fn lock_game(id: &Uuid, conn: &PgConnection) -> Result<Option<Game>, Error> {
+    use db::schema::games;
+
+    Ok(games::table
+        .find(id)
+        .for_update()
+        .first(conn)
+        .optional()
+        .context("error locking game")?)
+}
+
 #[derive(Clone)]
 pub struct GameExtended {
     pub game: Game,
@@ -549,17 +715,56 @@ pub fn update_game_command_success(
     conn: &PgConnection,
 ) -> Result<UpdatedGame, Error> {
     conn.transaction(|| {
+        // Game servers don't know who has resigned, so their turns are skipped here and they
+        // keep the place they resigned with. If only resigned players are left to move, the
+        // game can't go on and ends on points.
+        let left_places = resigned_places(&find_game_players_by_game(game_id, conn)?);
+        let is_resigned = |pos: &usize| left_places.get(*pos).map(|p| p.is_some()).unwrap_or(false);
+        let stalled = !update.is_finished && !whose_turn.is_empty()
+            && whose_turn.iter().all(|pos| is_resigned(pos));
+        let whose_turn = whose_turn
+            .iter()
+            .filter(|pos| !is_resigned(pos))
+            .cloned()
+            .collect::<Vec<usize>>();
+        let mut eliminated = eliminated.to_owned();
+        for (pos, left_place) in left_places.iter().enumerate() {
+            if left_place.is_some() && !eliminated.contains(&pos) {
+                eliminated.push(pos);
+            }
+        }
+        let placings = if stalled {
+            merge_resigned_placings(
+                &left_places,
+                &points.iter().map(|p| -p).collect::<Vec<f32>>(),
+            )
+        } else if !placings.is_empty() && left_places.iter().any(|p| p.is_some()) {
+            merge_resigned_placings(
+                &left_places,
+                &placings.iter().map(|p| *p as f32).collect::<Vec<f32>>(),
+            )
+        } else {
+            placings.to_owned()
+        };
+
         if let Some(game_state) = undo_game_state {
             player_can_undo_set_undo_game_state(game_id, game_player_id, game_state, conn)?;
         } else {
             player_cannot_undo_set_undo_game_state(game_id, conn)?;
         }
         update_game_points(game_id, points, conn)?;
-        let (placings, game_type_users) = update_game_placings(game_id, placings, conn)?;
+        let (placings, game_type_users) = update_game_placings(game_id, &placings, conn)?;
         Ok(UpdatedGame {
-            game: update_game(game_id, update, conn)?,
-            whose_turn: update_game_whose_turn(game_id, whose_turn, conn)?,
-            eliminated: update_game_eliminated(game_id, eliminated, conn)?,
+            game: update_game(
+                game_id,
+                &NewGame {
+                    is_finished: update.is_finished || stalled,
+                    ..*update
+                },
+                conn,
+            )?,
+            whose_turn: update_game_whose_turn(game_id, &whose_turn, conn)?,
+            eliminated: update_game_eliminated(game_id, &eliminated, conn)?,
             placings,
             is_read: update_game_is_read(game_id, &[*game_player_id], conn)?,
             game_type_users,
@@ -575,10 +780,16 @@ pub fn concede_game(
     conn.transaction(|| {
         let game_players = find_game_players_by_game(game_id, conn)
             .context("unable to find game players for concede")?;
-        let placings: Vec<usize> = game_players
-            .iter()
-            .map(|gp| if gp.id != *game_player_id { 1 } else { 2 })
-            .collect();
+        // The conceding player comes last of those still playing, and everyone else still
+        // playing shares first.
+        let mut left_places = resigned_places(&game_players);
+        let playing = left_places.iter().filter(|p| p.is_none()).count();
+        for gp in &game_players {
+            if gp.id == *game_player_id {
+                left_places[gp.position as usize] = Some(playing);
+            }
+        }
+        let placings = merge_resigned_placings(&left_places, &vec![0.0; game_players.len()]);
         let (placings, game_type_users) = update_game_placings(game_id, &placings, conn)?;
         Ok(UpdatedGame {
             game: update_game_is_finished(game_id, true, conn)?,
@@ -591,6 +802,125 @@ pub fn concede_game(
     })
 }
 
+/// Ends a game in a draw, with everyone still playing sharing first place and players who have
+/// resigned keeping their places.
+pub fn draw_game(game_id: &Uuid, conn: &PgConnection) -> Result<UpdatedGame, Error> {
+    conn.transaction(|| {
+        let game_players = find_game_players_by_game(game_id, conn)
+            .context("unable to find game players for draw")?;
+        let placings = merge_resigned_placings(
+            &resigned_places(&game_players),
+            &vec![0.0; game_players.len()],
+        );
+        let (placings, game_type_users) = update_game_placings(game_id, &placings, conn)?;
+        Ok(UpdatedGame {
+            game: update_game_is_finished(game_id, true, conn)?,
+            whose_turn: update_game_whose_turn(game_id, &[], conn)?,
+            eliminated: vec![],
+            placings,
+            is_read: vec![],
+            game_type_users,
+        })
+    })
+}
+
+/// Resigns a player from a game, placing them behind everyone still playing. The rest play on
+/// without them while someone still playing has the turn, otherwise the game ends on points.
+pub fn resign_player(
+    game_id: &Uuid,
+    game_player_id: &Uuid,
+    conn: &PgConnection,
+) -> Result<UpdatedGame, Error> {
+    use db::schema::game_players;
+
+    conn.transaction(|| {
+        let playing = find_game_players_by_game(game_id, conn)
+            .context("unable to find game players for resign")?
+            .iter()
+            .filter(|gp| gp.resigned_at.is_none())
+            .count();
+        let resigned: GamePlayer = diesel::update(
+            game_players::table
+                .find(game_player_id)
+                .filter(game_players::resigned_at.is_null()),
+        ).set((
+            game_players::resigned_at.eq(Utc::now().naive_utc()),
+            game_players::is_eliminated.eq(true),
+            game_players::is_turn.eq(false),
+            game_players::place.eq(playing as i32),
+        ))
+            .get_result(conn)
+            .optional()
+            .context("error resigning game player")?
+            .ok_or_else::<Error, _>(|| format_err!("player has already resigned"))?;
+
+        let game_players = find_game_players_by_game(game_id, conn)?;
+        if playing > 2 && game_players
+            .iter()
+            .any(|gp| gp.resigned_at.is_none() && gp.is_turn)
+        {
+            return Ok(UpdatedGame {
+                game: Some(find_game(game_id, conn)?),
+                whose_turn: vec![],
+                eliminated: vec![resigned],
+                placings: vec![],
+                is_read: vec![],
+                game_type_users: vec![],
+            });
+        }
+        let placings = merge_resigned_placings(
+            &resigned_places(&game_players),
+            &game_players
+                .iter()
+                .map(|gp| -gp.points.unwrap_or(0.0))
+                .collect::<Vec<f32>>(),
+        );
+        let (placings, game_type_users) = update_game_placings(game_id, &placings, conn)?;
+        Ok(UpdatedGame {
+            game: update_game_is_finished(game_id, true, conn)?,
+            whose_turn: update_game_whose_turn(game_id, &[], conn)?,
+            eliminated: vec![resigned],
+            placings,
+            is_read: vec![],
+            game_type_users,
+        })
+    })
+}
+
+/// The places of players who have resigned, indexed by position.
+fn resigned_places(game_players: &[GamePlayer]) -> Vec<Option<usize>> {
+    let mut places = vec![None; game_players.len()];
+    for gp in game_players {
+        if gp.resigned_at.is_some() {
+            if let Some(place) = places.get_mut(gp.position as usize) {
+                *place = gp.place.map(|p| p as usize);
+            }
+        }
+    }
+    places
+}
+
+/// Final placings for a game some players have left. Players who left keep the place they were
+/// given, and everyone else is ranked ahead of them by score, lowest first, with ties sharing a
+/// place.
+fn merge_resigned_placings(left_places: &[Option<usize>], scores: &[f32]) -> Vec<usize> {
+    let score = |pos: usize| scores.get(pos).cloned().unwrap_or(0.0);
+    left_places
+        .iter()
+        .enumerate()
+        .map(|(pos, left_place)| match *left_place {
+            Some(place) => place,
+            None => {
+                1 + left_places
+                    .iter()
+                    .enumerate()
+                    .filter(|&(other, p)| p.is_none() && score(other) < score(pos))
+                    .count()
+            }
+        })
+        .collect()
+}
+
 pub fn update_game_is_finished(
     game_id: &Uuid,
     is_finished: bool,
@@ -604,17 +934,6 @@ pub fn update_game_is_finished(
         .context("error updating game is_finished")?)
 }
 
-pub fn find_player_count_by_game(game_id: &Uuid, conn: &PgConnection) -> Result<i64, Error> {
-    use diesel::dsl::count;
-    use db::schema::game_players;
-
-    Ok(game_players::table
-        .select(count(game_players::id))
-        .filter(game_players::game_id.eq(game_id))
-        .get_result(conn)
-        .context("error getting player count")?)
-}
-
 fn to_i32_vec(from: &[usize]) -> Vec<i32> {
     from.iter().map(|p| *p as i32).collect::<Vec<i32>>()
 }
@@ -965,19 +1284,27 @@ pub fn find_game_player_type_users_by_game(
         .get_result::<(GameVersion, Game)>(conn)
         .context("error finding game version")?;
 
-    game_players::table
+    let players = game_players::table
         .filter(game_players::game_id.eq(game_id))
         .order(game_players::position)
         .inner_join(users::table)
         .get_results::<(GamePlayer, User)>(conn)
-        .context("error finding game players")?
+        .context("error finding game players")?;
+    let user_ids: Vec<Uuid> = players.iter().map(|&(_, ref u)| u.id).collect();
+    let vacations = vacation::find_current_by_users(&user_ids, Utc::now().naive_utc(), conn)?;
+    players
         .into_iter()
         .map(|(gp, u)| {
             let gtu = find_or_create_game_type_user(&game_version.game_type_id, &u.id, conn)?;
+            let on_vacation_until = vacations
+                .iter()
+                .find(|v| v.user_id == u.id)
+                .map(|v| v.ends_at);
             Ok(GamePlayerTypeUser {
                 game_player: gp,
                 user: u,
                 game_type_user: gtu,
+                on_vacation_until,
             })
         })
         .collect()
@@ -1095,7 +1422,7 @@ pub fn find_user_with_primary_email_by_email(
     use db::schema::{user_emails, users};
 
     Ok(match user_emails::table
-        .filter(user_emails::email.eq(search_email))
+        .filter(lower(user_emails::email).eq(search_email.to_lowercase()))
         .first::<UserEmail>(conn)
         .optional()?
     {
@@ -1185,6 +1512,23 @@ pub fn public_game_versions(conn: &PgConnection) -> Result<Vec<GameVersionType>,
         .collect())
 }
 
+/// The newest public version of a game type, used when the player doesn't pick one.
+pub fn find_latest_public_game_version(
+    game_type_id: &Uuid,
+    conn: &PgConnection,
+) -> Result<Option<GameVersion>, Error> {
+    use db::schema::game_versions;
+
+    Ok(game_versions::table
+        .filter(game_versions::game_type_id.eq(game_type_id))
+        .filter(game_versions::is_public.eq(true))
+        .filter(game_versions::is_deprecated.eq(false))
+        .order(game_versions::created_at.desc())
+        .first(conn)
+        .optional()
+        .context("error finding latest public game version")?)
+}
+
 pub fn find_public_game_logs_for_game(
     game_id: &Uuid,
     conn: &PgConnection,
@@ -1288,10 +1632,67 @@ fn create_test_game(players: usize, conn: &PgConnection) -> GameExtended {
     find_game_extended(&created_game.game.id, conn).expect("expected to find game")
 }
 
+/// A fixed time for tests which don't touch the database.
+#[cfg(test)]
+pub fn test_now() -> NaiveDateTime {
+    NaiveDateTime::from_timestamp(1_500_000_000, 0)
+}
+
+/// An unsaved game for tests which don't touch the database, created at `test_now`.
+#[cfg(test)]
+pub fn test_game() -> Game {
+    Game {
+        id: Uuid::new_v4(),
+        created_at: test_now(),
+        updated_at: test_now(),
+        game_version_id: Uuid::new_v4(),
+        is_finished: false,
+        finished_at: None,
+        game_state: "".to_string(),
+        chat_id: None,
+        restarted_game_id: None,
+        turn_days: None,
+        time_bank_hours: 0,
+        grace_hours: 0,
+        timeout_penalty: TimeoutPenalty::Concede.to_string(),
+    }
+}
+
+/// An unsaved player for tests which don't touch the database, whose turn started at
+/// `test_now`.
+#[cfg(test)]
+pub fn test_game_player() -> GamePlayer {
+    GamePlayer {
+        id: Uuid::new_v4(),
+        created_at: test_now(),
+        updated_at: test_now(),
+        game_id: Uuid::new_v4(),
+        user_id: Uuid::new_v4(),
+        position: 0,
+        color: "Green".to_string(),
+        has_accepted: true,
+        is_turn: true,
+        is_turn_at: test_now(),
+        last_turn_at: test_now(),
+        is_eliminated: false,
+        is_read: false,
+        points: None,
+        undo_game_state: None,
+        place: None,
+        rating_change: None,
+        turn_notified_at: None,
+        email_token: Uuid::new_v4(),
+        time_bank_secs: 0,
+        turn_warned_at: None,
+        resigned_at: None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use db::color::Color;
+    use db::time_control::{TimeControl, TimeoutPenalty};
 
     #[test]
     fn rand_code_works() {
@@ -1347,6 +1748,39 @@ mod tests {
                 .expect("error confirming auth")
                 .expect("invalid confirm code");
             assert!(authenticate(&uat.id, conn).unwrap().is_some());
+            assert!(
+                user_login_confirm("beefsack@gmail.com", &confirmation, conn)
+                    .expect("error confirming auth")
+                    .is_none(),
+                "confirmation could be used twice"
+            );
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn revoke_user_auth_token_works() {
+        with_db(|conn| {
+            let (_, user) = create_user_by_email("beefsack@gmail.com", conn).unwrap();
+            let uat1 = create_auth_token(&user.id, conn).unwrap();
+            let uat2 = create_auth_token(&user.id, conn).unwrap();
+            assert!(
+                revoke_user_auth_token(&user.id, &uat1.session_id, conn)
+                    .unwrap()
+                    .is_some()
+            );
+            assert!(authenticate(&uat1.id, conn).unwrap().is_none());
+            assert!(authenticate(&uat2.id, conn).unwrap().is_some());
+            assert_eq!(
+                find_valid_user_auth_tokens_for_users(&[user.id], conn)
+                    .unwrap()
+                    .into_iter()
+                    .map(|uat| uat.id)
+                    .collect::<Vec<Uuid>>(),
+                vec![uat2.id]
+            );
+            revoke_user_auth_tokens_for_user(&user.id, conn).unwrap();
+            assert!(authenticate(&uat2.id, conn).unwrap().is_none());
         });
     }
 
@@ -1382,6 +1816,10 @@ mod tests {
                     .expect("user doesn't exist");
             assert_eq!(user.id, found_user.id);
             assert_eq!("beefsack@gmail.com", user_email.email);
+            let (_, found_user) = find_user_by_email("BeefSack+Two@gmail.com", conn)
+                .expect("error finding user")
+                .expect("user doesn't exist");
+            assert_eq!(user.id, found_user.id);
         });
     }
 
@@ -1600,4 +2038,206 @@ mod tests {
             assert_eq!(ratings, vec![1264, 1200, 1232, 1136, 1168]);
         });
     }
+
+    #[test]
+    fn merge_resigned_placings_works() {
+        assert_eq!(
+            vec![2, 4, 1, 3],
+            merge_resigned_placings(&[None, Some(4), None, Some(3)], &[2.0, 0.0, 1.0, 0.0])
+        );
+        // Ties share a place.
+        assert_eq!(
+            vec![1, 3, 1],
+            merge_resigned_placings(&[None, Some(3), None], &[0.0, 0.0, 0.0])
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn resign_player_works() {
+        with_db(|conn| {
+            let game_extended = create_test_game(3, conn);
+            let game_id = game_extended.game.id;
+            let player_id = |pos: usize| game_extended.game_players[pos].game_player.id;
+            update_game_points(&game_id, &[1.0, 3.0, 2.0], conn).unwrap();
+
+            // Two players are left, so the game goes on without player 0.
+            update_game_whose_turn(&game_id, &[0, 1], conn).unwrap();
+            let updated = resign_player(&game_id, &player_id(0), conn).unwrap();
+            assert!(!updated.game.unwrap().is_finished);
+            assert_eq!(Some(3), updated.eliminated[0].place);
+            assert!(resign_player(&game_id, &player_id(0), conn).is_err());
+
+            let updated = resign_player(&game_id, &player_id(2), conn).unwrap();
+            assert!(updated.game.unwrap().is_finished);
+            let places: Vec<Option<i32>> = find_game_players_by_game(&game_id, conn)
+                .unwrap()
+                .iter()
+                .map(|gp| gp.place)
+                .collect();
+            assert_eq!(vec![Some(3), Some(1), Some(2)], places);
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn time_out_on_turn_eliminates_player() {
+        with_db(|conn| {
+            let game_extended = create_test_game(3, conn);
+            let game_id = game_extended.game.id;
+            let player_id = |pos: usize| game_extended.game_players[pos].game_player.id;
+            game::update_time_control(
+                &game_id,
+                &TimeControl {
+                    turn_days: 1,
+                    time_bank_hours: 0,
+                    grace_hours: 0,
+                    timeout_penalty: TimeoutPenalty::Eliminate,
+                },
+                conn,
+            ).unwrap();
+            update_game_whose_turn(&game_id, &[0, 1], conn).unwrap();
+            update_game_points(&game_id, &[0.0, 1.0, 2.0], conn).unwrap();
+            let timed = game::find_timed_turns(false, conn).unwrap();
+            assert_eq!(
+                vec![player_id(0), player_id(1)],
+                timed.iter().map(|t| t.0.id).collect::<Vec<Uuid>>()
+            );
+
+            // Timing out resigns the player, and the game goes on while someone else has the
+            // turn.
+            let updated = resign_player(&game_id, &player_id(0), conn).unwrap();
+            assert!(!updated.game.unwrap().is_finished);
+            let timed = game::find_timed_turns(false, conn).unwrap();
+            assert_eq!(vec![player_id(1)], timed.iter().map(|t| t.0.id).collect::<Vec<Uuid>>());
+
+            // The game server doesn't know they resigned, so their turns are skipped.
+            let updated = update_game_command_success(
+                &game_id,
+                &player_id(1),
+                &NewGame {
+                    game_version_id: game_extended.game.game_version_id,
+                    is_finished: false,
+                    game_state: "moved",
+                },
+                None,
+                &[0, 2],
+                &[],
+                &[],
+                &[0.0, 1.0, 2.0],
+                conn,
+            ).unwrap();
+            assert!(!updated.game.unwrap().is_finished);
+            let game_players = find_game_players_by_game(&game_id, conn).unwrap();
+            assert_eq!(
+                vec![false, false, true],
+                game_players.iter().map(|gp| gp.is_turn).collect::<Vec<bool>>()
+            );
+            assert_eq!(
+                vec![true, false, false],
+                game_players.iter().map(|gp| gp.is_eliminated).collect::<Vec<bool>>()
+            );
+
+            // Once only the resigned player is left to move the game ends on points.
+            let updated = update_game_command_success(
+                &game_id,
+                &player_id(2),
+                &NewGame {
+                    game_version_id: game_extended.game.game_version_id,
+                    is_finished: false,
+                    game_state: "stalled",
+                },
+                None,
+                &[0],
+                &[],
+                &[],
+                &[0.0, 1.0, 2.0],
+                conn,
+            ).unwrap();
+            assert!(updated.game.unwrap().is_finished);
+            let places: Vec<Option<i32>> = find_game_players_by_game(&game_id, conn)
+                .unwrap()
+                .iter()
+                .map(|gp| gp.place)
+                .collect();
+            assert_eq!(vec![Some(3), Some(2), Some(1)], places);
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn resign_on_sole_turn_ends_game() {
+        with_db(|conn| {
+            let game_extended = create_test_game(3, conn);
+            let game_id = game_extended.game.id;
+            let player_id = |pos: usize| game_extended.game_players[pos].game_player.id;
+            update_game_points(&game_id, &[0.0, 2.0, 1.0], conn).unwrap();
+            update_game_whose_turn(&game_id, &[0], conn).unwrap();
+
+            // Nobody else can move until the resigned player does, so the game ends on points.
+            let updated = resign_player(&game_id, &player_id(0), conn).unwrap();
+            assert!(updated.game.unwrap().is_finished);
+            let places: Vec<Option<i32>> = find_game_players_by_game(&game_id, conn)
+                .unwrap()
+                .iter()
+                .map(|gp| gp.place)
+                .collect();
+            assert_eq!(vec![Some(3), Some(1), Some(2)], places);
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn draw_game_works() {
+        with_db(|conn| {
+            let game_extended = create_test_game(3, conn);
+            let game_id = game_extended.game.id;
+            let updated = draw_game(&game_id, conn).unwrap();
+            assert!(updated.game.unwrap().is_finished);
+            let updated_game_extended = find_game_extended(&game_id, conn).unwrap();
+            for gptu in &updated_game_extended.game_players {
+                assert_eq!(Some(1), gptu.game_player.place);
+                assert_eq!(1200, gptu.game_type_user.rating);
+            }
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn multi_player_concede_works() {
+        with_db(|conn| {
+            let game_extended = create_test_game(4, conn);
+            let game_id = game_extended.game.id;
+            let player_id = |pos: usize| game_extended.game_players[pos].game_player.id;
+
+            update_game_whose_turn(&game_id, &[1], conn).unwrap();
+            let updated = resign_player(&game_id, &player_id(0), conn).unwrap();
+            assert!(!updated.game.unwrap().is_finished);
+            assert!(updated.game_type_users.is_empty());
+
+            let updated = resign_player(&game_id, &player_id(2), conn).unwrap();
+            assert!(!updated.game.unwrap().is_finished);
+
+            // With two left, conceding ends the game.
+            let updated = concede_game(&game_id, &player_id(3), conn).unwrap();
+            assert!(updated.game.unwrap().is_finished);
+            let updated_game_extended = find_game_extended(&game_id, conn).unwrap();
+            assert_eq!(
+                vec![Some(4), Some(1), Some(3), Some(2)],
+                updated_game_extended
+                    .game_players
+                    .iter()
+                    .map(|gptu| gptu.game_player.place)
+                    .collect::<Vec<Option<i32>>>()
+            );
+            assert_eq!(
+                vec![1152, 1248, 1184, 1216],
+                updated_game_extended
+                    .game_players
+                    .iter()
+                    .map(|gptu| gptu.game_type_user.rating)
+                    .collect::<Vec<i32>>()
+            );
+        });
+    }
 }
@@ -0,0 +1,114 @@
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use uuid::Uuid;
+use chrono::{Duration, NaiveDateTime, Utc};
+use failure::{Error, ResultExt};
+
+use db::models::*;
+use db::notification::TurnNotification;
+
+lazy_static! {
+    static ref DIGEST_INTERVAL: Duration = Duration::days(1);
+}
+
+/// Finds players whose turn started before `started_before` and who haven't been notified about
+/// it yet, for users who want to be notified immediately.
+pub fn find_pending_turn_notifications(
+    started_before: NaiveDateTime,
+    conn: &PgConnection,
+) -> Result<Vec<(GamePlayer, User)>, Error> {
+    use db::schema::{game_players, users};
+
+    Ok(game_players::table
+        .inner_join(users::table)
+        .filter(users::turn_notification.eq(TurnNotification::Immediately.to_string()))
+        .filter(game_players::is_turn.eq(true))
+        .filter(game_players::has_accepted.eq(true))
+        .filter(game_players::is_turn_at.lt(started_before))
+        .filter(
+            game_players::turn_notified_at
+                .is_null()
+                .or(game_players::turn_notified_at.lt(game_players::is_turn_at.nullable())),
+        )
+        .get_results(conn)
+        .context("error finding pending turn notifications")?)
+}
+
+pub fn mark_turn_notified(
+    game_player_id: &Uuid,
+    conn: &PgConnection,
+) -> Result<Option<GamePlayer>, Error> {
+    use db::schema::game_players;
+
+    Ok(diesel::update(game_players::table.find(game_player_id))
+        .set(game_players::turn_notified_at.eq(Utc::now().naive_utc()))
+        .get_result(conn)
+        .optional()
+        .context("error marking game player turn notified")?)
+}
+
+pub fn find_users_due_turn_digest(conn: &PgConnection) -> Result<Vec<User>, Error> {
+    use db::schema::users;
+
+    Ok(users::table
+        .filter(users::turn_notification.eq(TurnNotification::Daily.to_string()))
+        .filter(
+            users::turn_digest_at
+                .is_null()
+                .or(users::turn_digest_at.lt(Utc::now().naive_utc() - *DIGEST_INTERVAL)),
+        )
+        .get_results(conn)
+        .context("error finding users due a turn digest")?)
+}
+
+pub fn mark_turn_digest_sent(user_id: &Uuid, conn: &PgConnection) -> Result<Option<User>, Error> {
+    use db::schema::users;
+
+    Ok(diesel::update(users::table.find(user_id))
+        .set(users::turn_digest_at.eq(Utc::now().naive_utc()))
+        .get_result(conn)
+        .optional()
+        .context("error updating user turn_digest_at")?)
+}
+
+pub fn update_turn_notification(
+    user_id: &Uuid,
+    turn_notification: TurnNotification,
+    conn: &PgConnection,
+) -> Result<User, Error> {
+    use db::schema::users;
+
+    Ok(diesel::update(users::table.find(user_id))
+        .set(users::turn_notification.eq(turn_notification.to_string()))
+        .get_result(conn)
+        .context("error updating user turn_notification")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use db::query::*;
+    use super::*;
+
+    #[test]
+    #[ignore]
+    fn find_pending_turn_notifications_works() {
+        with_db(|conn| {
+            let game_extended = create_test_game(2, conn);
+            for gptu in &game_extended.game_players {
+                game::update_has_accepted(&gptu.game_player.id, true, conn).unwrap();
+                update_turn_notification(&gptu.user.id, TurnNotification::Immediately, conn)
+                    .unwrap();
+            }
+            let later = Utc::now().naive_utc() + Duration::minutes(1);
+            let pending = find_pending_turn_notifications(later, conn).unwrap();
+            assert_eq!(pending.len(), 1);
+            mark_turn_notified(&pending[0].0.id, conn).unwrap();
+            assert!(
+                find_pending_turn_notifications(later, conn)
+                    .unwrap()
+                    .is_empty()
+            );
+        });
+    }
+}
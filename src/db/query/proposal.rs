@@ -0,0 +1,242 @@
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use chrono::{NaiveDateTime, Utc};
+use uuid::Uuid;
+use failure::{Error, ResultExt};
+
+use db::models::*;
+use db::proposal::{ProposalKind, ProposalOutcome};
+
+/// Proposes ending a game early, counting the proposer's own vote in favour.
+pub fn create(
+    game_id: &Uuid,
+    game_player_id: &Uuid,
+    kind: ProposalKind,
+    expires_at: NaiveDateTime,
+    conn: &PgConnection,
+) -> Result<(GameProposal, GameProposalVote), Error> {
+    use db::schema::game_proposals;
+
+    conn.transaction(|| {
+        let proposal: GameProposal = diesel::insert_into(game_proposals::table)
+            .values(&NewGameProposal {
+                game_id: *game_id,
+                game_player_id: *game_player_id,
+                kind: &kind.to_string(),
+                expires_at,
+            })
+            .get_result(conn)
+            .context("error creating game proposal")?;
+        let vote = vote(&proposal.id, game_player_id, true, conn)?;
+        Ok((proposal, vote))
+    })
+}
+
+/// The game's proposal which hasn't been settled yet, which may have expired but not been
+/// cleaned up.
+pub fn find_open(game_id: &Uuid, conn: &PgConnection) -> Result<Option<GameProposal>, Error> {
+    use db::schema::game_proposals;
+
+    Ok(game_proposals::table
+        .filter(game_proposals::game_id.eq(game_id))
+        .filter(game_proposals::settled_at.is_null())
+        .first(conn)
+        .optional()
+        .context("error finding open game proposal")?)
+}
+
+/// The game's open proposal, locked until the transaction ends so votes on it are counted one at
+/// a time. Waits for anyone else holding the lock, and returns `None` if they settled it.
+pub fn lock_open(game_id: &Uuid, conn: &PgConnection) -> Result<Option<GameProposal>, Error> {
+    use db::schema::game_proposals;
+
+    Ok(game_proposals::table
+        .filter(game_proposals::game_id.eq(game_id))
+        .filter(game_proposals::settled_at.is_null())
+        .for_update()
+        .first(conn)
+        .optional()
+        .context("error locking open game proposal")?)
+}
+
+/// Unsettled proposals which expired before `now`.
+pub fn find_expired(now: NaiveDateTime, conn: &PgConnection) -> Result<Vec<GameProposal>, Error> {
+    use db::schema::game_proposals;
+
+    Ok(game_proposals::table
+        .filter(game_proposals::settled_at.is_null())
+        .filter(game_proposals::expires_at.le(now))
+        .order(game_proposals::expires_at)
+        .get_results(conn)
+        .context("error finding expired game proposals")?)
+}
+
+pub fn find_votes(
+    game_proposal_id: &Uuid,
+    conn: &PgConnection,
+) -> Result<Vec<GameProposalVote>, Error> {
+    use db::schema::game_proposal_votes;
+
+    Ok(game_proposal_votes::table
+        .filter(game_proposal_votes::game_proposal_id.eq(game_proposal_id))
+        .order(game_proposal_votes::created_at)
+        .get_results(conn)
+        .context("error finding game proposal votes")?)
+}
+
+/// Records a player's vote. Players only get one vote on each proposal.
+pub fn vote(
+    game_proposal_id: &Uuid,
+    game_player_id: &Uuid,
+    is_accepted: bool,
+    conn: &PgConnection,
+) -> Result<GameProposalVote, Error> {
+    use db::schema::game_proposal_votes;
+
+    Ok(diesel::insert_into(game_proposal_votes::table)
+        .values(&NewGameProposalVote {
+            game_proposal_id: *game_proposal_id,
+            game_player_id: *game_player_id,
+            is_accepted,
+        })
+        .get_result(conn)
+        .context("error creating game proposal vote")?)
+}
+
+/// Marks a proposal as settled, returning `None` if it was already settled.
+pub fn settle(
+    id: &Uuid,
+    outcome: ProposalOutcome,
+    conn: &PgConnection,
+) -> Result<Option<GameProposal>, Error> {
+    use db::schema::game_proposals;
+
+    Ok(diesel::update(
+        game_proposals::table
+            .find(id)
+            .filter(game_proposals::settled_at.is_null()),
+    ).set((
+        game_proposals::outcome.eq(outcome.to_string()),
+        game_proposals::settled_at.eq(Utc::now().naive_utc()),
+    ))
+        .get_result(conn)
+        .optional()
+        .context("error settling game proposal")?)
+}
+
+/// Whether the votes so far settle a proposal. A single rejection is enough to reject it,
+/// and it's accepted once everyone still playing has accepted.
+pub fn outcome(
+    game_players: &[GamePlayer],
+    votes: &[GameProposalVote],
+) -> Option<ProposalOutcome> {
+    if votes.iter().any(|v| !v.is_accepted) {
+        return Some(ProposalOutcome::Rejected);
+    }
+    if game_players
+        .iter()
+        .filter(|gp| gp.resigned_at.is_none())
+        .all(|gp| votes.iter().any(|v| v.game_player_id == gp.id))
+    {
+        return Some(ProposalOutcome::Accepted);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use db::query::*;
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    #[ignore]
+    fn proposal_works() {
+        with_db(|conn| {
+            let game_extended = create_test_game(3, conn);
+            let game_id = game_extended.game.id;
+            let player_id = |pos: usize| game_extended.game_players[pos].game_player.id;
+            let now = Utc::now().naive_utc();
+            let (proposal, _) = create(
+                &game_id,
+                &player_id(0),
+                ProposalKind::Draw,
+                now + Duration::hours(1),
+                conn,
+            ).unwrap();
+            assert_eq!(Some(proposal.id), find_open(&game_id, conn).unwrap().map(|p| p.id));
+            assert_eq!(Some(proposal.id), lock_open(&game_id, conn).unwrap().map(|p| p.id));
+            assert!(find_expired(now, conn).unwrap().is_empty());
+            assert_eq!(1, find_expired(now + Duration::hours(2), conn).unwrap().len());
+
+            let game_players = find_game_players_by_game(&game_id, conn).unwrap();
+            vote(&proposal.id, &player_id(1), true, conn).unwrap();
+            assert_eq!(
+                None,
+                outcome(&game_players, &find_votes(&proposal.id, conn).unwrap())
+            );
+            vote(&proposal.id, &player_id(2), true, conn).unwrap();
+            assert_eq!(
+                Some(ProposalOutcome::Accepted),
+                outcome(&game_players, &find_votes(&proposal.id, conn).unwrap())
+            );
+            assert!(
+                settle(&proposal.id, ProposalOutcome::Accepted, conn)
+                    .unwrap()
+                    .is_some()
+            );
+            assert!(
+                settle(&proposal.id, ProposalOutcome::Expired, conn)
+                    .unwrap()
+                    .is_none()
+            );
+            assert!(find_open(&game_id, conn).unwrap().is_none());
+            assert!(lock_open(&game_id, conn).unwrap().is_none());
+
+            // Duplicate votes fail, which aborts the transaction so must come last.
+            assert!(vote(&proposal.id, &player_id(1), true, conn).is_err());
+        });
+    }
+
+    fn game_player(resigned: bool) -> GamePlayer {
+        GamePlayer {
+            is_turn: false,
+            is_eliminated: resigned,
+            resigned_at: if resigned { Some(test_now()) } else { None },
+            ..test_game_player()
+        }
+    }
+
+    fn proposal_vote(game_player: &GamePlayer, is_accepted: bool) -> GameProposalVote {
+        GameProposalVote {
+            id: Uuid::new_v4(),
+            created_at: test_now(),
+            updated_at: test_now(),
+            game_proposal_id: Uuid::new_v4(),
+            game_player_id: game_player.id,
+            is_accepted,
+        }
+    }
+
+    #[test]
+    fn outcome_works() {
+        let players = vec![game_player(false), game_player(false), game_player(true)];
+        assert_eq!(None, outcome(&players, &[proposal_vote(&players[0], true)]));
+        assert_eq!(
+            Some(ProposalOutcome::Rejected),
+            outcome(
+                &players,
+                &[proposal_vote(&players[0], true), proposal_vote(&players[1], false)],
+            )
+        );
+        // Players who have resigned don't get a say.
+        assert_eq!(
+            Some(ProposalOutcome::Accepted),
+            outcome(
+                &players,
+                &[proposal_vote(&players[0], true), proposal_vote(&players[1], true)],
+            )
+        );
+    }
+}
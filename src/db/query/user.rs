@@ -0,0 +1,260 @@
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use uuid::Uuid;
+use chrono::Utc;
+use failure::{Error, ResultExt};
+
+use db::models::*;
+use super::{create_user_email, rand_code, CONFIRMATION_EXPIRY};
+
+pub fn find_by_name(name: &str, conn: &PgConnection) -> Result<Option<User>, Error> {
+    use db::schema::users;
+
+    Ok(users::table
+        .filter(users::name.eq(name))
+        .first(conn)
+        .optional()
+        .context("error finding user by name")?)
+}
+
+pub fn update_name(user_id: &Uuid, name: &str, conn: &PgConnection) -> Result<User, Error> {
+    use db::schema::users;
+
+    Ok(diesel::update(users::table.find(user_id))
+        .set(users::name.eq(name))
+        .get_result(conn)
+        .context("error updating user name")?)
+}
+
+pub fn update_pref_colors(
+    user_id: &Uuid,
+    pref_colors: &[String],
+    conn: &PgConnection,
+) -> Result<User, Error> {
+    use db::schema::users;
+
+    Ok(diesel::update(users::table.find(user_id))
+        .set(users::pref_colors.eq(pref_colors))
+        .get_result(conn)
+        .context("error updating user pref_colors")?)
+}
+
+pub fn find_emails(user_id: &Uuid, conn: &PgConnection) -> Result<Vec<UserEmail>, Error> {
+    use db::schema::user_emails;
+
+    Ok(user_emails::table
+        .filter(user_emails::user_id.eq(user_id))
+        .order((user_emails::is_primary.desc(), user_emails::created_at))
+        .get_results(conn)
+        .context("error finding user emails")?)
+}
+
+pub fn find_pending_emails(
+    user_id: &Uuid,
+    conn: &PgConnection,
+) -> Result<Vec<PendingUserEmail>, Error> {
+    use db::schema::pending_user_emails;
+
+    Ok(pending_user_emails::table
+        .filter(pending_user_emails::user_id.eq(user_id))
+        .filter(pending_user_emails::created_at.gt(Utc::now().naive_utc() - *CONFIRMATION_EXPIRY))
+        .order(pending_user_emails::created_at)
+        .get_results(conn)
+        .context("error finding pending user emails")?)
+}
+
+/// Creates a pending email for the user and returns the confirmation code, replacing any
+/// previous confirmation for the same email.
+pub fn create_pending_email(
+    user_id: &Uuid,
+    email: &str,
+    conn: &PgConnection,
+) -> Result<String, Error> {
+    use db::schema::pending_user_emails;
+
+    conn.transaction(|| {
+        diesel::delete(
+            pending_user_emails::table
+                .filter(pending_user_emails::user_id.eq(user_id))
+                .filter(pending_user_emails::email.eq(email)),
+        ).execute(conn)
+            .context("error deleting existing pending user email")?;
+        let confirmation = rand_code();
+        diesel::insert_into(pending_user_emails::table)
+            .values(&NewPendingUserEmail {
+                user_id: *user_id,
+                email,
+                confirmation: &confirmation,
+            })
+            .execute(conn)
+            .context("error creating pending user email")?;
+        Ok(confirmation)
+    })
+}
+
+/// Turns a pending email into a secondary email for the user if the confirmation is valid.
+pub fn confirm_pending_email(
+    user_id: &Uuid,
+    email: &str,
+    confirmation: &str,
+    conn: &PgConnection,
+) -> Result<Option<UserEmail>, Error> {
+    use db::schema::pending_user_emails;
+
+    let since = Utc::now().naive_utc() - *CONFIRMATION_EXPIRY;
+    conn.transaction(|| {
+        let pending: PendingUserEmail = match pending_user_emails::table
+            .filter(pending_user_emails::user_id.eq(user_id))
+            .filter(pending_user_emails::email.eq(email))
+            .filter(pending_user_emails::created_at.gt(since))
+            .first(conn)
+            .optional()
+            .context("error finding pending user email")?
+        {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+        if pending.confirmation != confirmation {
+            return Ok(None);
+        }
+        diesel::delete(pending_user_emails::table.find(pending.id))
+            .execute(conn)
+            .context("error deleting pending user email")?;
+        Ok(Some(create_user_email(
+            &NewUserEmail {
+                user_id: *user_id,
+                email,
+                is_primary: false,
+            },
+            conn,
+        )?))
+    })
+}
+
+/// Primary emails can't be deleted, another email must be made primary first.
+pub fn delete_email(
+    user_id: &Uuid,
+    id: &Uuid,
+    conn: &PgConnection,
+) -> Result<Option<UserEmail>, Error> {
+    use db::schema::user_emails;
+
+    Ok(diesel::delete(
+        user_emails::table
+            .find(id)
+            .filter(user_emails::user_id.eq(user_id))
+            .filter(user_emails::is_primary.eq(false)),
+    ).get_result(conn)
+        .optional()
+        .context("error deleting user email")?)
+}
+
+pub fn set_primary_email(
+    user_id: &Uuid,
+    id: &Uuid,
+    conn: &PgConnection,
+) -> Result<Option<UserEmail>, Error> {
+    use db::schema::user_emails;
+
+    conn.transaction(|| {
+        if user_emails::table
+            .find(id)
+            .filter(user_emails::user_id.eq(user_id))
+            .first::<UserEmail>(conn)
+            .optional()
+            .context("error finding user email")?
+            .is_none()
+        {
+            return Ok(None);
+        }
+        Ok(diesel::update(user_emails::table.filter(user_emails::user_id.eq(user_id)))
+            .set(user_emails::is_primary.eq(user_emails::id.eq(id)))
+            .get_results::<UserEmail>(conn)
+            .context("error updating primary user email")?
+            .into_iter()
+            .find(|ue| ue.id == *id))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use db::query::*;
+    use super::*;
+
+    #[test]
+    #[ignore]
+    fn update_name_works() {
+        with_db(|conn| {
+            let user = create_user_by_name("blah", conn).unwrap();
+            update_name(&user.id, "beefsack", conn).unwrap();
+            assert!(find_by_name("blah", conn).unwrap().is_none());
+            assert_eq!(
+                find_by_name("beefsack", conn).unwrap().map(|u| u.id),
+                Some(user.id)
+            );
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn update_pref_colors_works() {
+        with_db(|conn| {
+            let user = create_user_by_name("blah", conn).unwrap();
+            let updated =
+                update_pref_colors(&user.id, &["Red".to_string(), "Blue".to_string()], conn)
+                    .unwrap();
+            assert_eq!(updated.pref_colors, vec!["Red", "Blue"]);
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn confirm_pending_email_works() {
+        with_db(|conn| {
+            let (_, user) = create_user_by_email("beefsack@gmail.com", conn).unwrap();
+            let confirmation =
+                create_pending_email(&user.id, "beefsack+two@gmail.com", conn).unwrap();
+            assert!(
+                confirm_pending_email(&user.id, "beefsack+two@gmail.com", "nope", conn)
+                    .unwrap()
+                    .is_none()
+            );
+            let user_email =
+                confirm_pending_email(&user.id, "beefsack+two@gmail.com", &confirmation, conn)
+                    .unwrap()
+                    .expect("expected pending email to be confirmed");
+            assert!(!user_email.is_primary);
+            assert_eq!(
+                find_user_with_primary_email_by_email("beefsack+two@gmail.com", conn)
+                    .unwrap()
+                    .map(|(_, u)| u.id),
+                Some(user.id)
+            );
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn set_primary_email_works() {
+        with_db(|conn| {
+            let (primary, user) = create_user_by_email("beefsack@gmail.com", conn).unwrap();
+            let secondary = create_user_email(
+                &NewUserEmail {
+                    user_id: user.id,
+                    email: "beefsack+two@gmail.com",
+                    is_primary: false,
+                },
+                conn,
+            ).unwrap();
+            assert!(delete_email(&user.id, &primary.id, conn).unwrap().is_none());
+            assert!(
+                set_primary_email(&user.id, &secondary.id, conn)
+                    .unwrap()
+                    .unwrap()
+                    .is_primary
+            );
+            assert!(delete_email(&user.id, &primary.id, conn).unwrap().is_some());
+        });
+    }
+}
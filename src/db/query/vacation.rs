@@ -0,0 +1,152 @@
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use chrono::{Duration, NaiveDateTime};
+use uuid::Uuid;
+use failure::{Error, ResultExt};
+
+use db::models::*;
+use db::time_control;
+
+/// How many days of vacation each user can take in any year.
+pub const ALLOWANCE_DAYS: i64 = 30;
+
+/// Starts a vacation for a user immediately, lasting until `ends_at`.
+pub fn create(
+    user_id: &Uuid,
+    ends_at: NaiveDateTime,
+    conn: &PgConnection,
+) -> Result<UserVacation, Error> {
+    use db::schema::user_vacations;
+
+    Ok(diesel::insert_into(user_vacations::table)
+        .values(&NewUserVacation {
+            user_id: *user_id,
+            ends_at,
+        })
+        .get_result(conn)
+        .context("error creating user vacation")?)
+}
+
+/// The vacation the user is on at `now`, if any.
+pub fn find_current(
+    user_id: &Uuid,
+    now: NaiveDateTime,
+    conn: &PgConnection,
+) -> Result<Option<UserVacation>, Error> {
+    use db::schema::user_vacations;
+
+    Ok(user_vacations::table
+        .filter(user_vacations::user_id.eq(user_id))
+        .filter(user_vacations::starts_at.le(now))
+        .filter(user_vacations::ends_at.gt(now))
+        .first(conn)
+        .optional()
+        .context("error finding current user vacation")?)
+}
+
+/// The vacations any of the users are on at `now`.
+pub fn find_current_by_users(
+    user_ids: &[Uuid],
+    now: NaiveDateTime,
+    conn: &PgConnection,
+) -> Result<Vec<UserVacation>, Error> {
+    use db::schema::user_vacations;
+
+    Ok(user_vacations::table
+        .filter(user_vacations::user_id.eq_any(user_ids))
+        .filter(user_vacations::starts_at.le(now))
+        .filter(user_vacations::ends_at.gt(now))
+        .get_results(conn)
+        .context("error finding current user vacations")?)
+}
+
+/// Vacations which haven't finished by `since`, oldest first.
+pub fn find_by_user_since(
+    user_id: &Uuid,
+    since: NaiveDateTime,
+    conn: &PgConnection,
+) -> Result<Vec<UserVacation>, Error> {
+    use db::schema::user_vacations;
+
+    Ok(user_vacations::table
+        .filter(user_vacations::user_id.eq(user_id))
+        .filter(user_vacations::ends_at.gt(since))
+        .order(user_vacations::starts_at)
+        .get_results(conn)
+        .context("error finding user vacations")?)
+}
+
+/// How much of the user's allowance has been used by vacations in the year up to `now`,
+/// including all of any vacation currently underway.
+pub fn allowance_used(
+    user_id: &Uuid,
+    now: NaiveDateTime,
+    conn: &PgConnection,
+) -> Result<Duration, Error> {
+    let year_ago = now - Duration::days(365);
+    let vacations = find_by_user_since(user_id, year_ago, conn)?;
+    Ok(match vacations.iter().map(|v| v.ends_at).max() {
+        Some(until) => time_control::paused_during(&vacations, year_ago, until),
+        None => Duration::zero(),
+    })
+}
+
+/// Ends the user's current vacation early, returning the unused days to their allowance.
+pub fn end(
+    user_id: &Uuid,
+    now: NaiveDateTime,
+    conn: &PgConnection,
+) -> Result<Option<UserVacation>, Error> {
+    use db::schema::user_vacations;
+
+    Ok(diesel::update(
+        user_vacations::table
+            .filter(user_vacations::user_id.eq(user_id))
+            .filter(user_vacations::starts_at.le(now))
+            .filter(user_vacations::ends_at.gt(now)),
+    ).set(user_vacations::ends_at.eq(now))
+        .get_result(conn)
+        .optional()
+        .context("error ending user vacation")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use db::query::*;
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    #[ignore]
+    fn vacation_works() {
+        with_db(|conn| {
+            let user = create_user_by_name("blah", conn).unwrap();
+            let now = Utc::now().naive_utc();
+            assert!(find_current(&user.id, now, conn).unwrap().is_none());
+            assert_eq!(Duration::zero(), allowance_used(&user.id, now, conn).unwrap());
+
+            let vacation = create(&user.id, now + Duration::days(7), conn).unwrap();
+            let later = now + Duration::days(1);
+            assert_eq!(
+                Some(vacation.id),
+                find_current(&user.id, later, conn).unwrap().map(|v| v.id)
+            );
+            assert_eq!(
+                vec![vacation.id],
+                find_current_by_users(&[user.id], later, conn)
+                    .unwrap()
+                    .iter()
+                    .map(|v| v.id)
+                    .collect::<Vec<Uuid>>()
+            );
+            assert!(allowance_used(&user.id, later, conn).unwrap() >= Duration::days(7));
+
+            // Ending early gives back the days not taken.
+            assert!(end(&user.id, later, conn).unwrap().is_some());
+            assert!(find_current(&user.id, later, conn).unwrap().is_none());
+            assert!(allowance_used(&user.id, later, conn).unwrap() <= Duration::days(1));
+            assert!(end(&user.id, later, conn).unwrap().is_none());
+        });
+    }
+}
@@ -38,6 +38,21 @@ table! {
     }
 }
 
+table! {
+    game_history_entries (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        game_id -> Uuid,
+        sequence -> Int4,
+        game_player_id -> Nullable<Uuid>,
+        action -> Text,
+        command -> Nullable<Text>,
+        game_state -> Text,
+        whose_turn -> Array<Int4>,
+    }
+}
+
 table! {
     game_logs (id) {
         id -> Uuid,
@@ -79,6 +94,36 @@ table! {
         undo_game_state -> Nullable<Text>,
         place -> Nullable<Int4>,
         rating_change -> Nullable<Int4>,
+        turn_notified_at -> Nullable<Timestamp>,
+        email_token -> Uuid,
+        time_bank_secs -> Int4,
+        turn_warned_at -> Nullable<Timestamp>,
+        resigned_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    game_proposal_votes (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        game_proposal_id -> Uuid,
+        game_player_id -> Uuid,
+        is_accepted -> Bool,
+    }
+}
+
+table! {
+    game_proposals (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        game_id -> Uuid,
+        game_player_id -> Uuid,
+        kind -> Text,
+        expires_at -> Timestamp,
+        outcome -> Nullable<Text>,
+        settled_at -> Nullable<Timestamp>,
     }
 }
 
@@ -93,6 +138,10 @@ table! {
         game_state -> Text,
         chat_id -> Nullable<Uuid>,
         restarted_game_id -> Nullable<Uuid>,
+        turn_days -> Nullable<Int4>,
+        time_bank_hours -> Int4,
+        grace_hours -> Int4,
+        timeout_penalty -> Text,
     }
 }
 
@@ -133,12 +182,124 @@ table! {
     }
 }
 
+table! {
+    login_attempts (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        email -> Text,
+        ip -> Nullable<Text>,
+        is_success -> Bool,
+    }
+}
+
+table! {
+    login_requests (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        email -> Text,
+        ip -> Nullable<Text>,
+    }
+}
+
+table! {
+    matchmaking_entries (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        user_id -> Uuid,
+        game_type_id -> Uuid,
+        player_count -> Int4,
+        rating -> Int4,
+        game_id -> Nullable<Uuid>,
+    }
+}
+
+table! {
+    open_game_players (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        open_game_id -> Uuid,
+        user_id -> Uuid,
+    }
+}
+
+table! {
+    open_games (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        game_version_id -> Uuid,
+        creator_id -> Uuid,
+        player_count -> Int4,
+        game_id -> Nullable<Uuid>,
+        cancelled_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    outbound_emails (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        message_id -> Text,
+        from_address -> Text,
+        to_addresses -> Array<Text>,
+        message -> Text,
+        attempts -> Int4,
+        next_attempt_at -> Timestamp,
+        last_error -> Nullable<Text>,
+        sent_at -> Nullable<Timestamp>,
+        failed_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    pending_user_emails (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        user_id -> Uuid,
+        email -> Text,
+        confirmation -> Text,
+    }
+}
+
+table! {
+    user_api_keys (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        user_id -> Uuid,
+        name -> Text,
+        key -> Uuid,
+        scopes -> Array<Text>,
+        last_used_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
 table! {
     user_auth_tokens (id) {
         id -> Uuid,
         created_at -> Timestamp,
         updated_at -> Timestamp,
         user_id -> Uuid,
+        last_used_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+        session_id -> Uuid,
+    }
+}
+
+table! {
+    user_blocks (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        user_id -> Uuid,
+        blocked_user_id -> Uuid,
     }
 }
 
@@ -153,6 +314,17 @@ table! {
     }
 }
 
+table! {
+    user_vacations (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        user_id -> Uuid,
+        starts_at -> Timestamp,
+        ends_at -> Timestamp,
+    }
+}
+
 table! {
     users (id) {
         id -> Uuid,
@@ -162,38 +334,69 @@ table! {
         pref_colors -> Array<Text>,
         login_confirmation -> Nullable<Text>,
         login_confirmation_at -> Nullable<Timestamp>,
+        turn_notification -> Text,
+        turn_digest_at -> Nullable<Timestamp>,
+        login_confirmation_failures -> Int4,
     }
 }
 
 joinable!(chat_messages -> chat_users (chat_user_id));
 joinable!(chat_users -> chats (chat_id));
 joinable!(chat_users -> users (user_id));
+joinable!(game_history_entries -> game_players (game_player_id));
+joinable!(game_history_entries -> games (game_id));
 joinable!(game_log_targets -> game_logs (game_log_id));
 joinable!(game_log_targets -> game_players (game_player_id));
 joinable!(game_logs -> games (game_id));
 joinable!(game_players -> games (game_id));
 joinable!(game_players -> users (user_id));
+joinable!(game_proposal_votes -> game_players (game_player_id));
+joinable!(game_proposal_votes -> game_proposals (game_proposal_id));
+joinable!(game_proposals -> game_players (game_player_id));
+joinable!(game_proposals -> games (game_id));
 joinable!(game_type_users -> game_types (game_type_id));
 joinable!(game_type_users -> users (user_id));
 joinable!(game_versions -> game_types (game_type_id));
 joinable!(games -> chats (chat_id));
 joinable!(games -> game_versions (game_version_id));
+joinable!(matchmaking_entries -> game_types (game_type_id));
+joinable!(matchmaking_entries -> users (user_id));
+joinable!(open_game_players -> open_games (open_game_id));
+joinable!(open_game_players -> users (user_id));
+joinable!(open_games -> game_versions (game_version_id));
+joinable!(pending_user_emails -> users (user_id));
+joinable!(user_api_keys -> users (user_id));
 joinable!(user_auth_tokens -> users (user_id));
+joinable!(user_blocks -> users (user_id));
 joinable!(user_emails -> users (user_id));
+joinable!(user_vacations -> users (user_id));
 
 allow_tables_to_appear_in_same_query!(
     chat_messages,
     chats,
     chat_users,
     friends,
+    game_history_entries,
     game_logs,
     game_log_targets,
     game_players,
+    game_proposal_votes,
+    game_proposals,
     games,
     game_types,
     game_type_users,
     game_versions,
+    login_attempts,
+    login_requests,
+    matchmaking_entries,
+    open_game_players,
+    open_games,
+    outbound_emails,
+    pending_user_emails,
+    user_api_keys,
     user_auth_tokens,
+    user_blocks,
     user_emails,
+    user_vacations,
     users,
 );
@@ -0,0 +1,58 @@
+use failure::Error;
+
+use std::str::FromStr;
+
+/// Scopes limit what an API key is able to do.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Scope {
+    /// Read games and other data.
+    Read,
+    /// Create and play games.
+    Play,
+}
+
+pub static SCOPES: &'static [Scope] = &[Scope::Read, Scope::Play];
+
+impl Scope {
+    pub fn from_strings(from: &[String]) -> Result<Vec<Scope>, Error> {
+        let mut scopes = vec![];
+        for s in from {
+            scopes.push(Scope::from_str(s)?)
+        }
+        Ok(scopes)
+    }
+}
+
+impl ToString for Scope {
+    fn to_string(&self) -> String {
+        match *self {
+            Scope::Read => "read",
+            Scope::Play => "play",
+        }.to_string()
+    }
+}
+
+impl FromStr for Scope {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Ok(match s {
+            "read" => Scope::Read,
+            "play" => Scope::Play,
+            _ => bail!("Invalid scope"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scope_strings_round_trip() {
+        for scope in SCOPES {
+            assert_eq!(*scope, Scope::from_str(&scope.to_string()).unwrap());
+        }
+        assert!(Scope::from_str("admin").is_err());
+    }
+}
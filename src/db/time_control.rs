@@ -0,0 +1,229 @@
+use chrono::{Duration, NaiveDateTime};
+use failure::Error;
+
+use std::cmp;
+use std::str::FromStr;
+
+use db::models::{Game, GamePlayer, UserVacation};
+
+lazy_static! {
+    /// Players are warned this long before they run out of time, or halfway through their turn
+    /// if turns are shorter than twice this.
+    static ref WARNING_LEAD: Duration = Duration::hours(24);
+}
+
+/// What happens to a player who runs out of time.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum TimeoutPenalty {
+    /// The game ends and the player who ran out of time loses.
+    Concede,
+    /// In games with more than two players remaining, the player is eliminated and the rest
+    /// play on. Otherwise this is the same as conceding.
+    Eliminate,
+}
+
+pub static TIMEOUT_PENALTIES: &'static [TimeoutPenalty] =
+    &[TimeoutPenalty::Concede, TimeoutPenalty::Eliminate];
+
+impl ToString for TimeoutPenalty {
+    fn to_string(&self) -> String {
+        match *self {
+            TimeoutPenalty::Concede => "concede",
+            TimeoutPenalty::Eliminate => "eliminate",
+        }.to_string()
+    }
+}
+
+impl FromStr for TimeoutPenalty {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Ok(match s {
+            "concede" => TimeoutPenalty::Concede,
+            "eliminate" => TimeoutPenalty::Eliminate,
+            _ => bail!("Invalid timeout penalty"),
+        })
+    }
+}
+
+/// Turn time controls chosen when a game is created.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct TimeControl {
+    pub turn_days: i32,
+    /// Extra time each player can dip into over the whole game, chess clock style.
+    pub time_bank_hours: i32,
+    /// Time allowed after the deadline before the penalty is applied.
+    pub grace_hours: i32,
+    pub timeout_penalty: TimeoutPenalty,
+}
+
+impl TimeControl {
+    /// The time control an existing game was created with, if any.
+    pub fn from_game(game: &Game) -> Result<Option<TimeControl>, Error> {
+        Ok(match game.turn_days {
+            Some(turn_days) => Some(TimeControl {
+                turn_days,
+                time_bank_hours: game.time_bank_hours,
+                grace_hours: game.grace_hours,
+                timeout_penalty: TimeoutPenalty::from_str(&game.timeout_penalty)?,
+            }),
+            None => None,
+        })
+    }
+}
+
+/// When the player runs out of time on their current turn, including what is left in their
+/// time bank but not the grace period. Time spent `paused` on vacation pushes the deadline
+/// back. `None` if the game has no turn time limit.
+pub fn turn_deadline(
+    game: &Game,
+    game_player: &GamePlayer,
+    paused: Duration,
+) -> Option<NaiveDateTime> {
+    game.turn_days.map(|turn_days| {
+        game_player.is_turn_at + Duration::days(turn_days as i64)
+            + Duration::seconds(game_player.time_bank_secs as i64) + paused
+    })
+}
+
+pub fn warning_at(
+    game: &Game,
+    game_player: &GamePlayer,
+    paused: Duration,
+) -> Option<NaiveDateTime> {
+    match (turn_deadline(game, game_player, paused), game.turn_days) {
+        (Some(deadline), Some(turn_days)) => {
+            Some(deadline - cmp::min(*WARNING_LEAD, Duration::days(turn_days as i64) / 2))
+        }
+        _ => None,
+    }
+}
+
+/// When the timeout penalty is applied.
+pub fn timeout_at(
+    game: &Game,
+    game_player: &GamePlayer,
+    paused: Duration,
+) -> Option<NaiveDateTime> {
+    turn_deadline(game, game_player, paused).map(|deadline| {
+        deadline + Duration::hours(game.grace_hours as i64)
+    })
+}
+
+/// How much of the time between `from` and `to` was spent on vacation. Mirrors the
+/// `spend_time_bank` trigger, which excludes the same time from time banks.
+pub fn paused_during(
+    vacations: &[UserVacation],
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+) -> Duration {
+    vacations.iter().fold(Duration::zero(), |acc, v| {
+        let start = cmp::max(v.starts_at, from);
+        let end = cmp::min(v.ends_at, to);
+        if end > start {
+            acc + end.signed_duration_since(start)
+        } else {
+            acc
+        }
+    })
+}
+
+/// A rough description of how long is left, for warning players.
+pub fn describe_remaining(remaining: Duration) -> String {
+    if remaining.num_hours() >= 48 {
+        format!("{} days", remaining.num_days())
+    } else if remaining.num_hours() >= 2 {
+        format!("{} hours", remaining.num_hours())
+    } else if remaining.num_minutes() >= 2 {
+        format!("{} minutes", remaining.num_minutes())
+    } else {
+        "moments".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::query::{test_game, test_game_player, test_now};
+    use uuid::Uuid;
+
+    fn game(turn_days: Option<i32>, grace_hours: i32) -> Game {
+        Game {
+            turn_days,
+            grace_hours,
+            ..test_game()
+        }
+    }
+
+    fn game_player(time_bank_secs: i32) -> GamePlayer {
+        GamePlayer {
+            time_bank_secs,
+            ..test_game_player()
+        }
+    }
+
+    #[test]
+    fn timeout_penalty_strings_round_trip() {
+        for tp in TIMEOUT_PENALTIES {
+            assert_eq!(*tp, TimeoutPenalty::from_str(&tp.to_string()).unwrap());
+        }
+    }
+
+    #[test]
+    fn deadlines_work() {
+        let gp = game_player(3600);
+        let zero = Duration::zero();
+        assert_eq!(None, turn_deadline(&game(None, 0), &gp, zero));
+        assert_eq!(None, timeout_at(&game(None, 0), &gp, zero));
+
+        let g = game(Some(3), 12);
+        let deadline = test_now() + Duration::days(3) + Duration::hours(1);
+        assert_eq!(Some(deadline), turn_deadline(&g, &gp, zero));
+        assert_eq!(Some(deadline - Duration::hours(24)), warning_at(&g, &gp, zero));
+        assert_eq!(Some(deadline + Duration::hours(12)), timeout_at(&g, &gp, zero));
+        assert_eq!(
+            Some(deadline + Duration::days(2)),
+            turn_deadline(&g, &gp, Duration::days(2))
+        );
+
+        // Short turns are warned halfway through.
+        let g = game(Some(1), 0);
+        assert_eq!(
+            Some(test_now() + Duration::hours(13)),
+            warning_at(&g, &gp, zero)
+        );
+    }
+
+    fn vacation(starts_in_hours: i64, hours: i64) -> UserVacation {
+        let starts_at = test_now() + Duration::hours(starts_in_hours);
+        UserVacation {
+            id: Uuid::new_v4(),
+            created_at: starts_at,
+            updated_at: starts_at,
+            user_id: Uuid::new_v4(),
+            starts_at,
+            ends_at: starts_at + Duration::hours(hours),
+        }
+    }
+
+    #[test]
+    fn paused_during_works() {
+        let to = test_now() + Duration::hours(100);
+        assert_eq!(Duration::zero(), paused_during(&[], test_now(), to));
+        // Only the parts of vacations inside the range count.
+        let vacations = vec![vacation(-10, 20), vacation(50, 10), vacation(95, 48)];
+        assert_eq!(Duration::hours(25), paused_during(&vacations, test_now(), to));
+        assert_eq!(
+            Duration::zero(),
+            paused_during(&[vacation(-30, 10)], test_now(), to)
+        );
+    }
+
+    #[test]
+    fn describe_remaining_works() {
+        assert_eq!("3 days", describe_remaining(Duration::hours(80)));
+        assert_eq!("47 hours", describe_remaining(Duration::minutes(47 * 60 + 59)));
+        assert_eq!("30 minutes", describe_remaining(Duration::minutes(30)));
+        assert_eq!("moments", describe_remaining(Duration::seconds(30)));
+    }
+}
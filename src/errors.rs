@@ -13,6 +13,8 @@ use std::io::Cursor;
 #[derive(Fail, Debug)]
 pub enum ControllerError {
     #[fail(display = "Bad request: {}", message)] BadRequest { message: String },
+    #[fail(display = "Unauthorized: {}", message)] Unauthorized { message: String },
+    #[fail(display = "Too many requests: {}", message)] TooManyRequests { message: String },
     #[fail(display = "Internal error: {}", inner)] Internal { inner: Error },
 }
 
@@ -22,6 +24,18 @@ impl ControllerError {
             message: message.into(),
         }
     }
+
+    pub fn unauthorized<T: Into<String>>(message: T) -> Self {
+        ControllerError::Unauthorized {
+            message: message.into(),
+        }
+    }
+
+    pub fn too_many_requests<T: Into<String>>(message: T) -> Self {
+        ControllerError::TooManyRequests {
+            message: message.into(),
+        }
+    }
 }
 
 impl From<Error> for ControllerError {
@@ -44,27 +58,39 @@ impl From<diesel::result::Error> for ControllerError {
     }
 }
 
+fn message_response<'r>(status: Status, message: &str) -> response::Result<'r> {
+    Ok(Response::build()
+        .status(status)
+        .header(ContentType::Plain)
+        .header(AccessControlAllowOrigin::Any)
+        .header(AccessControlAllowMethods(vec![
+            Method::Get,
+            Method::Post,
+            Method::Put,
+            Method::Delete,
+            Method::Options,
+        ]))
+        .header(AccessControlAllowHeaders(vec![
+            UniCase("Authorization".to_string()),
+            UniCase("Content-Type".to_string()),
+        ]))
+        .header(AccessControlAllowCredentials)
+        .sized_body(Cursor::new(message.to_owned()))
+        .finalize())
+}
+
 impl<'r> Responder<'r> for ControllerError {
     fn respond_to(self, _: &Request) -> response::Result<'r> {
         match self {
-            ControllerError::BadRequest { ref message } => Ok(Response::build()
-                .status(Status::BadRequest)
-                .header(ContentType::Plain)
-                .header(AccessControlAllowOrigin::Any)
-                .header(AccessControlAllowMethods(vec![
-                    Method::Get,
-                    Method::Post,
-                    Method::Put,
-                    Method::Delete,
-                    Method::Options,
-                ]))
-                .header(AccessControlAllowHeaders(vec![
-                    UniCase("Authorization".to_string()),
-                    UniCase("Content-Type".to_string()),
-                ]))
-                .header(AccessControlAllowCredentials)
-                .sized_body(Cursor::new(message.to_owned()))
-                .finalize()),
+            ControllerError::BadRequest { ref message } => {
+                message_response(Status::BadRequest, message)
+            }
+            ControllerError::Unauthorized { ref message } => {
+                message_response(Status::Unauthorized, message)
+            }
+            ControllerError::TooManyRequests { ref message } => {
+                message_response(Status::TooManyRequests, message)
+            }
             ControllerError::Internal { inner } => {
                 error!("{}, {}", inner.cause(), inner.backtrace());
                 Err(Status::InternalServerError)
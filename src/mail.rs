@@ -1,32 +1,136 @@
 use email::MimeMessage;
-use lettre::email::SendableEmail;
+use lettre::email::{SendableEmail, SimpleSendableEmail};
 use lettre::transport::EmailTransport;
 use lettre::transport::file::FileEmailTransport;
-use lettre::transport::smtp::{SmtpTransportBuilder, SUBMISSION_PORT};
+use lettre::transport::smtp::{SecurityLevel, SmtpTransport, SmtpTransportBuilder};
+use diesel::pg::PgConnection;
 use failure::{Error, ResultExt};
+use uuid::Uuid;
 
-use std::env::temp_dir;
-
-use config::{Mail, CONFIG};
-
-pub fn send<T: SendableEmail>(email: T) -> Result<(), Error> {
-    match CONFIG.mail {
-        Mail::File => Ok(FileEmailTransport::new(temp_dir())
-            .send(email)
-            .map(|_| ())
-            .context("unable to send email")?),
-        Mail::Smtp {
-            ref addr,
-            ref user,
-            ref pass,
-        } => Ok(SmtpTransportBuilder::new((addr.as_ref(), SUBMISSION_PORT))
-            .context("could not initialise SMTP transport")?
-            .encrypt()
-            .credentials(user, pass)
-            .build()
-            .send(email)
-            .map(|_| ())
-            .context("unable to send email")?),
+use std::sync::Mutex;
+
+use config::{Mail, SmtpTls, CONFIG};
+use db::query;
+use db::models::{NewOutboundEmail, OutboundEmail};
+
+lazy_static! {
+    static ref CAPTURED: Mutex<Vec<CapturedEmail>> = Mutex::new(vec![]);
+}
+
+/// An email kept in memory by the capture transport.
+#[derive(Debug, Clone)]
+pub struct CapturedEmail {
+    pub from: String,
+    pub to: Vec<String>,
+    pub message: String,
+}
+
+/// Queues an email to be delivered by the mail queue worker, on the caller's connection so it is
+/// only sent if their transaction commits. The capture transport skips the queue so tests can
+/// assert against emails immediately.
+pub fn send<T: SendableEmail>(email: T, conn: &PgConnection) -> Result<(), Error> {
+    let message_id = email.message_id();
+    let from_address = email.from();
+    let to_addresses = email.to();
+    let message = email.message();
+    if let Mail::Capture = CONFIG.mail {
+        return capture(CapturedEmail {
+            from: from_address,
+            to: to_addresses,
+            message,
+        });
+    }
+    query::mail::enqueue(
+        &NewOutboundEmail {
+            message_id: &message_id,
+            from_address: &from_address,
+            to_addresses: &to_addresses,
+            message: &message,
+        },
+        conn,
+    ).context("unable to queue email")?;
+    Ok(())
+}
+
+fn capture(email: CapturedEmail) -> Result<(), Error> {
+    CAPTURED
+        .lock()
+        .map_err::<Error, _>(|e| format_err!("unable to get lock on captured emails: {}", e))?
+        .push(email);
+    Ok(())
+}
+
+/// Captured emails sent to an address.
+pub fn captured_for(address: &str) -> Result<Vec<CapturedEmail>, Error> {
+    Ok(CAPTURED
+        .lock()
+        .map_err::<Error, _>(|e| format_err!("unable to get lock on captured emails: {}", e))?
+        .iter()
+        .filter(|e| e.to.iter().any(|to| to == address))
+        .cloned()
+        .collect())
+}
+
+/// A transport built once and reused for a batch of deliveries.
+pub enum Transport {
+    File(FileEmailTransport),
+    Smtp(SmtpTransport),
+    Capture,
+}
+
+impl Transport {
+    pub fn new() -> Result<Self, Error> {
+        Ok(match CONFIG.mail {
+            Mail::File { ref dir } => Transport::File(FileEmailTransport::new(dir)),
+            Mail::Smtp {
+                ref host,
+                port,
+                tls,
+                ref auth,
+            } => {
+                let mut builder = SmtpTransportBuilder::new((host.as_ref(), port))
+                    .context("could not initialise SMTP transport")?
+                    .security_level(match tls {
+                        SmtpTls::None => SecurityLevel::NeverEncrypt,
+                        SmtpTls::StartTls => SecurityLevel::AlwaysEncrypt,
+                        SmtpTls::Implicit => SecurityLevel::EncryptedWrapper,
+                    });
+                if let Some((ref user, ref pass)) = *auth {
+                    builder = builder.credentials(user, pass);
+                }
+                Transport::Smtp(builder.build())
+            }
+            Mail::Capture => Transport::Capture,
+        })
+    }
+
+    pub fn deliver(&mut self, email: &OutboundEmail) -> Result<(), Error> {
+        let sendable = SimpleSendableEmail::new(
+            &email.from_address,
+            email.to_addresses.clone(),
+            email.message.clone(),
+        );
+        match *self {
+            Transport::File(ref mut t) => t.send(sendable)
+                .map(|_| ())
+                .map_err(|e| format_err!("{:?}", e)),
+            Transport::Smtp(ref mut t) => t.send(sendable)
+                .map(|_| ())
+                .map_err(|e| format_err!("{:?}", e)),
+            Transport::Capture => capture(CapturedEmail {
+                from: email.from_address.to_owned(),
+                to: email.to_addresses.to_owned(),
+                message: email.message.to_owned(),
+            }),
+        }
+    }
+
+    pub fn close(&mut self) {
+        match *self {
+            Transport::File(ref mut t) => t.close(),
+            Transport::Smtp(ref mut t) => t.close(),
+            Transport::Capture => {}
+        }
     }
 }
 
@@ -48,17 +152,159 @@ pub fn html_layout(content: &str) -> String {
     )
 }
 
-pub fn handle_inbound_email(e: &str) {
-    // TODO handle error
-    let parsed = MimeMessage::parse(e).unwrap();
-    let bodies = extract_bodies(&parsed);
-    println!("{} {:?}", bodies.len(), bodies);
+/// An inbound email reduced to the parts needed for play-by-email.
+pub struct InboundEmail {
+    pub from: Option<String>,
+    pub to: Vec<String>,
+    pub body: String,
+}
+
+/// Headers which may contain the address the email was delivered to.
+static RECIPIENT_HEADERS: &'static [&'static str] =
+    &["To", "Cc", "Delivered-To", "X-Original-To", "Envelope-To"];
+
+pub fn parse_inbound_email(e: &str) -> Result<InboundEmail, Error> {
+    let parsed = MimeMessage::parse(e).map_err(|e| format_err!("unable to parse email: {:?}", e))?;
+    Ok(InboundEmail {
+        from: header_value(&parsed, "From").and_then(|f| extract_addresses(&f).pop()),
+        to: RECIPIENT_HEADERS
+            .iter()
+            .filter_map(|h| header_value(&parsed, h))
+            .flat_map(|v| extract_addresses(&v))
+            .collect(),
+        body: extract_text_body(&parsed).unwrap_or_else(String::new),
+    })
+}
+
+fn header_value(mm: &MimeMessage, name: &str) -> Option<String> {
+    mm.headers
+        .get(name.to_string())
+        .and_then(|h| h.get_value::<String>().ok())
+}
+
+/// Finds the first plain text body, falling back to the top level body.
+fn extract_text_body(mm: &MimeMessage) -> Option<String> {
+    let is_text = match header_value(mm, "Content-Type") {
+        Some(ct) => ct.trim().to_lowercase().starts_with("text/plain"),
+        None => mm.children.is_empty(),
+    };
+    if is_text {
+        return Some(mm.decoded_body_string().unwrap_or_else(|_| mm.body.clone()));
+    }
+    mm.children
+        .iter()
+        .filter_map(extract_text_body)
+        .next()
+        .or_else(|| {
+            if mm.children.is_empty() {
+                Some(mm.body.clone())
+            } else {
+                None
+            }
+        })
+}
+
+/// Extracts bare addresses from an address list such as `Bob <bob@example.com>, a@b.c`.
+pub fn extract_addresses(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .filter_map(|part| {
+            let part = part.trim();
+            let addr = match (part.rfind('<'), part.rfind('>')) {
+                (Some(start), Some(end)) if start < end => &part[start + 1..end],
+                _ => part,
+            };
+            let addr = addr.trim();
+            if addr.contains('@') {
+                Some(addr.to_lowercase())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// The address players reply to in order to play a game by email, in the form
+/// `local+token@domain` based on the configured from address.
+pub fn reply_address(token: &Uuid) -> String {
+    let from = extract_addresses(&CONFIG.mail_from)
+        .pop()
+        .unwrap_or_else(|| CONFIG.mail_from.to_owned());
+    match from.find('@') {
+        Some(at) => format!("{}+{}{}", &from[..at], token, &from[at..]),
+        None => format!("{}+{}", from, token),
+    }
+}
+
+/// Finds the play-by-email token in the first recipient address which has one.
+pub fn reply_token(addresses: &[String]) -> Option<Uuid> {
+    addresses.iter().filter_map(|a| address_token(a)).next()
+}
+
+fn address_token(address: &str) -> Option<Uuid> {
+    let local = &address[..address.find('@')?];
+    let plus = local.find('+')?;
+    Uuid::parse_str(&local[plus + 1..]).ok()
+}
+
+/// Strips quoted replies and signatures from an email body, returning the
+/// remaining non-empty lines.
+pub fn strip_quoted(body: &str) -> Vec<String> {
+    let mut lines = vec![];
+    for line in body.lines() {
+        let trimmed = line.trim();
+        if trimmed == "--" || trimmed.starts_with("-----Original Message")
+            || trimmed.starts_with("________________")
+            || (trimmed.starts_with("On ") && trimmed.ends_with("wrote:"))
+        {
+            break;
+        }
+        if trimmed.is_empty() || trimmed.starts_with('>') {
+            continue;
+        }
+        lines.push(trimmed.to_string());
+    }
+    lines
 }
 
-fn extract_bodies(mm: &MimeMessage) -> Vec<String> {
-    let mut bodies: Vec<String> = vec![mm.body.clone()];
-    for c in &mm.children {
-        bodies.extend(extract_bodies(c));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_addresses_works() {
+        assert_eq!(
+            vec!["bob@example.com".to_string(), "alice@example.com".to_string()],
+            extract_addresses("Bob <Bob@example.com>, alice@example.com")
+        );
+        assert!(extract_addresses("undisclosed-recipients:;").is_empty());
+    }
+
+    #[test]
+    fn reply_token_works() {
+        let token = Uuid::new_v4();
+        assert_eq!(
+            Some(token),
+            reply_token(&[
+                "play@brdg.me".to_string(),
+                format!("play+{}@brdg.me", token),
+            ])
+        );
+        assert_eq!(None, reply_token(&["play+nope@brdg.me".to_string()]));
+    }
+
+    #[test]
+    fn strip_quoted_works() {
+        assert_eq!(
+            vec!["play 5".to_string(), "done".to_string()],
+            strip_quoted(
+                "play 5\r\n\r\ndone\r\n\r\nOn Tue, 12 Dec 2017, brdg.me <play@brdg.me> wrote:\r\n\
+                 > It's your turn\r\n"
+            )
+        );
+        assert_eq!(
+            vec!["buy 2".to_string()],
+            strip_quoted("> quoted\nbuy 2\n--\nBob")
+        );
     }
-    bodies
 }
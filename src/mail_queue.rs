@@ -0,0 +1,55 @@
+use failure::{Error, ResultExt};
+
+use std::thread;
+use std::time::Duration;
+
+use db::{query, CONN};
+use mail::Transport;
+
+const POLL_INTERVAL_SECS: u64 = 5;
+const BATCH_SIZE: i64 = 50;
+
+pub fn run() {
+    loop {
+        match deliver_due() {
+            // Keep going straight away if there might be more waiting.
+            Ok(delivered) if delivered as i64 >= BATCH_SIZE => continue,
+            Ok(_) => {}
+            Err(e) => warn!("error delivering queued email: {}", e),
+        }
+        thread::sleep(Duration::from_secs(POLL_INTERVAL_SECS));
+    }
+}
+
+/// Delivers a batch of due emails, returning how many were attempted.
+fn deliver_due() -> Result<usize, Error> {
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+    let due = query::mail::find_due(BATCH_SIZE, conn)?;
+    if due.is_empty() {
+        return Ok(0);
+    }
+    let mut transport = Transport::new()?;
+    let mut attempted = 0;
+    for email in &due {
+        if !query::mail::claim(email, conn)? {
+            continue;
+        }
+        attempted += 1;
+        match transport.deliver(email) {
+            Ok(()) => {
+                query::mail::mark_sent(&email.id, conn)?;
+            }
+            Err(e) => {
+                let failed = query::mail::mark_attempt_failed(email, &e.to_string(), conn)?;
+                if failed.failed_at.is_some() {
+                    warn!(
+                        "giving up on email {} after {} attempts: {}",
+                        email.id, failed.attempts, e
+                    );
+                }
+            }
+        }
+    }
+    transport.close();
+    Ok(attempted)
+}
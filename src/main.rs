@@ -41,10 +41,14 @@ mod config;
 mod controller;
 mod db;
 mod mail;
+mod mail_queue;
+mod matchmaker;
 mod game_client;
 mod errors;
 mod websocket;
 mod render;
+mod turn_notifier;
+mod turn_timer;
 
 use std::thread;
 use std::sync::Mutex;
@@ -52,6 +56,12 @@ use std::sync::Mutex;
 fn main() {
     let (pub_queue, pub_queue_tx) = websocket::PubQueue::new();
     thread::spawn(move || pub_queue.run());
+    thread::spawn(turn_notifier::run);
+    thread::spawn(mail_queue::run);
+    let matchmaker_tx = pub_queue_tx.clone();
+    thread::spawn(move || matchmaker::run(matchmaker_tx));
+    let turn_timer_tx = pub_queue_tx.clone();
+    thread::spawn(move || turn_timer::run(turn_timer_tx));
 
     rocket::ignite()
         .manage(Mutex::new(pub_queue_tx))
@@ -60,18 +70,100 @@ fn main() {
             routes![
                 controller::game::create,
                 controller::game::show,
+                controller::game::show_with_query,
                 controller::game::command,
                 controller::game::undo,
                 controller::game::mark_read,
                 controller::game::concede,
                 controller::game::restart,
+                controller::game::accept,
+                controller::game::decline,
+                controller::proposal::show,
+                controller::proposal::create,
+                controller::proposal::vote,
             ],
         )
         .mount(
             "/auth",
-            routes![controller::auth::create, controller::auth::confirm,],
+            routes![
+                controller::auth::create,
+                controller::auth::confirm,
+                controller::auth::logout,
+                controller::auth::sessions,
+                controller::auth::revoke_session,
+                controller::auth::revoke_sessions,
+                controller::auth::api_keys,
+                controller::auth::create_api_key,
+                controller::auth::revoke_api_key,
+            ],
+        )
+        .mount(
+            "/user",
+            routes![
+                controller::user::profile,
+                controller::user::update_profile,
+                controller::user::emails,
+                controller::user::add_email,
+                controller::user::confirm_email,
+                controller::user::delete_email,
+                controller::user::make_primary_email,
+                controller::user::settings,
+                controller::user::update_settings,
+                controller::user::blocks,
+                controller::user::create_block,
+                controller::user::delete_block,
+                controller::user::vacation,
+                controller::user::create_vacation,
+                controller::user::delete_vacation,
+            ],
+        )
+        .mount(
+            "/chat",
+            routes![
+                controller::chat::create_message,
+                controller::chat::messages,
+                controller::chat::messages_with_query,
+                controller::chat::mark_read,
+            ],
+        )
+        .mount(
+            "/friend",
+            routes![
+                controller::friend::index,
+                controller::friend::pending,
+                controller::friend::create,
+                controller::friend::accept,
+                controller::friend::decline,
+                controller::friend::remove,
+            ],
+        )
+        .mount(
+            "/lobby",
+            routes![
+                controller::lobby::index,
+                controller::lobby::create,
+                controller::lobby::join,
+                controller::lobby::leave,
+                controller::lobby::start,
+            ],
+        )
+        .mount(
+            "/matchmaking",
+            routes![
+                controller::matchmaking::index,
+                controller::matchmaking::create,
+                controller::matchmaking::remove,
+            ],
         )
         .mount("/mail", routes![controller::mail::index])
+        .mount(
+            "/admin",
+            routes![
+                controller::admin::failed_emails,
+                controller::admin::retry_email,
+                controller::admin::game_history,
+            ],
+        )
         .mount("/", routes![controller::options, controller::init])
         .launch();
 }
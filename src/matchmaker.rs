@@ -0,0 +1,262 @@
+use diesel::Connection;
+use diesel::pg::PgConnection;
+use chrono::{NaiveDateTime, Utc};
+use uuid::Uuid;
+use failure::{Error, ResultExt};
+
+use std::cmp;
+use std::thread;
+use std::time::Duration;
+use std::sync::mpsc::Sender;
+
+use controller::game::{publish_started_game, start_game, StartedGame};
+use db::{query, CONN};
+use db::models::*;
+use websocket;
+
+const POLL_INTERVAL_SECS: u64 = 10;
+/// Players are always happy to play opponents this close to their own rating.
+const BASE_RATING_RANGE: i32 = 100;
+/// How much further the range extends for each minute spent waiting.
+const RATING_RANGE_PER_MINUTE: i32 = 50;
+const MAX_RATING_RANGE: i32 = 1000;
+
+pub fn run(pub_queue_tx: Sender<websocket::Message>) {
+    loop {
+        if let Err(e) = match_queued(&pub_queue_tx) {
+            warn!("error matching queued players: {}", e);
+        }
+        thread::sleep(Duration::from_secs(POLL_INTERVAL_SECS));
+    }
+}
+
+/// How far from their own rating a player will accept opponents, widening the
+/// longer they've been waiting.
+pub fn rating_range(queued_at: NaiveDateTime, now: NaiveDateTime) -> i32 {
+    let minutes = cmp::max(now.signed_duration_since(queued_at).num_minutes(), 0);
+    cmp::min(
+        BASE_RATING_RANGE as i64 + RATING_RANGE_PER_MINUTE as i64 * minutes,
+        MAX_RATING_RANGE as i64,
+    ) as i32
+}
+
+/// Whether both entries want the same kind of game and are each within the
+/// other's rating range.
+fn compatible(a: &MatchmakingEntry, b: &MatchmakingEntry, now: NaiveDateTime) -> bool {
+    a.game_type_id == b.game_type_id && a.player_count == b.player_count
+        && (a.rating - b.rating).abs()
+            <= cmp::min(rating_range(a.created_at, now), rating_range(b.created_at, now))
+}
+
+/// Groups queued entries into matches. Entries are expected oldest first, so
+/// those who have waited longest get first pick of the closest ratings. The
+/// first entry of each match is the player who waited longest.
+pub fn find_matches<F>(
+    entries: &[MatchmakingEntry],
+    now: NaiveDateTime,
+    mut is_blocked: F,
+) -> Result<Vec<Vec<MatchmakingEntry>>, Error>
+where
+    F: FnMut(&Uuid, &Uuid) -> Result<bool, Error>,
+{
+    let mut remaining: Vec<&MatchmakingEntry> = entries.iter().collect();
+    let mut matches: Vec<Vec<MatchmakingEntry>> = vec![];
+    let mut i = 0;
+    while i < remaining.len() {
+        let anchor = remaining[i];
+        let mut candidates: Vec<usize> = (0..remaining.len())
+            .filter(|&j| j != i && compatible(anchor, remaining[j], now))
+            .collect();
+        candidates.sort_by_key(|&j| (remaining[j].rating - anchor.rating).abs());
+        let mut group = vec![i];
+        for j in candidates {
+            if group.len() == anchor.player_count as usize {
+                break;
+            }
+            let mut fits = true;
+            for &g in &group {
+                if !compatible(remaining[g], remaining[j], now)
+                    || is_blocked(&remaining[g].user_id, &remaining[j].user_id)?
+                {
+                    fits = false;
+                    break;
+                }
+            }
+            if fits {
+                group.push(j);
+            }
+        }
+        if group.len() < anchor.player_count as usize {
+            i += 1;
+            continue;
+        }
+        matches.push(group.iter().map(|&j| remaining[j].to_owned()).collect());
+        group.sort();
+        for &j in group.iter().rev() {
+            remaining.remove(j);
+        }
+    }
+    Ok(matches)
+}
+
+fn match_queued(pub_queue_tx: &Sender<websocket::Message>) -> Result<(), Error> {
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+    let now = Utc::now().naive_utc();
+    // Queued entries stay locked until the transaction ends, so other matchers skip them.
+    let started = conn.transaction::<_, Error, _>(|| {
+        let queued = query::matchmaking::find_queued(now, conn)?;
+        let matches = find_matches(&queued, now, |a, b| {
+            query::block::is_blocked_either_way(a, b, conn)
+        })?;
+        let mut started = vec![];
+        for entries in matches {
+            match conn.transaction::<_, Error, _>(|| start_matched_game(&entries, conn)) {
+                Ok(s) => started.push((entries, s)),
+                Err(e) => warn!("error creating matched game: {}", e),
+            }
+        }
+        Ok(started)
+    })?;
+    for (entries, started) in started {
+        if let Err(e) = publish_match(&entries, &started, pub_queue_tx, conn) {
+            warn!("error publishing matched game: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Starts a game for matched players, who have all implicitly accepted by
+/// queueing. Should be run inside a transaction.
+fn start_matched_game(
+    entries: &[MatchmakingEntry],
+    conn: &PgConnection,
+) -> Result<StartedGame, Error> {
+    let game_type_id = entries[0].game_type_id;
+    let game_version = query::find_latest_public_game_version(&game_type_id, conn)?
+        .ok_or_else::<Error, _>(|| {
+            format_err!("no public game version for game type {}", game_type_id)
+        })?;
+    let opponent_ids: Vec<Uuid> = entries[1..].iter().map(|e| e.user_id).collect();
+    let started = start_game(
+        &entries[0].user_id,
+        &game_version.id,
+        &opponent_ids,
+        &[],
+        None,
+        conn,
+    )?;
+    let entry_ids: Vec<Uuid> = entries.iter().map(|e| e.id).collect();
+    if query::matchmaking::mark_matched(&entry_ids, &started.created_game.game.id, conn)?
+        != entries.len()
+    {
+        bail!("matched players are no longer queued");
+    }
+    for player in &started.created_game.players {
+        query::game::update_has_accepted(&player.id, true, conn)?;
+    }
+    Ok(started)
+}
+
+fn publish_match(
+    entries: &[MatchmakingEntry],
+    started: &StartedGame,
+    pub_queue_tx: &Sender<websocket::Message>,
+    conn: &PgConnection,
+) -> Result<(), Error> {
+    let game_extended = publish_started_game(started, pub_queue_tx, conn)?;
+    let user_ids: Vec<Uuid> = entries.iter().map(|e| e.user_id).collect();
+    websocket::enqueue_match_found(
+        &game_extended.game.id,
+        &game_extended.game_type,
+        &game_extended
+            .game_players
+            .iter()
+            .map(|gptu| gptu.user.to_owned().into_public())
+            .collect::<Vec<PublicUser>>(),
+        &query::find_valid_user_auth_tokens_for_users(&user_ids, conn)?,
+        pub_queue_tx,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use db::query::test_now;
+
+    fn entry(game_type_id: &Uuid, rating: i32, waited_mins: i64) -> MatchmakingEntry {
+        let queued_at = test_now() - Duration::minutes(waited_mins);
+        MatchmakingEntry {
+            id: Uuid::new_v4(),
+            created_at: queued_at,
+            updated_at: queued_at,
+            user_id: Uuid::new_v4(),
+            game_type_id: *game_type_id,
+            player_count: 2,
+            rating,
+            game_id: None,
+        }
+    }
+
+    #[test]
+    fn rating_range_works() {
+        assert_eq!(BASE_RATING_RANGE, rating_range(test_now(), test_now()));
+        assert_eq!(200, rating_range(test_now() - Duration::minutes(2), test_now()));
+        assert_eq!(MAX_RATING_RANGE, rating_range(test_now() - Duration::days(1), test_now()));
+    }
+
+    #[test]
+    fn find_matches_pairs_closest_ratings() {
+        let game_type_id = Uuid::new_v4();
+        let entries = vec![
+            entry(&game_type_id, 1200, 0),
+            entry(&game_type_id, 1400, 0),
+            entry(&game_type_id, 1250, 0),
+            entry(&game_type_id, 1210, 0),
+        ];
+        let matches = find_matches(&entries, test_now(), |_, _| Ok(false)).unwrap();
+        assert_eq!(1, matches.len());
+        assert_eq!(entries[0].id, matches[0][0].id);
+        assert_eq!(entries[3].id, matches[0][1].id);
+    }
+
+    #[test]
+    fn find_matches_widens_over_time() {
+        let game_type_id = Uuid::new_v4();
+        let fresh = vec![entry(&game_type_id, 1200, 0), entry(&game_type_id, 1500, 0)];
+        assert!(find_matches(&fresh, test_now(), |_, _| Ok(false)).unwrap().is_empty());
+        let waited = vec![entry(&game_type_id, 1200, 10), entry(&game_type_id, 1500, 10)];
+        assert_eq!(1, find_matches(&waited, test_now(), |_, _| Ok(false)).unwrap().len());
+    }
+
+    #[test]
+    fn find_matches_respects_game_type_and_blocks() {
+        let game_type_id = Uuid::new_v4();
+        let other_game_type_id = Uuid::new_v4();
+        let entries = vec![
+            entry(&game_type_id, 1200, 0),
+            entry(&other_game_type_id, 1200, 0),
+        ];
+        assert!(find_matches(&entries, test_now(), |_, _| Ok(false)).unwrap().is_empty());
+
+        let entries = vec![entry(&game_type_id, 1200, 0), entry(&game_type_id, 1200, 0)];
+        assert!(find_matches(&entries, test_now(), |_, _| Ok(true)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn find_matches_fills_larger_games() {
+        let game_type_id = Uuid::new_v4();
+        let mut entries = vec![
+            entry(&game_type_id, 1200, 0),
+            entry(&game_type_id, 1220, 0),
+            entry(&game_type_id, 1180, 0),
+        ];
+        for e in &mut entries {
+            e.player_count = 3;
+        }
+        let matches = find_matches(&entries, test_now(), |_, _| Ok(false)).unwrap();
+        assert_eq!(1, matches.len());
+        assert_eq!(3, matches[0].len());
+        assert!(find_matches(&entries[..2], test_now(), |_, _| Ok(false)).unwrap().is_empty());
+    }
+}
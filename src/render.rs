@@ -31,10 +31,96 @@ pub fn game_players_to_markup_players(
 }
 
 pub fn markup_html(template: &str, players: &[markup::Player]) -> Result<String, Error> {
-    Ok(markup::html(&markup::transform(
+    markup_render(template, players, Format::Html)
+}
+
+pub fn markup_render(
+    template: &str,
+    players: &[markup::Player],
+    format: Format,
+) -> Result<String, Error> {
+    let nodes = markup::transform(
         &markup::from_string(template)
             .context("failed to parse template")?
             .0,
         players,
-    )))
+    );
+    Ok(match format {
+        Format::Html => markup::html(&nodes),
+        Format::Text => markup::plain(&nodes),
+        // Player colours are already applied by the transform, so they carry through to ANSI.
+        Format::Ansi => markup::ansi(&nodes),
+    })
+}
+
+/// Output formats for rendered markup.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Format {
+    Html,
+    Text,
+    Ansi,
+}
+
+pub static FORMATS: &'static [Format] = &[Format::Html, Format::Text, Format::Ansi];
+
+impl Format {
+    /// Parses a comma separated list of extra formats, such as `text,ansi`. HTML is always
+    /// rendered, so it can't be requested.
+    pub fn from_list(from: &str) -> Result<Vec<Format>, Error> {
+        let mut formats = vec![];
+        for s in from.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            match Format::from_str(s)? {
+                Format::Html => bail!("HTML is always rendered"),
+                format => formats.push(format),
+            }
+        }
+        Ok(formats)
+    }
+}
+
+impl ToString for Format {
+    fn to_string(&self) -> String {
+        match *self {
+            Format::Html => "html",
+            Format::Text => "text",
+            Format::Ansi => "ansi",
+        }.to_string()
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Ok(match s {
+            "html" => Format::Html,
+            "text" => Format::Text,
+            "ansi" => Format::Ansi,
+            _ => bail!("Invalid render format"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_strings_round_trip() {
+        for format in FORMATS {
+            assert_eq!(*format, Format::from_str(&format.to_string()).unwrap());
+        }
+        assert!(Format::from_str("pdf").is_err());
+    }
+
+    #[test]
+    fn format_from_list_works() {
+        assert_eq!(
+            vec![Format::Text, Format::Ansi],
+            Format::from_list("text, ansi").unwrap()
+        );
+        assert!(Format::from_list("").unwrap().is_empty());
+        assert!(Format::from_list("text,pdf").is_err());
+        assert!(Format::from_list("html").is_err());
+    }
 }
@@ -0,0 +1,187 @@
+use diesel::pg::PgConnection;
+use lettre::email::EmailBuilder;
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use failure::{Error, ResultExt};
+
+use std::thread;
+use std::time::Duration as StdDuration;
+
+use config::CONFIG;
+use db::{query, CONN};
+use db::models::*;
+use game_client;
+use mail;
+use render;
+use websocket;
+
+lazy_static! {
+    /// Players are given a little time before being emailed in case they come straight back.
+    static ref NOTIFY_DELAY: Duration = Duration::minutes(1);
+}
+
+const POLL_INTERVAL_SECS: u64 = 30;
+const RECENT_LOG_LIMIT: usize = 10;
+
+pub fn run() {
+    loop {
+        if let Err(e) = notify_turns() {
+            warn!("error sending turn notifications: {}", e);
+        }
+        if let Err(e) = send_digests() {
+            warn!("error sending turn digests: {}", e);
+        }
+        thread::sleep(StdDuration::from_secs(POLL_INTERVAL_SECS));
+    }
+}
+
+fn notify_turns() -> Result<(), Error> {
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+    for (game_player, user) in query::notification::find_pending_turn_notifications(
+        Utc::now().naive_utc() - *NOTIFY_DELAY,
+        conn,
+    )? {
+        let tokens = query::find_valid_user_auth_tokens_for_users(&[user.id], conn)?;
+        // Users who are online will see it's their turn without an email.
+        if !websocket::has_live_session(&tokens)? {
+            if let Err(e) = send_turn_email(&game_player, &user, conn) {
+                warn!("error sending turn email to {}: {}", user.id, e);
+            }
+        }
+        query::notification::mark_turn_notified(&game_player.id, conn)?;
+    }
+    Ok(())
+}
+
+pub fn opponent_names(user_id: &Uuid, game_extended: &query::GameExtended) -> String {
+    game_extended
+        .game_players
+        .iter()
+        .filter(|gptu| gptu.user.id != *user_id)
+        .map(|gptu| gptu.user.name.to_owned())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+pub fn game_link(game_id: &Uuid) -> String {
+    format!("{}/game/{}", CONFIG.web_url, game_id)
+}
+
+fn send_turn_email(
+    game_player: &GamePlayer,
+    user: &User,
+    conn: &PgConnection,
+) -> Result<(), Error> {
+    let user_email = match query::find_user_with_primary_email(&user.id, conn)? {
+        Some((ue, _)) => ue,
+        None => return Ok(()),
+    };
+    let game_extended = query::find_game_extended(&game_player.game_id, conn)?;
+    let markup_players = render::game_players_to_markup_players(&game_extended.game_players)?;
+    let player_render = game_client::player_render(
+        &game_extended.game_version.uri,
+        game_extended.game.game_state.to_owned(),
+        game_player.position as usize,
+    )?;
+
+    // Show what has happened since the player last had a turn.
+    let logs = query::find_game_logs_for_player(&game_player.id, conn)?;
+    let recent_logs = logs.iter()
+        .filter(|gl| gl.logged_at > game_player.last_turn_at)
+        .collect::<Vec<&GameLog>>();
+    let log_html = recent_logs
+        .iter()
+        .skip(recent_logs.len().saturating_sub(RECENT_LOG_LIMIT))
+        .map(|gl| gl.render(&markup_players))
+        .collect::<Result<Vec<String>, Error>>()?
+        .join("\n");
+
+    let message = EmailBuilder::new()
+        .to(user_email.email.as_ref())
+        .from(CONFIG.mail_from.as_ref())
+        .reply_to(mail::reply_address(&game_player.email_token).as_ref())
+        .subject(&format!("Your turn in {}", game_extended.game_type.name))
+        .html(&mail::html_layout(&format!(
+            "It's your turn in <b>{}</b> against {}.
+
+{}
+
+{}
+
+Reply to this email with your command, or <a href=\"{}\">play on brdg.me</a>.",
+            game_extended.game_type.name,
+            opponent_names(&user.id, &game_extended),
+            log_html,
+            render::markup_html(&player_render.render, &markup_players)?,
+            game_link(&game_extended.game.id)
+        )))
+        .build()
+        .context("unable to create turn email")?;
+    mail::send(message, conn).context("unable to send turn email")?;
+    Ok(())
+}
+
+fn send_digests() -> Result<(), Error> {
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+    for user in query::notification::find_users_due_turn_digest(conn)? {
+        let tokens = query::find_valid_user_auth_tokens_for_users(&[user.id], conn)?;
+        if websocket::has_live_session(&tokens)? {
+            // Try again once they've gone offline.
+            continue;
+        }
+        let games = query::find_active_games_for_user(&user.id, conn)?
+            .into_iter()
+            .filter(|ge| {
+                !ge.game.is_finished
+                    && ge.game_players
+                        .iter()
+                        .any(|gptu| gptu.user.id == user.id && gptu.game_player.is_turn)
+            })
+            .collect::<Vec<query::GameExtended>>();
+        if !games.is_empty() {
+            if let Err(e) = send_digest_email(&user, &games, conn) {
+                warn!("error sending turn digest to {}: {}", user.id, e);
+            }
+        }
+        query::notification::mark_turn_digest_sent(&user.id, conn)?;
+    }
+    Ok(())
+}
+
+fn send_digest_email(
+    user: &User,
+    games: &[query::GameExtended],
+    conn: &PgConnection,
+) -> Result<(), Error> {
+    let user_email = match query::find_user_with_primary_email(&user.id, conn)? {
+        Some((ue, _)) => ue,
+        None => return Ok(()),
+    };
+    let game_lines = games
+        .iter()
+        .map(|ge| {
+            format!(
+                "<a href=\"{}\">{}</a> against {}",
+                game_link(&ge.game.id),
+                ge.game_type.name,
+                opponent_names(&user.id, ge)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let message = EmailBuilder::new()
+        .to(user_email.email.as_ref())
+        .from(CONFIG.mail_from.as_ref())
+        .subject("Your daily brdg.me turn digest")
+        .html(&mail::html_layout(&format!(
+            "It's your turn in the following games:
+
+{}",
+            game_lines
+        )))
+        .build()
+        .context("unable to create turn digest email")?;
+    mail::send(message, conn).context("unable to send turn digest email")?;
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use diesel::Connection;
+use diesel::pg::PgConnection;
+use lettre::email::EmailBuilder;
+use chrono::{NaiveDateTime, Utc};
+use failure::{Error, ResultExt};
+
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+use std::sync::mpsc::Sender;
+
+use config::CONFIG;
+use controller::game::concede_or_resign;
+use controller::proposal;
+use db::{query, CONN};
+use db::models::*;
+use db::time_control::{self, TimeoutPenalty};
+use mail;
+use turn_notifier::{game_link, opponent_names};
+use websocket;
+
+const POLL_INTERVAL_SECS: u64 = 60;
+
+/// Enforces turn time limits, and lapses proposals to end games early which weren't voted on
+/// in time.
+pub fn run(pub_queue_tx: Sender<websocket::Message>) {
+    loop {
+        if let Err(e) = check_turns(&pub_queue_tx) {
+            warn!("error checking turn time limits: {}", e);
+        }
+        if let Err(e) = expire_proposals(&pub_queue_tx) {
+            warn!("error expiring game proposals: {}", e);
+        }
+        thread::sleep(Duration::from_secs(POLL_INTERVAL_SECS));
+    }
+}
+
+fn expire_proposals(pub_queue_tx: &Sender<websocket::Message>) -> Result<(), Error> {
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+    for proposal in query::proposal::find_expired(Utc::now().naive_utc(), conn)? {
+        if let Err(e) = conn.transaction(|| proposal::expire(&proposal, pub_queue_tx, conn)) {
+            warn!("error expiring game proposal {}: {}", proposal.id, e);
+        }
+    }
+    Ok(())
+}
+
+fn check_turns(pub_queue_tx: &Sender<websocket::Message>) -> Result<(), Error> {
+    let conn = &*CONN.w.get().context("unable to get connection")?;
+    let now = Utc::now().naive_utc();
+    for (game_player, game) in query::game::find_timed_turns(CONFIG.require_accept, conn)? {
+        let vacations = query::vacation::find_by_user_since(
+            &game_player.user_id,
+            game_player.is_turn_at,
+            conn,
+        )?;
+        // Turn clocks are frozen while the player is on vacation.
+        if vacations.iter().any(|v| v.starts_at <= now && v.ends_at > now) {
+            continue;
+        }
+        let paused = time_control::paused_during(&vacations, game_player.is_turn_at, now);
+        let (deadline, warning_at, timeout_at) = match (
+            time_control::turn_deadline(&game, &game_player, paused),
+            time_control::warning_at(&game, &game_player, paused),
+            time_control::timeout_at(&game, &game_player, paused),
+        ) {
+            (Some(d), Some(w), Some(t)) => (d, w, t),
+            _ => continue,
+        };
+        if now >= timeout_at {
+            if let Err(e) = time_out(&game_player, pub_queue_tx, conn) {
+                warn!("error timing out player {}: {}", game_player.id, e);
+            }
+        } else if now >= warning_at && game_player.turn_warned_at.is_none() {
+            if let Err(e) = send_warning_email(&game, &game_player, deadline, now, conn) {
+                warn!("error sending turn warning to {}: {}", game_player.user_id, e);
+            }
+            query::game::mark_turn_warned(&game_player.id, conn)?;
+        }
+    }
+    Ok(())
+}
+
+/// Applies the game's timeout penalty. Eliminating a player only makes sense while at least
+/// two others are still playing, otherwise the player concedes.
+fn time_out(
+    game_player: &GamePlayer,
+    pub_queue_tx: &Sender<websocket::Message>,
+    conn: &PgConnection,
+) -> Result<(), Error> {
+    conn.transaction(|| {
+        // An earlier timeout in the same pass may have already finished the game.
+        query::lock_game(&game_player.game_id, conn)?;
+        let (game, game_version) = query::find_game_with_version(&game_player.game_id, conn)?
+            .ok_or_else::<Error, _>(|| format_err!("could not find game"))?;
+        if game.is_finished {
+            return Ok(());
+        }
+        let playing = query::find_game_players_by_game(&game.id, conn)?
+            .iter()
+            .filter(|gp| gp.resigned_at.is_none())
+            .count();
+        let resign = TimeoutPenalty::from_str(&game.timeout_penalty)? == TimeoutPenalty::Eliminate
+            && playing > 2;
+        concede_or_resign(
+            &game,
+            &game_version,
+            game_player,
+            resign,
+            " ran out of time",
+            pub_queue_tx,
+            conn,
+        )?;
+        Ok(())
+    })
+}
+
+fn send_warning_email(
+    game: &Game,
+    game_player: &GamePlayer,
+    deadline: NaiveDateTime,
+    now: NaiveDateTime,
+    conn: &PgConnection,
+) -> Result<(), Error> {
+    let (user_email, user) = match query::find_user_with_primary_email(&game_player.user_id, conn)?
+    {
+        Some(ue) => ue,
+        None => return Ok(()),
+    };
+    let game_extended = query::find_game_extended(&game.id, conn)?;
+
+    let message = EmailBuilder::new()
+        .to(user_email.email.as_ref())
+        .from(CONFIG.mail_from.as_ref())
+        .reply_to(mail::reply_address(&game_player.email_token).as_ref())
+        .subject(&format!(
+            "Your turn in {} is running out of time",
+            game_extended.game_type.name
+        ))
+        .html(&mail::html_layout(&format!(
+            "You have {} left to take your turn in <b>{}</b> against {}.
+
+Reply to this email with your command, or <a href=\"{}\">play on brdg.me</a>.",
+            time_control::describe_remaining(deadline.signed_duration_since(now)),
+            game_extended.game_type.name,
+            opponent_names(&user.id, &game_extended),
+            game_link(&game.id)
+        )))
+        .build()
+        .context("unable to create turn warning email")?;
+    mail::send(message, conn).context("unable to send turn warning email")?;
+    Ok(())
+}
@@ -6,6 +6,7 @@ use failure::{Error, ResultExt};
 use brdgme_cmd::cli;
 use brdgme_markup as markup;
 
+use std::collections::{HashMap, HashSet};
 use std::sync::mpsc::{channel, Receiver, Sender};
 
 use config::CONFIG;
@@ -34,6 +35,32 @@ pub enum MessageKind {
         restarted_game_id: Uuid,
     },
     GameUpdate(ShowResponse),
+    GameInvitation {
+        game_id: Uuid,
+        game_type: PublicGameType,
+        inviter: PublicUser,
+    },
+    GameInvitationResponse {
+        game_id: Uuid,
+        user: PublicUser,
+        has_accepted: bool,
+    },
+    ChatMessage {
+        chat_id: Uuid,
+        game_id: Option<Uuid>,
+        chat_message: PublicChatMessage,
+        user: PublicUser,
+    },
+    MatchFound {
+        game_id: Uuid,
+        game_type: PublicGameType,
+        players: Vec<PublicUser>,
+    },
+    GameProposal {
+        game_id: Uuid,
+        proposal: PublicGameProposal,
+        votes: Vec<PublicGameProposalVote>,
+    },
 }
 
 pub struct PubQueue {
@@ -97,6 +124,57 @@ fn user_channel(user_auth_token_id: &Uuid) -> String {
     format!("user.{}", user_auth_token_id)
 }
 
+/// The extra renders clients can opt into for game updates, in their canonical order.
+static RENDER_OPTIONS: &'static [&'static [render::Format]] = &[
+    &[render::Format::Text],
+    &[render::Format::Ansi],
+    &[render::Format::Text, render::Format::Ansi],
+];
+
+/// The channel game updates with extra renders are sent to, named after the `render` query
+/// parameter used by the HTTP API, such as `game.<id>?render=text,ansi`.
+fn render_channel(channel: &str, formats: &[render::Format]) -> String {
+    format!(
+        "{}?render={}",
+        channel,
+        formats
+            .iter()
+            .map(|f| f.to_string())
+            .collect::<Vec<String>>()
+            .join(",")
+    )
+}
+
+/// The channels which currently have something subscribed to them.
+fn subscribed_channels(channels: Vec<String>) -> Result<HashSet<String>, Error> {
+    if channels.is_empty() {
+        return Ok(HashSet::new());
+    }
+    let conn = CLIENT
+        .get_connection()
+        .context("unable to get Redis connection from client")?;
+    let subscribers: HashMap<String, i64> = redis::cmd("PUBSUB")
+        .arg("NUMSUB")
+        .arg(channels)
+        .query(&conn)
+        .context("unable to get subscriber counts for channels")?;
+    Ok(subscribers
+        .into_iter()
+        .filter(|&(_, count)| count > 0)
+        .map(|(channel, _)| channel)
+        .collect())
+}
+
+/// Whether any of the tokens currently has a websocket connection, which we detect by checking
+/// whether anything is subscribed to their user channels.
+pub fn has_live_session(user_auth_tokens: &[UserAuthToken]) -> Result<bool, Error> {
+    Ok(!subscribed_channels(user_auth_tokens
+        .iter()
+        .map(|uat| user_channel(&uat.id))
+        .collect())?
+        .is_empty())
+}
+
 pub fn enqueue_game_restarted(
     game_id: &Uuid,
     restarted_game_id: &Uuid,
@@ -124,6 +202,178 @@ pub fn enqueue_game_restarted(
     Ok(())
 }
 
+pub fn enqueue_game_invitation(
+    game_id: &Uuid,
+    game_type: &PublicGameType,
+    inviter: &PublicUser,
+    user_auth_tokens: &[UserAuthToken],
+    pub_queue_tx: &Sender<Message>,
+) -> Result<(), Error> {
+    let message = MessageKind::GameInvitation {
+        game_id: game_id.to_owned(),
+        game_type: game_type.to_owned(),
+        inviter: inviter.to_owned(),
+    };
+    for uat in user_auth_tokens {
+        pub_queue_tx
+            .send(Message {
+                channel: user_channel(&uat.id),
+                payload: message.clone(),
+            })
+            .context("error enqueuing game invitation message")?;
+    }
+    Ok(())
+}
+
+pub fn enqueue_game_invitation_response(
+    game_id: &Uuid,
+    user: &PublicUser,
+    has_accepted: bool,
+    user_auth_tokens: &[UserAuthToken],
+    pub_queue_tx: &Sender<Message>,
+) -> Result<(), Error> {
+    let message = MessageKind::GameInvitationResponse {
+        game_id: game_id.to_owned(),
+        user: user.to_owned(),
+        has_accepted,
+    };
+    for uat in user_auth_tokens {
+        pub_queue_tx
+            .send(Message {
+                channel: user_channel(&uat.id),
+                payload: message.clone(),
+            })
+            .context("error enqueuing game invitation response message")?;
+    }
+    Ok(())
+}
+
+pub fn enqueue_chat_message(
+    chat_id: &Uuid,
+    game_id: Option<&Uuid>,
+    chat_message: &PublicChatMessage,
+    user: &PublicUser,
+    user_auth_tokens: &[UserAuthToken],
+    pub_queue_tx: &Sender<Message>,
+) -> Result<(), Error> {
+    let message = MessageKind::ChatMessage {
+        chat_id: chat_id.to_owned(),
+        game_id: game_id.cloned(),
+        chat_message: chat_message.to_owned(),
+        user: user.to_owned(),
+    };
+    if let Some(game_id) = game_id {
+        pub_queue_tx
+            .send(Message {
+                channel: game_channel(game_id),
+                payload: message.clone(),
+            })
+            .context("error enqueuing public chat message")?;
+    }
+    for uat in user_auth_tokens {
+        pub_queue_tx
+            .send(Message {
+                channel: user_channel(&uat.id),
+                payload: message.clone(),
+            })
+            .context("error enqueuing user chat message")?;
+    }
+    Ok(())
+}
+
+pub fn enqueue_match_found(
+    game_id: &Uuid,
+    game_type: &PublicGameType,
+    players: &[PublicUser],
+    user_auth_tokens: &[UserAuthToken],
+    pub_queue_tx: &Sender<Message>,
+) -> Result<(), Error> {
+    let message = MessageKind::MatchFound {
+        game_id: game_id.to_owned(),
+        game_type: game_type.to_owned(),
+        players: players.to_owned(),
+    };
+    for uat in user_auth_tokens {
+        pub_queue_tx
+            .send(Message {
+                channel: user_channel(&uat.id),
+                payload: message.clone(),
+            })
+            .context("error enqueuing match found message")?;
+    }
+    Ok(())
+}
+
+/// Publishes a proposal to end a game early whenever it's made, voted on or settled.
+pub fn enqueue_game_proposal(
+    proposal: &PublicGameProposal,
+    votes: &[PublicGameProposalVote],
+    user_auth_tokens: &[UserAuthToken],
+    pub_queue_tx: &Sender<Message>,
+) -> Result<(), Error> {
+    let message = MessageKind::GameProposal {
+        game_id: proposal.game_id,
+        proposal: proposal.to_owned(),
+        votes: votes.to_owned(),
+    };
+    pub_queue_tx
+        .send(Message {
+            channel: game_channel(&proposal.game_id),
+            payload: message.clone(),
+        })
+        .context("error enqueuing public game proposal message")?;
+    for uat in user_auth_tokens {
+        pub_queue_tx
+            .send(Message {
+                channel: user_channel(&uat.id),
+                payload: message.clone(),
+            })
+            .context("error enqueuing user game proposal message")?;
+    }
+    Ok(())
+}
+
+/// Sends a game update to the channel, and to any of its render channels with subscribers.
+fn send_game_update(
+    channel: String,
+    update: ShowResponse,
+    template: &str,
+    players: &[markup::Player],
+    render_subscribed: &HashSet<String>,
+    pub_queue_tx: &Sender<Message>,
+) -> Result<(), Error> {
+    for formats in RENDER_OPTIONS {
+        let render_channel = render_channel(&channel, formats);
+        if !render_subscribed.contains(&render_channel) {
+            continue;
+        }
+        let mut rendered = update.clone();
+        for format in *formats {
+            let output = Some(render::markup_render(template, players, *format)?);
+            match *format {
+                render::Format::Text => rendered.text = output,
+                render::Format::Ansi => rendered.ansi = output,
+                render::Format::Html => {}
+            }
+        }
+        pub_queue_tx
+            .send(Message {
+                channel: render_channel,
+                payload: MessageKind::GameUpdate(rendered),
+            })
+            .context("error enqueuing rendered game update")?;
+    }
+    pub_queue_tx
+        .send(Message {
+            channel,
+            payload: MessageKind::GameUpdate(update),
+        })
+        .context("error enqueuing game update")?;
+    Ok(())
+}
+
+/// Publishes a game update to the game channel and each player's user channels. Plain text and
+/// ANSI renders are only included for clients which subscribed to a render channel.
 pub fn enqueue_game_update<'a>(
     game: &'a PublicGameExtended,
     game_logs: &[CreatedGameLog],
@@ -133,23 +383,39 @@ pub fn enqueue_game_update<'a>(
     pub_queue_tx: &Sender<Message>,
 ) -> Result<(), Error> {
     let markup_players = render::public_game_players_to_markup_players(&game.game_players)?;
-    pub_queue_tx
-        .send(Message {
-            channel: game_channel(&game.game.id),
-            payload: MessageKind::GameUpdate(ShowResponse {
-                game_player: None,
-                game: game.game.to_owned(),
-                game_type: game.game_type.to_owned(),
-                game_version: game.game_version.to_owned(),
-                game_players: game.game_players.to_owned(),
-                game_logs: created_logs_for_player(None, game_logs, &markup_players)?,
-                state: public_render.pub_state.to_owned(),
-                html: render::markup_html(&public_render.render, &markup_players)?,
-                command_spec: None,
-                chat: game.chat.to_owned(),
-            }),
-        })
-        .context("error enqueuing public game update")?;
+    let mut channels = vec![game_channel(&game.game.id)];
+    channels.extend(user_auth_tokens.iter().map(|uat| user_channel(&uat.id)));
+    let render_subscribed = subscribed_channels(channels
+        .iter()
+        .flat_map(|c| RENDER_OPTIONS.iter().map(move |f| render_channel(c, f)))
+        .collect())
+        .unwrap_or_else(|e| {
+            // Clients still get the HTML render, so this shouldn't fail the update.
+            warn!("error finding render channel subscribers: {}", e);
+            HashSet::new()
+        });
+
+    send_game_update(
+        game_channel(&game.game.id),
+        ShowResponse {
+            game_player: None,
+            game: game.game.to_owned(),
+            game_type: game.game_type.to_owned(),
+            game_version: game.game_version.to_owned(),
+            game_players: game.game_players.to_owned(),
+            game_logs: created_logs_for_player(None, game_logs, &markup_players)?,
+            state: public_render.pub_state.to_owned(),
+            html: render::markup_html(&public_render.render, &markup_players)?,
+            text: None,
+            ansi: None,
+            command_spec: None,
+            chat: game.chat.to_owned(),
+        },
+        &public_render.render,
+        &markup_players,
+        &render_subscribed,
+        pub_queue_tx,
+    ).context("error enqueuing public game update")?;
     for gp in &game.game_players {
         let player_render = match player_renders.get(gp.game_player.position as usize) {
             Some(pr) => pr,
@@ -168,17 +434,21 @@ pub fn enqueue_game_update<'a>(
             )?,
             state: player_render.player_state.to_owned(),
             html: render::markup_html(&player_render.render, &markup_players)?,
+            text: None,
+            ansi: None,
             command_spec: player_render.command_spec.to_owned(),
             chat: game.chat.to_owned(),
         };
         for uat in user_auth_tokens {
             if uat.user_id == gp.user.id {
-                pub_queue_tx
-                    .send(Message {
-                        channel: user_channel(&uat.id),
-                        payload: MessageKind::GameUpdate(player_message.clone()),
-                    })
-                    .context("error enqueuing player game update")?;
+                send_game_update(
+                    user_channel(&uat.id),
+                    player_message.clone(),
+                    &player_render.render,
+                    &markup_players,
+                    &render_subscribed,
+                    pub_queue_tx,
+                ).context("error enqueuing player game update")?;
             }
         }
     }